@@ -0,0 +1,56 @@
+//! Scaling of the Rust stats path.
+//!
+//! `cargo bench --bench stats` runs 10k..1M transfers; set `STATS_BENCH_MAX=50000000` to extend the
+//! series to tens of millions (needs a few GB of RAM for the input slice).
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rust_challenge::model::{Decimal, Transfer, DEFAULT_TOKEN};
+use rust_challenge::stats::calculate_user_stats_rust;
+use std::hint::black_box;
+
+const ADDRESSES: usize = 100_000;
+
+fn transfers(count: usize) -> Vec<Transfer> {
+    let mut rng = StdRng::seed_from_u64(42);
+    let addresses: Vec<String> = (0..ADDRESSES).map(|i| format!("0x{i:010}")).collect();
+    (0..count)
+        .map(|i| Transfer {
+            ts: i as u64,
+            address_from: addresses[rng.gen_range(0..ADDRESSES)].clone(),
+            address_to: addresses[rng.gen_range(0..ADDRESSES)].clone(),
+            token: DEFAULT_TOKEN.to_string(),
+            amount: Decimal::new(rng.gen_range(1_000..1_000_000), 3),
+            usd_price: Decimal::new(rng.gen_range(100..2_000), 3),
+            block_number: i as u64,
+            tx_hash: format!("0x{i:064x}"),
+            log_index: 0,
+        })
+        .collect()
+}
+
+fn sizes() -> Vec<usize> {
+    let max = std::env::var("STATS_BENCH_MAX")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(1_000_000);
+    std::iter::successors(Some(10_000), |n| Some(n * 10))
+        .take_while(|n| *n <= max)
+        .collect()
+}
+
+fn bench_user_stats(c: &mut Criterion) {
+    let mut group = c.benchmark_group("calculate_user_stats_rust");
+    group.sample_size(10);
+    for size in sizes() {
+        let input = transfers(size);
+        group.throughput(Throughput::Elements(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &input, |b, input| {
+            b.iter(|| calculate_user_stats_rust(black_box(input)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_user_stats);
+criterion_main!(benches);
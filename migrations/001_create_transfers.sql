@@ -1,4 +1,4 @@
-CREATE TABLE IF NOT EXISTS transfers (
+CREATE TABLE IF NOT EXISTS {table} (
     ts UInt64,
     address_from String,
     address_to String,
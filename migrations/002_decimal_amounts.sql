@@ -0,0 +1,3 @@
+ALTER TABLE {table}
+    MODIFY COLUMN amount Decimal(38, 18),
+    MODIFY COLUMN usd_price Decimal(38, 18);
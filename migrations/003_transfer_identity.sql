@@ -0,0 +1,29 @@
+-- Transfers get an id (tx_hash, log_index) and duplicates of an id are merged away.
+-- ClickHouse can't change a table engine in place, so the table is rebuilt and swapped in.
+-- Rows from before this migration get a content hash as their id, so exact copies merge.
+-- Copying again after the swap would be wrong, so the applied check in src/migrate skips it.
+DROP TABLE IF EXISTS {table}_dedup;
+CREATE TABLE {table}_dedup (
+    ts UInt64,
+    address_from String,
+    address_to String,
+    amount Decimal(38, 18),
+    usd_price Decimal(38, 18),
+    block_number UInt64,
+    tx_hash String,
+    log_index UInt32
+) ENGINE = ReplacingMergeTree()
+ORDER BY (tx_hash, log_index);
+INSERT INTO {table}_dedup
+SELECT
+    ts,
+    address_from,
+    address_to,
+    amount,
+    usd_price,
+    0 AS block_number,
+    concat('legacy-', lower(hex(cityHash64(ts, address_from, address_to, amount, usd_price)))) AS tx_hash,
+    0 AS log_index
+FROM {table};
+EXCHANGE TABLES {table} AND {table}_dedup;
+DROP TABLE {table}_dedup;
@@ -0,0 +1,3 @@
+-- Rows stored before transfers carried a token get model::DEFAULT_TOKEN.
+ALTER TABLE {table}
+    ADD COLUMN IF NOT EXISTS token String DEFAULT 'TKN' AFTER address_to;
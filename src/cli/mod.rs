@@ -0,0 +1,417 @@
+//! Command-line interface of the binary: generate transfers to a file, load a file into
+//! ClickHouse, compute and compare stats, migrate the schema and serve the HTTP API.
+//!
+//! Transfer files are CSV or NDJSON dumps (see [`dump`]); `-` stands for stdin/stdout. Stats are
+//! written in any [`OutputFormat`]. Results go to stdout and progress to stderr, so commands can
+//! be piped. [`Cli::run`] returns the process exit code, see [`exit`].
+
+use crate::common::{ClickhouseClient, ClickhouseConfig, CONFIG_PATH_ENV};
+use crate::dump::{self, write_transfers, OnInvalidRow, TransferFormat, TransferReader};
+use crate::generator::{DefaultTransferGenerator, TransferGenConfig};
+use crate::migrate;
+use crate::model::{Decimal, UserStats};
+use crate::output::{sort_stats, write_user_stats, OutputFormat, SortKey};
+use crate::server;
+use crate::stats::{
+    calculate_user_stats_clickhouse_matching, calculate_user_stats_clickhouse_with_policy,
+    calculate_user_stats_rust_with_policy, calculate_user_stats_stream_with_policy, compare,
+    NegativeBalancePolicy, Tolerance,
+};
+use crate::storage::{ClickhouseStorage, TransferQuery, TransferStore};
+use anyhow::{Context, Result};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+/// Process exit codes.
+pub mod exit {
+    pub const SUCCESS: u8 = 0;
+    /// Any error: unreadable input, ClickHouse unreachable, invalid config and so on.
+    pub const ERROR: u8 = 1;
+    /// Invalid command-line arguments (clap's own code).
+    pub const USAGE: u8 = 2;
+    /// `compare` found mismatches between the engines.
+    pub const MISMATCH: u8 = 3;
+    /// `migrate --check` found migrations that were not applied.
+    pub const OUTDATED: u8 = 4;
+    /// `load --skip-invalid` left out rows it could not parse.
+    pub const INVALID_ROWS: u8 = 5;
+}
+
+#[derive(Debug, Parser)]
+#[command(version, about = "Token transfer generator and per-address stats")]
+pub struct Cli {
+    /// ClickHouse config file (.toml or .json); `CLICKHOUSE_*` variables override it.
+    #[arg(long, global = true, env = CONFIG_PATH_ENV, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Generate transfers into a CSV or NDJSON file.
+    Generate(GenerateArgs),
+    /// Insert transfers from a CSV or NDJSON file into ClickHouse.
+    Load(LoadArgs),
+    /// Compute per-address stats.
+    Stats(StatsArgs),
+    /// Compute stats with both engines and report where they differ.
+    Compare(CompareArgs),
+    /// Apply pending schema migrations.
+    Migrate(MigrateArgs),
+    /// Serve stats and transfer uploads over HTTP.
+    Serve(ServeArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct GenerateArgs {
+    /// Number of transfers.
+    #[arg(short = 'n', long, default_value_t = 10_000)]
+    pub count: usize,
+    /// Generator config file (.toml or .json), see `generator.example.toml`.
+    #[arg(long, value_name = "PATH")]
+    pub generator_config: Option<PathBuf>,
+    /// Seed, overriding the config; the seed in use is printed either way. It fixes everything
+    /// but the timestamps, which count back from `--now`.
+    #[arg(long)]
+    pub seed: Option<u64>,
+    /// Unix time in seconds that timestamps count back from, overriding the config
+    /// [default: the current time]. With `--seed`, the output is reproducible.
+    #[arg(long, value_name = "UNIX_SECONDS")]
+    pub now: Option<u64>,
+    /// Token of the generated transfers, overriding the config.
+    #[arg(long)]
+    pub token: Option<String>,
+    #[arg(short, long, value_name = "PATH", default_value = "-")]
+    pub output: PathBuf,
+    /// csv or ndjson [default: from the output file extension, else ndjson].
+    #[arg(long)]
+    pub format: Option<TransferFormat>,
+}
+
+#[derive(Debug, Args)]
+pub struct LoadArgs {
+    #[arg(value_name = "PATH", default_value = "-")]
+    pub input: PathBuf,
+    /// csv or ndjson [default: from the file extension, else ndjson].
+    #[arg(long)]
+    pub format: Option<TransferFormat>,
+    /// Report invalid rows and load the rest, exiting with 5, instead of stopping at the first.
+    #[arg(long)]
+    pub skip_invalid: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Engine {
+    Rust,
+    Clickhouse,
+}
+
+/// [`NegativeBalancePolicy`] on the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Policy {
+    #[default]
+    Allow,
+    Clamp,
+    PreFunded,
+}
+
+impl From<Policy> for NegativeBalancePolicy {
+    fn from(policy: Policy) -> Self {
+        match policy {
+            Policy::Allow => NegativeBalancePolicy::Allow,
+            Policy::Clamp => NegativeBalancePolicy::Clamp,
+            Policy::PreFunded => NegativeBalancePolicy::PreFunded,
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct StatsArgs {
+    #[arg(long, value_enum, default_value_t = Engine::Clickhouse)]
+    pub engine: Engine,
+    /// Read transfers from a CSV or NDJSON file instead of ClickHouse (rust engine only).
+    #[arg(long, value_name = "PATH")]
+    pub input: Option<PathBuf>,
+    /// Format of `--input` [default: from the file extension, else ndjson].
+    #[arg(long)]
+    pub input_format: Option<TransferFormat>,
+    #[arg(long, value_enum, default_value_t)]
+    pub policy: Policy,
+    /// Only these addresses; may be repeated.
+    #[arg(long)]
+    pub address: Vec<String>,
+    /// Only this token.
+    #[arg(long)]
+    pub token: Option<String>,
+    /// Ties are broken by address, then token.
+    #[arg(long, value_enum, default_value_t)]
+    pub sort: SortKey,
+    #[arg(long)]
+    pub desc: bool,
+    #[arg(long)]
+    pub limit: Option<usize>,
+    /// json, ndjson, csv or table.
+    #[arg(short, long, default_value_t)]
+    pub format: OutputFormat,
+    /// Round decimals to this many fractional digits; exact by default.
+    #[arg(long)]
+    pub precision: Option<usize>,
+}
+
+#[derive(Debug, Args)]
+pub struct CompareArgs {
+    #[arg(long, value_enum, default_value_t)]
+    pub policy: Policy,
+    /// Largest accepted absolute difference [default: 1e-9].
+    #[arg(long)]
+    pub absolute: Option<Decimal>,
+    /// Largest accepted difference relative to the larger value [default: 1e-9].
+    #[arg(long)]
+    pub relative: Option<f64>,
+}
+
+#[derive(Debug, Args)]
+pub struct MigrateArgs {
+    /// Only report the schema status; exits with 4 if migrations are pending.
+    #[arg(long)]
+    pub check: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ServeArgs {
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    pub listen: SocketAddr,
+    #[arg(long, value_enum, default_value_t)]
+    pub policy: Policy,
+}
+
+impl Cli {
+    /// Runs the command, writing its results to `out`, and returns the exit code.
+    pub async fn run(self, out: &mut impl Write) -> Result<u8> {
+        match &self.command {
+            Command::Generate(args) => generate(args, out),
+            Command::Load(args) => load(&self.client()?, args).await,
+            Command::Stats(args) => stats(&self, args, out).await,
+            Command::Compare(args) => compare_engines(&self.client()?, args, out).await,
+            Command::Migrate(args) => run_migrate(&self.client()?, args, out).await,
+            Command::Serve(args) => serve(&self.client()?, args).await,
+        }
+    }
+
+    fn client(&self) -> Result<ClickhouseClient> {
+        let config = match &self.config {
+            Some(path) => ClickhouseConfig::from_file(path)?,
+            None => ClickhouseConfig::default(),
+        };
+        let config = config
+            .with_env(std::env::vars())
+            .context("Failed to load ClickHouse config")?;
+        ClickhouseClient::from_config(&config)
+    }
+}
+
+fn generate(args: &GenerateArgs, out: &mut impl Write) -> Result<u8> {
+    let mut config = match &args.generator_config {
+        Some(path) => TransferGenConfig::from_file(path)?,
+        None => TransferGenConfig::default(),
+    };
+    if let Some(seed) = args.seed {
+        config.seed = Some(seed);
+    }
+    if let Some(now) = args.now {
+        config.now = Some(now);
+    }
+    if let Some(token) = &args.token {
+        config.token = token.clone();
+    }
+    let transfers = DefaultTransferGenerator { config }.transfers(args.count)?;
+    eprintln!(
+        "Generating {} transfers (seed {}, now {})",
+        args.count,
+        transfers.seed(),
+        transfers.now()
+    );
+
+    let format = transfer_format(args.format, &args.output);
+    if args.output == Path::new("-") {
+        write_transfers(out, format, transfers)?;
+        return Ok(exit::SUCCESS);
+    }
+    let file = File::create(&args.output)
+        .with_context(|| format!("Failed to create {}", args.output.display()))?;
+    write_transfers(BufWriter::new(file), format, transfers)
+        .with_context(|| format!("Failed to write {}", args.output.display()))?;
+    Ok(exit::SUCCESS)
+}
+
+async fn load(client: &ClickhouseClient, args: &LoadArgs) -> Result<u8> {
+    let rows = read_transfers(&args.input, args.format)?;
+    migrate::ensure_up_to_date(client).await?;
+    let on_invalid = if args.skip_invalid {
+        OnInvalidRow::Skip
+    } else {
+        OnInvalidRow::Abort
+    };
+    let report = dump::load(&ClickhouseStorage::new(client.clone()), rows, on_invalid)
+        .await
+        .with_context(|| format!("Failed to load {}", args.input.display()))?;
+    for error in &report.invalid {
+        eprintln!("Skipped {}: {error}", args.input.display());
+    }
+    eprintln!(
+        "Loaded {} transfers into {}, skipped {}",
+        report.inserted,
+        client.table,
+        report.invalid.len()
+    );
+    Ok(if report.invalid.is_empty() {
+        exit::SUCCESS
+    } else {
+        exit::INVALID_ROWS
+    })
+}
+
+async fn stats(cli: &Cli, args: &StatsArgs, out: &mut impl Write) -> Result<u8> {
+    let policy = args.policy.into();
+    // An address' stats only depend on the transfers it takes part in, so only those are read.
+    let mut query = TransferQuery::new().addresses(args.address.iter().cloned());
+    if let Some(token) = &args.token {
+        query = query.token(token);
+    }
+    let mut stats = match (args.engine, &args.input) {
+        (Engine::Rust, Some(path)) => {
+            let transfers = read_transfers(path, args.input_format)?
+                .filter(|t| t.as_ref().map_or(true, |t| query.matches(t)))
+                .collect::<Result<Vec<_>, _>>()
+                .with_context(|| format!("Invalid transfer in {}", path.display()))?;
+            calculate_user_stats_rust_with_policy(&transfers, policy)?
+        }
+        (Engine::Clickhouse, Some(_)) => {
+            anyhow::bail!("--input is only supported by the rust engine")
+        }
+        (Engine::Rust, None) => rust_stats_from_clickhouse(&cli.client()?, policy, &query).await?,
+        (Engine::Clickhouse, None) => {
+            calculate_user_stats_clickhouse_matching(&cli.client()?, policy, &query).await?
+        }
+    };
+
+    // The other side of each transfer gets stats too.
+    stats.retain(|s| args.address.is_empty() || args.address.contains(&s.address));
+    sort_stats(&mut stats, args.sort, args.desc);
+    stats.truncate(args.limit.unwrap_or(usize::MAX));
+
+    write_user_stats(out, &stats, args.format, args.precision)?;
+    Ok(exit::SUCCESS)
+}
+
+async fn compare_engines(
+    client: &ClickhouseClient,
+    args: &CompareArgs,
+    out: &mut impl Write,
+) -> Result<u8> {
+    let policy = args.policy.into();
+    let rust = rust_stats_from_clickhouse(client, policy, &TransferQuery::new()).await?;
+    let clickhouse = calculate_user_stats_clickhouse_with_policy(client, policy)
+        .await
+        .context("Failed to calculate ClickHouse stats")?;
+
+    let default = Tolerance::default();
+    let tolerance = Tolerance {
+        absolute: args.absolute.unwrap_or(default.absolute),
+        relative: args.relative.unwrap_or(default.relative),
+    };
+    let report = compare(&rust, &clickhouse, tolerance);
+    writeln!(
+        out,
+        "{} address/token pairs compared, {} mismatches",
+        report.compared,
+        report.mismatches.len()
+    )?;
+    for mismatch in &report.mismatches {
+        writeln!(out, "{mismatch}")?;
+    }
+    Ok(if report.mismatches.is_empty() {
+        exit::SUCCESS
+    } else {
+        exit::MISMATCH
+    })
+}
+
+async fn run_migrate(
+    client: &ClickhouseClient,
+    args: &MigrateArgs,
+    out: &mut impl Write,
+) -> Result<u8> {
+    if args.check {
+        let status = migrate::status(client).await?;
+        writeln!(out, "{}: {status}", client.table)?;
+        return Ok(if status.is_up_to_date() {
+            exit::SUCCESS
+        } else {
+            exit::OUTDATED
+        });
+    }
+    let applied = migrate::run(client)
+        .await
+        .context("Failed to migrate the schema")?;
+    if applied.is_empty() {
+        writeln!(out, "{} is up to date", client.table)?;
+    } else {
+        writeln!(out, "Applied migrations {applied:?} to {}", client.table)?;
+    }
+    Ok(exit::SUCCESS)
+}
+
+async fn serve(client: &ClickhouseClient, args: &ServeArgs) -> Result<u8> {
+    migrate::ensure_up_to_date(client).await?;
+    let listener = tokio::net::TcpListener::bind(args.listen)
+        .await
+        .with_context(|| format!("Failed to listen on {}", args.listen))?;
+    eprintln!(
+        "Serving {} on http://{}",
+        client.table,
+        listener.local_addr()?
+    );
+    let storage = ClickhouseStorage::new(client.clone());
+    server::serve(listener, storage, args.policy.into()).await?;
+    Ok(exit::SUCCESS)
+}
+
+/// The rust engine over the transfers in ClickHouse matching `query`, streamed in replay order.
+async fn rust_stats_from_clickhouse(
+    client: &ClickhouseClient,
+    policy: NegativeBalancePolicy,
+    query: &TransferQuery,
+) -> Result<Vec<UserStats>> {
+    let storage = ClickhouseStorage::new(client.clone());
+    calculate_user_stats_stream_with_policy(storage.stream_transfers(query), policy)
+        .await
+        .context("Failed to read transfers from storage")
+}
+
+/// `explicit`, else the format named by `path`'s extension, else NDJSON.
+fn transfer_format(explicit: Option<TransferFormat>, path: &Path) -> TransferFormat {
+    explicit
+        .or_else(|| TransferFormat::from_path(path))
+        .unwrap_or_default()
+}
+
+fn read_transfers(
+    path: &Path,
+    format: Option<TransferFormat>,
+) -> Result<TransferReader<Box<dyn BufRead + Send>>> {
+    let input: Box<dyn BufRead + Send> = if path == Path::new("-") {
+        Box::new(BufReader::new(io::stdin()))
+    } else {
+        Box::new(BufReader::new(
+            File::open(path).with_context(|| format!("Failed to open {}", path.display()))?,
+        ))
+    };
+    TransferReader::new(input, transfer_format(format, path))
+        .with_context(|| format!("Invalid transfer file {}", path.display()))
+}
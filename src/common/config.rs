@@ -0,0 +1,231 @@
+use super::config_file::{self, ConfigFile};
+use anyhow::{Context, Result};
+use clickhouse::Client;
+use hyper_util::client::legacy::{connect::HttpConnector, Client as HyperClient};
+use hyper_util::rt::TokioExecutor;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+use std::time::Duration;
+
+/// Environment variable with the path of a config file for [`ClickhouseConfig::load`].
+pub const CONFIG_PATH_ENV: &str = "CLICKHOUSE_CONFIG";
+
+// Same values `clickhouse::Client::default()` uses for its own HTTP client.
+const TCP_KEEPALIVE: Duration = Duration::from_secs(60);
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CompressionMode {
+    None,
+    #[default]
+    Lz4,
+}
+
+impl std::str::FromStr for CompressionMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(CompressionMode::None),
+            "lz4" => Ok(CompressionMode::Lz4),
+            _ => anyhow::bail!("Unknown compression {s:?}, expected none or lz4"),
+        }
+    }
+}
+
+impl From<CompressionMode> for clickhouse::Compression {
+    fn from(mode: CompressionMode) -> Self {
+        match mode {
+            CompressionMode::None => clickhouse::Compression::None,
+            CompressionMode::Lz4 => clickhouse::Compression::Lz4,
+        }
+    }
+}
+
+/// How to reach ClickHouse and which table holds the transfers.
+///
+/// Settings come from a `.toml`/`.json` file, then `CLICKHOUSE_*` environment variables on top
+/// (see [`ClickhouseConfig::with_env`]); anything left unset keeps its default.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClickhouseConfig {
+    pub url: String,
+    pub user: String,
+    pub password: String,
+    pub database: String,
+    pub table: String,
+    pub compression: CompressionMode,
+    /// How long to wait for a TCP connection; no limit when unset.
+    pub connect_timeout_secs: Option<u64>,
+    /// Sent as the `max_execution_time` setting, so the server cancels longer queries.
+    pub query_timeout_secs: Option<u64>,
+}
+
+impl Default for ClickhouseConfig {
+    fn default() -> Self {
+        Self {
+            url: "http://localhost:8123".to_string(),
+            user: "default".to_string(),
+            password: String::new(),
+            database: "default".to_string(),
+            table: "transfers".to_string(),
+            compression: CompressionMode::default(),
+            connect_timeout_secs: None,
+            query_timeout_secs: None,
+        }
+    }
+}
+
+// Keeps the password out of logs.
+impl fmt::Debug for ClickhouseConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClickhouseConfig")
+            .field("url", &self.url)
+            .field("user", &self.user)
+            .field("password", &"***")
+            .field("database", &self.database)
+            .field("table", &self.table)
+            .field("compression", &self.compression)
+            .field("connect_timeout_secs", &self.connect_timeout_secs)
+            .field("query_timeout_secs", &self.query_timeout_secs)
+            .finish()
+    }
+}
+
+impl ConfigFile for ClickhouseConfig {
+    const NAME: &'static str = "ClickHouse config";
+
+    fn check(&self) -> Result<()> {
+        self.validate()
+    }
+}
+
+impl ClickhouseConfig {
+    /// Defaults, overridden by the file named in [`CONFIG_PATH_ENV`] (if set) and then by the
+    /// process environment.
+    pub fn load() -> Result<Self> {
+        let config = match std::env::var_os(CONFIG_PATH_ENV) {
+            Some(path) => Self::from_file(path)?,
+            None => Self::default(),
+        };
+        config.with_env(std::env::vars())
+    }
+
+    /// The file alone, `.toml` or `.json`; unlike [`ClickhouseConfig::load`], the environment is
+    /// not applied.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        config_file::from_file(path.as_ref())
+    }
+
+    pub fn from_toml_str(content: &str) -> Result<Self> {
+        config_file::from_toml_str(content)
+    }
+
+    pub fn from_json_str(content: &str) -> Result<Self> {
+        config_file::from_json_str(content)
+    }
+
+    /// Applies `CLICKHOUSE_URL`, `CLICKHOUSE_USER`, `CLICKHOUSE_PASSWORD`, `CLICKHOUSE_DATABASE`,
+    /// `CLICKHOUSE_TABLE`, `CLICKHOUSE_COMPRESSION`, `CLICKHOUSE_CONNECT_TIMEOUT_SECS` and
+    /// `CLICKHOUSE_QUERY_TIMEOUT_SECS` from `vars`; other variables are ignored.
+    pub fn with_env<K, V>(mut self, vars: impl IntoIterator<Item = (K, V)>) -> Result<Self>
+    where
+        K: AsRef<str>,
+        V: Into<String>,
+    {
+        for (key, value) in vars {
+            let key = key.as_ref();
+            let value = value.into();
+            match key {
+                "CLICKHOUSE_URL" => self.url = value,
+                "CLICKHOUSE_USER" => self.user = value,
+                "CLICKHOUSE_PASSWORD" => self.password = value,
+                "CLICKHOUSE_DATABASE" => self.database = value,
+                "CLICKHOUSE_TABLE" => self.table = value,
+                "CLICKHOUSE_COMPRESSION" => {
+                    self.compression = value.parse().with_context(|| format!("Invalid {key}"))?
+                }
+                "CLICKHOUSE_CONNECT_TIMEOUT_SECS" => {
+                    self.connect_timeout_secs = Some(parse_secs(key, &value)?)
+                }
+                "CLICKHOUSE_QUERY_TIMEOUT_SECS" => {
+                    self.query_timeout_secs = Some(parse_secs(key, &value)?)
+                }
+                _ => {}
+            }
+        }
+        self.validate()?;
+        Ok(self)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        anyhow::ensure!(
+            self.url.starts_with("http://") || self.url.starts_with("https://"),
+            "url must start with http:// or https://, got {:?}",
+            self.url
+        );
+        anyhow::ensure!(!self.user.is_empty(), "user must not be empty");
+        identifier("database", &self.database)?;
+        // The table name is spliced into SQL, so only plain identifiers are accepted.
+        identifier("table", &self.table)?;
+        anyhow::ensure!(
+            self.connect_timeout_secs != Some(0),
+            "connect_timeout_secs must be positive"
+        );
+        anyhow::ensure!(
+            self.query_timeout_secs != Some(0),
+            "query_timeout_secs must be positive"
+        );
+        Ok(())
+    }
+
+    /// A client for these settings. It is cheap to clone and clones share one connection pool.
+    pub fn client(&self) -> Result<Client> {
+        self.validate()?;
+        let client = match self.connect_timeout_secs {
+            Some(secs) => {
+                let mut connector = HttpConnector::new();
+                connector.set_keepalive(Some(TCP_KEEPALIVE));
+                connector.set_connect_timeout(Some(Duration::from_secs(secs)));
+                Client::with_http_client(
+                    HyperClient::builder(TokioExecutor::new())
+                        .pool_idle_timeout(POOL_IDLE_TIMEOUT)
+                        .build(connector),
+                )
+            }
+            None => Client::default(),
+        };
+        let client = client
+            .with_url(&self.url)
+            .with_user(&self.user)
+            .with_password(&self.password)
+            .with_database(&self.database)
+            .with_compression(self.compression.into());
+        Ok(match self.query_timeout_secs {
+            Some(secs) => client.with_option("max_execution_time", secs.to_string()),
+            None => client,
+        })
+    }
+}
+
+fn parse_secs(key: &str, value: &str) -> Result<u64> {
+    value
+        .trim()
+        .parse()
+        .with_context(|| format!("Invalid {key}: expected whole seconds, got {value:?}"))
+}
+
+fn identifier(field: &str, value: &str) -> Result<()> {
+    let valid = value
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && value.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    anyhow::ensure!(
+        valid,
+        "{field} must be a plain identifier (letters, digits, _), got {value:?}"
+    );
+    Ok(())
+}
@@ -0,0 +1,39 @@
+//! Config files shared by [`ClickhouseConfig`](super::ClickhouseConfig) and
+//! [`TransferGenConfig`](crate::generator::TransferGenConfig): TOML or JSON, picked by the file
+//! extension, validated once parsed.
+
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use std::path::Path;
+
+pub(crate) trait ConfigFile: DeserializeOwned {
+    /// Names the config in errors, e.g. `"ClickHouse config"`.
+    const NAME: &'static str;
+
+    fn check(&self) -> Result<()>;
+}
+
+/// Reads a config from a `.toml` or `.json` file. Missing keys take their default values; the
+/// result is validated.
+pub(crate) fn from_file<T: ConfigFile>(path: &Path) -> Result<T> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {} {}", T::NAME, path.display()))?;
+    let config = match path.extension().and_then(|e| e.to_str()) {
+        Some("toml") => from_toml_str(&content),
+        Some("json") => from_json_str(&content),
+        _ => anyhow::bail!("Unsupported config format, expected .toml or .json"),
+    };
+    config.with_context(|| format!("Invalid {} {}", T::NAME, path.display()))
+}
+
+pub(crate) fn from_toml_str<T: ConfigFile>(content: &str) -> Result<T> {
+    let config: T = toml::from_str(content)?;
+    config.check()?;
+    Ok(config)
+}
+
+pub(crate) fn from_json_str<T: ConfigFile>(content: &str) -> Result<T> {
+    let config: T = serde_json::from_str(content)?;
+    config.check()?;
+    Ok(config)
+}
@@ -1,15 +1,38 @@
+mod config;
+pub(crate) mod config_file;
+
+pub use config::{ClickhouseConfig, CompressionMode, CONFIG_PATH_ENV};
+
+use anyhow::Result;
 use clickhouse::Client;
 
+/// Prints `error` with its causes to stderr, the way the binary reports any failure.
+pub fn report_error(error: &anyhow::Error) {
+    eprintln!("Error: {error:?}");
+}
+
+/// A connection shared by storage and the ClickHouse stats engine. Cloning is cheap and keeps
+/// the same connection pool.
+#[derive(Clone)]
 pub struct ClickhouseClient {
     pub client: Client,
+    /// Table holding the transfers.
+    pub table: String,
 }
 
 impl ClickhouseClient {
+    /// Default settings pointed at `database_url`.
     pub fn new(database_url: &str) -> Self {
-        let client = Client::default()
-            .with_url(database_url)
-            .with_user("default")
-            .with_password("111");
-        Self { client }
+        Self {
+            client: Client::default().with_url(database_url),
+            table: ClickhouseConfig::default().table,
+        }
+    }
+
+    pub fn from_config(config: &ClickhouseConfig) -> Result<Self> {
+        Ok(Self {
+            client: config.client()?,
+            table: config.table.clone(),
+        })
     }
 }
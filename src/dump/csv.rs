@@ -0,0 +1,110 @@
+//! The RFC 4180 subset used by dumps: `,` separators, fields quoted with `"` when they contain
+//! `,`, `"` or a line break, and `""` for a quote inside a quoted field.
+
+/// Outcome of splitting one record.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Split {
+    Fields(Vec<String>),
+    /// A quoted field is still open at the end of the text, so the record continues on the
+    /// next line.
+    Unterminated,
+    Invalid(&'static str),
+}
+
+pub(crate) fn split(record: &str) -> Split {
+    let record = record.strip_suffix('\n').unwrap_or(record);
+    let record = record.strip_suffix('\r').unwrap_or(record);
+
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut closed = false;
+    let mut chars = record.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted => {
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    field.push('"');
+                } else {
+                    quoted = false;
+                    closed = true;
+                }
+            }
+            _ if quoted => field.push(c),
+            ',' => {
+                fields.push(std::mem::take(&mut field));
+                closed = false;
+            }
+            _ if closed => return Split::Invalid("unexpected text after a closing quote"),
+            '"' if field.is_empty() => quoted = true,
+            '"' => return Split::Invalid("unexpected quote inside an unquoted field"),
+            _ => field.push(c),
+        }
+    }
+    if quoted {
+        return Split::Unterminated;
+    }
+    fields.push(field);
+    Split::Fields(fields)
+}
+
+/// Follows quotes through a record fed one line at a time, so a long record is scanned once
+/// rather than re-split on every line.
+#[derive(Debug, Default)]
+pub(crate) struct Quotes {
+    quoted: bool,
+    closed: bool,
+    started: bool,
+    invalid: bool,
+    lines: u64,
+    opened: u64,
+}
+
+impl Quotes {
+    /// Takes the next line of the record. `true` while a quoted field is still open after it;
+    /// text that [`split`] rejects ends the record as well.
+    pub(crate) fn feed(&mut self, line: &str) -> bool {
+        self.lines += 1;
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                _ if self.invalid => break,
+                '"' if self.quoted => {
+                    if chars.peek() == Some(&'"') {
+                        chars.next();
+                    } else {
+                        self.quoted = false;
+                        self.closed = true;
+                    }
+                }
+                _ if self.quoted => {}
+                ',' => {
+                    self.closed = false;
+                    self.started = false;
+                }
+                _ if self.closed => self.invalid = true,
+                '"' if !self.started => {
+                    self.quoted = true;
+                    self.opened = self.lines;
+                }
+                '"' => self.invalid = true,
+                _ => self.started = true,
+            }
+        }
+        self.quoted && !self.invalid
+    }
+
+    /// Which line of the record, counting from 0, the open quoted field starts on.
+    pub(crate) fn opened(&self) -> u64 {
+        self.opened - 1
+    }
+}
+
+pub(crate) fn quote(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
@@ -0,0 +1,476 @@
+//! Moving transfers in and out as CSV or NDJSON files.
+//!
+//! Both formats have the fields of [`TRANSFER_COLUMNS`]. A CSV dump starts with a header naming
+//! each of them exactly once, in any order; an NDJSON dump has one object per line with exactly
+//! those keys. Decimals are written as exact strings, and read from strings or JSON numbers,
+//! taken digit for digit as written rather than through `f64`.
+//!
+//! [`TransferReader`] parses one row at a time, so a dump of any size can be streamed into a
+//! [`TransferStore`] (see [`load`]) or collected for
+//! [`calculate_user_stats_rust`](crate::stats::calculate_user_stats_rust). A bad row becomes a
+//! [`RowError`] with its line, field and reason, and reading goes on with the next row.
+//!
+//! [`parquet`] writes and reads transfers and user stats as Parquet files instead.
+
+pub(crate) mod csv;
+pub mod parquet;
+
+use crate::model::{Decimal, Transfer};
+use crate::storage::TransferStore;
+use anyhow::{Context, Result};
+use csv::{Quotes, Split};
+use serde_json::value::RawValue;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+/// Fields of a dumped transfer, in the order writers put them.
+pub const TRANSFER_COLUMNS: [&str; 9] = [
+    "ts",
+    "address_from",
+    "address_to",
+    "token",
+    "amount",
+    "usd_price",
+    "block_number",
+    "tx_hash",
+    "log_index",
+];
+
+/// How long a CSV record may grow while a quoted field is open before the quote is taken to be
+/// stray. Dumped fields never come close.
+const MAX_RECORD_BYTES: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TransferFormat {
+    Csv,
+    /// One JSON object per line.
+    #[default]
+    Ndjson,
+}
+
+impl TransferFormat {
+    /// `.csv` is CSV, `.ndjson`/`.jsonl` are NDJSON; anything else is unknown.
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+        match path.as_ref().extension()?.to_str()? {
+            "csv" => Some(TransferFormat::Csv),
+            "ndjson" | "jsonl" => Some(TransferFormat::Ndjson),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for TransferFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TransferFormat::Csv => "csv",
+            TransferFormat::Ndjson => "ndjson",
+        })
+    }
+}
+
+impl std::str::FromStr for TransferFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "csv" => Ok(TransferFormat::Csv),
+            "ndjson" | "jsonl" => Ok(TransferFormat::Ndjson),
+            _ => anyhow::bail!("Unknown transfer format {s:?}, expected csv or ndjson"),
+        }
+    }
+}
+
+/// Why one row (or the CSV header) of a dump could not be read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RowError {
+    /// 1-based line the row starts on.
+    pub line: u64,
+    /// The offending field, when the problem is in a single one.
+    pub field: Option<String>,
+    pub reason: String,
+}
+
+impl RowError {
+    fn new(line: u64, reason: impl Into<String>) -> Self {
+        Self {
+            line,
+            field: None,
+            reason: reason.into(),
+        }
+    }
+
+    fn field(line: u64, field: &str, reason: impl Into<String>) -> Self {
+        Self {
+            line,
+            field: Some(field.to_string()),
+            reason: reason.into(),
+        }
+    }
+}
+
+impl fmt::Display for RowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.field {
+            Some(field) => write!(f, "line {}, field {field}: {}", self.line, self.reason),
+            None => write!(f, "line {}: {}", self.line, self.reason),
+        }
+    }
+}
+
+impl std::error::Error for RowError {}
+
+/// Transfers parsed from a dump, one `Result` per row. Blank lines are skipped. It ends after
+/// the first I/O error, which is returned as a [`RowError`] without a field.
+pub struct TransferReader<R> {
+    input: R,
+    format: TransferFormat,
+    /// CSV only: where each of [`TRANSFER_COLUMNS`] sits in a record.
+    positions: [usize; 9],
+    line: u64,
+    failed: bool,
+}
+
+impl<R: BufRead> TransferReader<R> {
+    /// For CSV, reads and checks the header first.
+    pub fn new(input: R, format: TransferFormat) -> Result<Self, RowError> {
+        let mut reader = Self {
+            input,
+            format,
+            positions: std::array::from_fn(|i| i),
+            line: 0,
+            failed: false,
+        };
+        if format == TransferFormat::Csv {
+            let (line, header) = match reader.next_record() {
+                Some(Ok((line, Split::Fields(header)))) => (line, header),
+                Some(Ok((line, _))) => return Err(RowError::new(line, "invalid CSV header")),
+                Some(Err(e)) => return Err(e),
+                None => return Err(RowError::new(1, "missing CSV header")),
+            };
+            reader.positions = header_positions(line, &header)?;
+        }
+        Ok(reader)
+    }
+
+    /// The next non-blank record and the line it starts on. CSV records continue over line
+    /// breaks inside quoted fields, up to [`MAX_RECORD_BYTES`]; an unterminated field is
+    /// reported on the line where it starts.
+    fn next_record(&mut self) -> Option<Result<(u64, Split), RowError>> {
+        let mut text = String::new();
+        let mut start = None;
+        let mut quotes = Quotes::default();
+        loop {
+            let mut line = String::new();
+            match self.input.read_line(&mut line) {
+                Ok(0) => {
+                    return start.map(|start| Ok((start + quotes.opened(), Split::Unterminated)));
+                }
+                Ok(_) => self.line += 1,
+                Err(e) => {
+                    self.failed = true;
+                    return Some(Err(RowError::new(self.line + 1, e.to_string())));
+                }
+            }
+            if start.is_none() && line.trim().is_empty() {
+                continue;
+            }
+            let start = *start.get_or_insert(self.line);
+            text.push_str(&line);
+            let split = match self.format {
+                TransferFormat::Csv if quotes.feed(&line) => {
+                    if text.len() > MAX_RECORD_BYTES {
+                        return Some(Err(RowError::new(
+                            start + quotes.opened(),
+                            format!(
+                                "unterminated quoted field, gave up after {MAX_RECORD_BYTES} bytes"
+                            ),
+                        )));
+                    }
+                    continue;
+                }
+                TransferFormat::Csv => csv::split(&text),
+                TransferFormat::Ndjson => Split::Fields(vec![text.trim().to_string()]),
+            };
+            return Some(Ok((start, split)));
+        }
+    }
+
+    fn parse(&self, line: u64, split: Split) -> Result<Transfer, RowError> {
+        match (self.format, split) {
+            (_, Split::Invalid(reason)) => Err(RowError::new(line, reason)),
+            (_, Split::Unterminated) => Err(RowError::new(line, "unterminated quoted field")),
+            (TransferFormat::Csv, Split::Fields(fields)) => {
+                if fields.len() != TRANSFER_COLUMNS.len() {
+                    return Err(RowError::new(
+                        line,
+                        format!(
+                            "expected {} fields, got {}",
+                            TRANSFER_COLUMNS.len(),
+                            fields.len()
+                        ),
+                    ));
+                }
+                transfer_from(line, |column| Ok(fields[self.positions[column]].clone()))
+            }
+            (TransferFormat::Ndjson, Split::Fields(mut fields)) => {
+                ndjson_transfer(line, &fields.remove(0))
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for TransferReader<R> {
+    type Item = Result<Transfer, RowError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        Some(match self.next_record()? {
+            Ok((line, split)) => self.parse(line, split),
+            Err(e) => Err(e),
+        })
+    }
+}
+
+fn header_positions(line: u64, header: &[String]) -> Result<[usize; 9], RowError> {
+    let mut positions = [None; 9];
+    for (index, name) in header.iter().enumerate() {
+        let name = name.trim();
+        let column = TRANSFER_COLUMNS
+            .iter()
+            .position(|column| *column == name)
+            .ok_or_else(|| RowError::field(line, name, "unknown column"))?;
+        if positions[column].replace(index).is_some() {
+            return Err(RowError::field(line, name, "duplicate column"));
+        }
+    }
+    let mut result = [0; 9];
+    for (column, position) in positions.into_iter().enumerate() {
+        result[column] = position
+            .ok_or_else(|| RowError::field(line, TRANSFER_COLUMNS[column], "missing column"))?;
+    }
+    Ok(result)
+}
+
+fn ndjson_transfer(line: u64, text: &str) -> Result<Transfer, RowError> {
+    // Raw values keep the text of numbers, which a `Value` would round to `f64`.
+    let object: BTreeMap<String, &RawValue> = serde_json::from_str(text)
+        .map_err(|e| RowError::new(line, format!("invalid JSON object: {e}")))?;
+    if let Some(key) = object
+        .keys()
+        .find(|key| !TRANSFER_COLUMNS.contains(&key.as_str()))
+    {
+        return Err(RowError::field(line, key, "unknown field"));
+    }
+    transfer_from(line, |column| {
+        let name = TRANSFER_COLUMNS[column];
+        let value = object
+            .get(name)
+            .ok_or_else(|| RowError::field(line, name, "missing"))?
+            .get();
+        match value.as_bytes()[0] {
+            b'"' => serde_json::from_str(value)
+                .map_err(|e| RowError::field(line, name, format!("invalid string: {e}"))),
+            b'-' | b'0'..=b'9' => Ok(plain_number(value)),
+            _ => Err(RowError::field(line, name, "expected a string or number")),
+        }
+    })
+}
+
+/// The JSON number `text` without an exponent: numbers may be written as `1e-7`, which
+/// [`Decimal`] doesn't parse.
+fn plain_number(text: &str) -> String {
+    let Some((mantissa, exponent)) = text.split_once(['e', 'E']) else {
+        return text.to_string();
+    };
+    let Ok(exponent) = exponent.parse::<i32>() else {
+        return text.to_string();
+    };
+    let (sign, mantissa) = match mantissa.strip_prefix('-') {
+        Some(mantissa) => ("-", mantissa),
+        None => ("", mantissa),
+    };
+    let (integer, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let digits = format!("{integer}{fraction}");
+    // Far past the digits of any decimal; left for the parser to reject without padding it out.
+    if exponent.unsigned_abs() as usize > digits.len() + 64 {
+        return text.to_string();
+    }
+    // Where the decimal point goes in `digits`.
+    let point = integer.len() as i32 + exponent;
+    let plain = if point <= 0 {
+        format!("0.{}{digits}", "0".repeat(point.unsigned_abs() as usize))
+    } else if point as usize >= digits.len() {
+        format!("{digits}{}", "0".repeat(point as usize - digits.len()))
+    } else {
+        let (integer, fraction) = digits.split_at(point as usize);
+        format!("{integer}.{fraction}")
+    };
+    format!("{sign}{plain}")
+}
+
+/// Builds a transfer from the text of each column, in [`TRANSFER_COLUMNS`] order.
+fn transfer_from(
+    line: u64,
+    value: impl Fn(usize) -> Result<String, RowError>,
+) -> Result<Transfer, RowError> {
+    let text = |column: usize| -> Result<String, RowError> {
+        let value = value(column)?;
+        if value.trim().is_empty() {
+            return Err(RowError::field(
+                line,
+                TRANSFER_COLUMNS[column],
+                "must not be empty",
+            ));
+        }
+        Ok(value)
+    };
+    let integer = |column: usize| -> Result<u64, RowError> {
+        let value = text(column)?;
+        value.trim().parse().map_err(|_| {
+            RowError::field(
+                line,
+                TRANSFER_COLUMNS[column],
+                format!("expected a non-negative integer, got {value:?}"),
+            )
+        })
+    };
+    let decimal = |column: usize| -> Result<Decimal, RowError> {
+        text(column)?
+            .trim()
+            .parse()
+            .map_err(|e: crate::model::ParseDecimalError| {
+                RowError::field(line, TRANSFER_COLUMNS[column], e.to_string())
+            })
+    };
+    // Fields are checked in column order, so the first bad one is reported.
+    let (ts, address_from, address_to, token) = (integer(0)?, text(1)?, text(2)?, text(3)?);
+    let (amount, usd_price, block_number, tx_hash) =
+        (decimal(4)?, decimal(5)?, integer(6)?, text(7)?);
+    let log_index = integer(8)?;
+    Ok(Transfer {
+        ts,
+        address_from,
+        address_to,
+        token,
+        amount,
+        usd_price,
+        block_number,
+        tx_hash,
+        log_index: log_index.try_into().map_err(|_| {
+            RowError::field(line, "log_index", format!("{log_index} is out of range"))
+        })?,
+    })
+}
+
+/// Writes transfers one row at a time; a CSV header is written up front.
+pub struct TransferWriter<W: Write> {
+    out: W,
+    format: TransferFormat,
+    rows: u64,
+}
+
+impl<W: Write> TransferWriter<W> {
+    pub fn new(mut out: W, format: TransferFormat) -> Result<Self> {
+        if format == TransferFormat::Csv {
+            writeln!(out, "{}", TRANSFER_COLUMNS.join(","))?;
+        }
+        Ok(Self {
+            out,
+            format,
+            rows: 0,
+        })
+    }
+
+    pub fn write(&mut self, t: &Transfer) -> Result<()> {
+        match self.format {
+            TransferFormat::Csv => writeln!(
+                self.out,
+                "{},{},{},{},{},{},{},{},{}",
+                t.ts,
+                csv::quote(&t.address_from),
+                csv::quote(&t.address_to),
+                csv::quote(&t.token),
+                t.amount,
+                t.usd_price,
+                t.block_number,
+                csv::quote(&t.tx_hash),
+                t.log_index
+            )?,
+            TransferFormat::Ndjson => {
+                serde_json::to_writer(&mut self.out, t)?;
+                writeln!(self.out)?;
+            }
+        }
+        self.rows += 1;
+        Ok(())
+    }
+
+    /// Flushes the output and returns the number of rows written.
+    pub fn finish(mut self) -> Result<u64> {
+        self.out.flush()?;
+        Ok(self.rows)
+    }
+}
+
+/// Writes all of `transfers` and returns the number of rows.
+pub fn write_transfers<W, I>(out: W, format: TransferFormat, transfers: I) -> Result<u64>
+where
+    W: Write,
+    I: IntoIterator<Item = Result<Transfer>>,
+{
+    let mut writer = TransferWriter::new(out, format)?;
+    for transfer in transfers {
+        writer.write(&transfer?)?;
+    }
+    writer.finish()
+}
+
+/// What [`load`] does with a row that fails to parse.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OnInvalidRow {
+    /// Stop reading and fail. Rows before it have been inserted already; inserts replace rows
+    /// with the same id, so the fixed file can simply be loaded again.
+    #[default]
+    Abort,
+    /// Leave the row out and carry on.
+    Skip,
+}
+
+#[derive(Debug, Default)]
+pub struct LoadReport {
+    /// Rows written, replaced ones included.
+    pub inserted: u64,
+    /// Skipped rows, in file order.
+    pub invalid: Vec<RowError>,
+}
+
+/// Streams `rows` into `store`, never holding more than one insert batch in memory.
+pub async fn load<S, R>(store: &S, rows: R, on_invalid: OnInvalidRow) -> Result<LoadReport>
+where
+    S: TransferStore,
+    R: Iterator<Item = Result<Transfer, RowError>> + Send,
+{
+    let mut invalid = vec![];
+    let inserted = match on_invalid {
+        OnInvalidRow::Abort => {
+            let valid = rows.map_while(|row| row.map_err(|e| invalid.push(e)).ok());
+            store.insert_transfers(valid).await?
+        }
+        OnInvalidRow::Skip => {
+            let valid = rows.filter_map(|row| row.map_err(|e| invalid.push(e)).ok());
+            store.insert_transfers(valid).await?
+        }
+    };
+    if on_invalid == OnInvalidRow::Abort {
+        if let Some(error) = invalid.pop() {
+            return Err(error).with_context(|| {
+                format!("Invalid transfer, stopped after inserting {inserted} rows")
+            });
+        }
+    }
+    Ok(LoadReport { inserted, invalid })
+}
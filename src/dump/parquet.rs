@@ -0,0 +1,278 @@
+//! Parquet files of [`Transfer`]s and [`UserStats`], for Arrow-based tools.
+//!
+//! Files are written with a fixed schema ([`ParquetRecord::schema`]): decimals are
+//! `Decimal128(38, 18)`, the exact values ClickHouse stores, and `ts` is a UTC timestamp in
+//! milliseconds (Parquet's `TIMESTAMP(MILLIS, true)`; it has no logical type for seconds).
+//!
+//! Reading is more lenient so files from other tools load too: columns are found by name,
+//! extra ones are ignored, and each is cast to the schema type, e.g. any timestamp unit,
+//! decimals of another scale, floats or other integer widths. Timestamps are truncated to whole
+//! seconds. Nulls and values that don't fit are errors.
+
+use crate::model::{Decimal, Transfer, UserStats};
+use anyhow::{Context, Result};
+use arrow_array::cast::AsArray;
+use arrow_array::types::{Decimal128Type, TimestampSecondType, UInt32Type, UInt64Type};
+use arrow_array::{
+    ArrayRef, Decimal128Array, RecordBatch, StringArray, TimestampMillisecondArray, UInt32Array,
+    UInt64Array,
+};
+use arrow_cast::{cast_with_options, CastOptions};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use parquet::file::reader::ChunkReader;
+use std::io::Write;
+use std::sync::{Arc, LazyLock};
+
+/// Rows per record batch, both when writing and when reading.
+const BATCH_ROWS: usize = 8192;
+
+const UTC: &str = "UTC";
+
+/// A row type with a Parquet schema.
+pub trait ParquetRecord: Sized {
+    fn schema() -> SchemaRef;
+
+    fn to_batch(rows: &[Self]) -> Result<RecordBatch>;
+
+    fn from_batch(batch: &RecordBatch) -> Result<Vec<Self>>;
+}
+
+fn decimal_field(name: &str) -> Field {
+    Field::new(name, DataType::Decimal128(38, Decimal::SCALE as i8), false)
+}
+
+static TRANSFER_SCHEMA: LazyLock<SchemaRef> = LazyLock::new(|| {
+    Arc::new(Schema::new(vec![
+        Field::new(
+            "ts",
+            DataType::Timestamp(TimeUnit::Millisecond, Some(UTC.into())),
+            false,
+        ),
+        Field::new("address_from", DataType::Utf8, false),
+        Field::new("address_to", DataType::Utf8, false),
+        Field::new("token", DataType::Utf8, false),
+        decimal_field("amount"),
+        decimal_field("usd_price"),
+        Field::new("block_number", DataType::UInt64, false),
+        Field::new("tx_hash", DataType::Utf8, false),
+        Field::new("log_index", DataType::UInt32, false),
+    ]))
+});
+
+static USER_STATS_SCHEMA: LazyLock<SchemaRef> = LazyLock::new(|| {
+    Arc::new(Schema::new(vec![
+        Field::new("address", DataType::Utf8, false),
+        Field::new("token", DataType::Utf8, false),
+        decimal_field("total_volume"),
+        decimal_field("avg_buy_price"),
+        decimal_field("avg_sell_price"),
+        decimal_field("max_balance"),
+    ]))
+});
+
+impl ParquetRecord for Transfer {
+    fn schema() -> SchemaRef {
+        TRANSFER_SCHEMA.clone()
+    }
+
+    fn to_batch(rows: &[Self]) -> Result<RecordBatch> {
+        let ts = rows
+            .iter()
+            .map(|t| {
+                i64::try_from(t.ts)
+                    .ok()
+                    .and_then(|secs| secs.checked_mul(1000))
+                    .with_context(|| format!("ts {} is out of range", t.ts))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(TimestampMillisecondArray::from(ts).with_timezone(UTC)),
+            strings(rows, |t| &t.address_from),
+            strings(rows, |t| &t.address_to),
+            strings(rows, |t| &t.token),
+            decimals(rows, |t| t.amount)?,
+            decimals(rows, |t| t.usd_price)?,
+            Arc::new(UInt64Array::from_iter_values(
+                rows.iter().map(|t| t.block_number),
+            )),
+            strings(rows, |t| &t.tx_hash),
+            Arc::new(UInt32Array::from_iter_values(
+                rows.iter().map(|t| t.log_index),
+            )),
+        ];
+        Ok(RecordBatch::try_new(Self::schema(), columns)?)
+    }
+
+    fn from_batch(batch: &RecordBatch) -> Result<Vec<Self>> {
+        let schema = Self::schema();
+        let column = |index: usize| column(batch, schema.field(index));
+        let ts = column(0)?;
+        let ts = ts.as_primitive::<TimestampSecondType>();
+        let (address_from, address_to, token) = (column(1)?, column(2)?, column(3)?);
+        let (address_from, address_to, token) = (
+            address_from.as_string::<i32>(),
+            address_to.as_string::<i32>(),
+            token.as_string::<i32>(),
+        );
+        let (amount, usd_price) = (column(4)?, column(5)?);
+        let (amount, usd_price) = (
+            amount.as_primitive::<Decimal128Type>(),
+            usd_price.as_primitive::<Decimal128Type>(),
+        );
+        let block_number = column(6)?;
+        let block_number = block_number.as_primitive::<UInt64Type>();
+        let tx_hash = column(7)?;
+        let tx_hash = tx_hash.as_string::<i32>();
+        let log_index = column(8)?;
+        let log_index = log_index.as_primitive::<UInt32Type>();
+
+        (0..batch.num_rows())
+            .map(|i| {
+                Ok(Transfer {
+                    ts: u64::try_from(ts.value(i))
+                        .with_context(|| format!("ts {} is before 1970", ts.value(i)))?,
+                    address_from: address_from.value(i).to_string(),
+                    address_to: address_to.value(i).to_string(),
+                    token: token.value(i).to_string(),
+                    amount: Decimal::from_raw(amount.value(i)),
+                    usd_price: Decimal::from_raw(usd_price.value(i)),
+                    block_number: block_number.value(i),
+                    tx_hash: tx_hash.value(i).to_string(),
+                    log_index: log_index.value(i),
+                })
+            })
+            .collect()
+    }
+}
+
+impl ParquetRecord for UserStats {
+    fn schema() -> SchemaRef {
+        USER_STATS_SCHEMA.clone()
+    }
+
+    fn to_batch(rows: &[Self]) -> Result<RecordBatch> {
+        let columns: Vec<ArrayRef> = vec![
+            strings(rows, |s| &s.address),
+            strings(rows, |s| &s.token),
+            decimals(rows, |s| s.total_volume)?,
+            decimals(rows, |s| s.avg_buy_price)?,
+            decimals(rows, |s| s.avg_sell_price)?,
+            decimals(rows, |s| s.max_balance)?,
+        ];
+        Ok(RecordBatch::try_new(Self::schema(), columns)?)
+    }
+
+    fn from_batch(batch: &RecordBatch) -> Result<Vec<Self>> {
+        let schema = Self::schema();
+        let columns = (0..schema.fields().len())
+            .map(|index| column(batch, schema.field(index)))
+            .collect::<Result<Vec<_>>>()?;
+        let (address, token) = (columns[0].as_string::<i32>(), columns[1].as_string::<i32>());
+        let decimal = |index: usize, row: usize| {
+            Decimal::from_raw(columns[index].as_primitive::<Decimal128Type>().value(row))
+        };
+
+        Ok((0..batch.num_rows())
+            .map(|i| UserStats {
+                address: address.value(i).to_string(),
+                token: token.value(i).to_string(),
+                total_volume: decimal(2, i),
+                avg_buy_price: decimal(3, i),
+                avg_sell_price: decimal(4, i),
+                max_balance: decimal(5, i),
+            })
+            .collect())
+    }
+}
+
+fn strings<T>(rows: &[T], value: impl Fn(&T) -> &String) -> ArrayRef {
+    Arc::new(StringArray::from_iter_values(rows.iter().map(value)))
+}
+
+fn decimals<T>(rows: &[T], value: impl Fn(&T) -> Decimal) -> Result<ArrayRef> {
+    let array = Decimal128Array::from_iter_values(rows.iter().map(|row| value(row).raw()))
+        .with_precision_and_scale(38, Decimal::SCALE as i8)?;
+    Ok(Arc::new(array))
+}
+
+/// `field`'s column of `batch`, cast to the type [`ParquetRecord::from_batch`] reads: the field
+/// type, or seconds for timestamps.
+fn column(batch: &RecordBatch, field: &Field) -> Result<ArrayRef> {
+    let name = field.name();
+    let column = batch
+        .column_by_name(name)
+        .with_context(|| format!("Missing column {name}"))?;
+    let target = match field.data_type() {
+        // An offset rather than "UTC": naming a time zone needs arrow's `chrono-tz` feature.
+        DataType::Timestamp(_, _) => DataType::Timestamp(TimeUnit::Second, Some("+00:00".into())),
+        data_type => data_type.clone(),
+    };
+    // `safe: false` turns values that don't fit into errors instead of nulls.
+    let options = CastOptions {
+        safe: false,
+        ..CastOptions::default()
+    };
+    let cast = cast_with_options(column, &target, &options)
+        .with_context(|| format!("Cannot read column {name} of type {}", column.data_type()))?;
+    anyhow::ensure!(
+        cast.null_count() == 0,
+        "Column {name} has {} nulls",
+        cast.null_count()
+    );
+    Ok(cast)
+}
+
+/// Writes `rows` as one Parquet file (Snappy-compressed, [`BATCH_ROWS`] per batch) and returns
+/// the number of rows.
+pub fn write_parquet<T, W, I>(out: W, rows: I) -> Result<u64>
+where
+    T: ParquetRecord,
+    W: Write + Send,
+    I: IntoIterator<Item = Result<T>>,
+{
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+    let mut writer = ArrowWriter::try_new(out, T::schema(), Some(properties))?;
+    let mut batch = Vec::with_capacity(BATCH_ROWS);
+    let mut written = 0;
+    for row in rows {
+        batch.push(row?);
+        if batch.len() == BATCH_ROWS {
+            writer.write(&T::to_batch(&batch)?)?;
+            written += batch.len() as u64;
+            batch.clear();
+        }
+    }
+    if !batch.is_empty() {
+        writer.write(&T::to_batch(&batch)?)?;
+        written += batch.len() as u64;
+    }
+    writer.close()?;
+    Ok(written)
+}
+
+/// Rows of a Parquet file, read one record batch at a time.
+pub fn read_parquet<T, R>(input: R) -> Result<impl Iterator<Item = Result<T>>>
+where
+    T: ParquetRecord,
+    R: ChunkReader + 'static,
+{
+    let reader = ParquetRecordBatchReaderBuilder::try_new(input)
+        .context("Not a Parquet file")?
+        .with_batch_size(BATCH_ROWS)
+        .build()?;
+    Ok(reader.flat_map(|batch| {
+        match batch
+            .map_err(anyhow::Error::from)
+            .and_then(|batch| T::from_batch(&batch))
+        {
+            Ok(rows) => rows.into_iter().map(Ok).collect::<Vec<_>>(),
+            Err(e) => vec![Err(e)],
+        }
+    }))
+}
@@ -0,0 +1,125 @@
+use anyhow::{Context, Result};
+use rand::distributions::{Alphanumeric, Distribution, WeightedIndex};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/// How often each address of the pool takes part in transfers.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AddressActivity {
+    /// Every address is equally likely.
+    Uniform,
+    /// The address of rank `k` (1-based) is picked with weight `1 / k^exponent`: with the default
+    /// exponent of about 1, a handful of whales take a large share of transfers and most
+    /// addresses appear only a few times. The exponent is at most [`MAX_ZIPF_EXPONENT`].
+    Zipf { exponent: f64 },
+}
+
+/// Largest Zipf exponent; far steeper than any real activity, while every rank of a pool still
+/// gets a positive weight.
+pub const MAX_ZIPF_EXPONENT: f64 = 10.0;
+
+impl Default for AddressActivity {
+    fn default() -> Self {
+        AddressActivity::Zipf { exponent: 1.1 }
+    }
+}
+
+/// A fixed population of addresses that transfer participants are drawn from.
+pub(crate) struct AddressPool {
+    addresses: Vec<String>,
+    shares: Vec<f64>,
+    /// Running sums of `shares`.
+    cumulative: Vec<f64>,
+    weights: WeightedIndex<f64>,
+}
+
+impl AddressPool {
+    pub(crate) fn new(rng: &mut impl Rng, size: usize, activity: AddressActivity) -> Result<Self> {
+        anyhow::ensure!(size > 0, "address pool must not be empty");
+        let addresses = (0..size).map(|_| rand_address(rng)).collect();
+        let raw: Vec<f64> = (1..=size)
+            .map(|rank| match activity {
+                AddressActivity::Uniform => 1.0,
+                AddressActivity::Zipf { exponent } => 1.0 / (rank as f64).powf(exponent),
+            })
+            .collect();
+        anyhow::ensure!(
+            raw.iter().all(|w| w.is_finite() && *w > 0.0),
+            "Address activity must give every address a positive, finite weight"
+        );
+        let weights = WeightedIndex::new(&raw).context("Invalid address activity weights")?;
+        let total: f64 = raw.iter().sum();
+        let shares: Vec<f64> = raw.iter().map(|w| w / total).collect();
+        let cumulative = shares
+            .iter()
+            .scan(0.0, |sum, share| {
+                *sum += share;
+                Some(*sum)
+            })
+            .collect();
+        Ok(Self {
+            addresses,
+            shares,
+            cumulative,
+            weights,
+        })
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.addresses.len()
+    }
+
+    pub(crate) fn address(&self, index: usize) -> &str {
+        &self.addresses[index]
+    }
+
+    /// Fraction of all activity that goes to the address at `index`; shares sum to 1.
+    pub(crate) fn share(&self, index: usize) -> f64 {
+        self.shares[index]
+    }
+
+    pub(crate) fn sample(&self, rng: &mut impl Rng) -> usize {
+        self.weights.sample(rng)
+    }
+
+    /// A receiver for `from`, distinct from it unless the pool has a single address: drawn by
+    /// activity from the rest of the pool, in one draw.
+    pub(crate) fn sample_other(&self, rng: &mut impl Rng, from: usize) -> usize {
+        if self.len() == 1 {
+            return from;
+        }
+        let others = 1.0 - self.shares[from];
+        if others > 0.0 {
+            // A point on the line of shares with the span of `from` cut out.
+            let mut point = rng.gen_range(0.0..others);
+            if point >= self.cumulative[from] - self.shares[from] {
+                point += self.shares[from];
+            }
+            let to = self
+                .cumulative
+                .partition_point(|&sum| sum <= point)
+                .min(self.len() - 1);
+            if to != from {
+                return to;
+            }
+        }
+        // `from` holds all the activity, up to rounding: any other address will do.
+        let to = rng.gen_range(0..self.len() - 1);
+        if to >= from {
+            to + 1
+        } else {
+            to
+        }
+    }
+}
+
+pub(crate) fn rand_address(rng: &mut impl Rng) -> String {
+    let suffix: String = rng
+        .sample_iter(&Alphanumeric)
+        .take(10)
+        .map(char::from)
+        .collect();
+
+    format!("0x{}", suffix)
+}
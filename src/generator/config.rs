@@ -0,0 +1,200 @@
+use super::{AddressActivity, BalanceMode, PriceModel, TransferGenConfig, MAX_ZIPF_EXPONENT};
+use crate::common::config_file::{self, ConfigFile};
+use crate::model::Decimal;
+use anyhow::Result;
+use std::fmt;
+use std::path::Path;
+
+/// Why a [`TransferGenConfig`] can't be generated from. `field` is the config key, dotted for
+/// nested settings (`price_model.volatility`).
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    InvertedRange {
+        field: &'static str,
+        min: Decimal,
+        max: Decimal,
+    },
+    ZeroMaxAge,
+    EmptyAddressPool,
+    Negative {
+        field: &'static str,
+        value: String,
+    },
+    NotPositive {
+        field: &'static str,
+        value: String,
+    },
+    NonFinite {
+        field: &'static str,
+        value: f64,
+    },
+    TooLarge {
+        field: &'static str,
+        value: f64,
+        max: f64,
+    },
+    EmptyPriceSeries,
+    EmptyToken,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::InvertedRange { field, min, max } => {
+                write!(f, "{field}: min {min} is above max {max}")
+            }
+            ConfigError::ZeroMaxAge => write!(f, "max_age_secs must be positive"),
+            ConfigError::EmptyAddressPool => write!(f, "address_count must be positive"),
+            ConfigError::Negative { field, value } => {
+                write!(f, "{field} must not be negative, got {value}")
+            }
+            ConfigError::NotPositive { field, value } => {
+                write!(f, "{field} must be positive, got {value}")
+            }
+            ConfigError::NonFinite { field, value } => {
+                write!(f, "{field} must be a finite number, got {value}")
+            }
+            ConfigError::TooLarge { field, value, max } => {
+                write!(f, "{field} must be at most {max}, got {value}")
+            }
+            ConfigError::EmptyPriceSeries => write!(f, "price_model.points must not be empty"),
+            ConfigError::EmptyToken => write!(f, "token must not be empty"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl TransferGenConfig {
+    /// Checks everything generation relies on; [`super::DefaultTransferGenerator`] runs this
+    /// before generating and returns the error instead of panicking.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.token.is_empty() {
+            return Err(ConfigError::EmptyToken);
+        }
+        range("amount", self.min_amount, self.max_amount)?;
+        not_negative_decimal("min_amount", self.min_amount)?;
+        if self.max_age_secs == 0 {
+            return Err(ConfigError::ZeroMaxAge);
+        }
+        if self.address_count == 0 {
+            return Err(ConfigError::EmptyAddressPool);
+        }
+
+        match &self.price_model {
+            PriceModel::Uniform => {
+                range("price", self.min_price, self.max_price)?;
+                not_negative_decimal("min_price", self.min_price)?;
+            }
+            PriceModel::Gbm {
+                start,
+                drift,
+                volatility,
+            } => {
+                positive_decimal("price_model.start", *start)?;
+                finite("price_model.drift", *drift)?;
+                not_negative("price_model.volatility", *volatility)?;
+            }
+            PriceModel::MeanReverting {
+                start,
+                mean,
+                speed,
+                volatility,
+            } => {
+                positive_decimal("price_model.start", *start)?;
+                positive_decimal("price_model.mean", *mean)?;
+                not_negative("price_model.speed", *speed)?;
+                not_negative("price_model.volatility", *volatility)?;
+            }
+            PriceModel::Replay { points } => {
+                if points.is_empty() {
+                    return Err(ConfigError::EmptyPriceSeries);
+                }
+                for (_, price) in points {
+                    positive_decimal("price_model.points", *price)?;
+                }
+            }
+        }
+
+        if let AddressActivity::Zipf { exponent } = self.activity {
+            not_negative("activity.exponent", exponent)?;
+            if exponent > MAX_ZIPF_EXPONENT {
+                return Err(ConfigError::TooLarge {
+                    field: "activity.exponent",
+                    value: exponent,
+                    max: MAX_ZIPF_EXPONENT,
+                });
+            }
+        }
+        if let BalanceMode::Constrained { genesis_supply } = self.balance_mode {
+            positive_decimal("balance_mode.genesis_supply", genesis_supply)?;
+        }
+        Ok(())
+    }
+
+    /// A `.toml` or `.json` file, validated with [`TransferGenConfig::validate`].
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        config_file::from_file(path.as_ref())
+    }
+
+    pub fn from_toml_str(content: &str) -> Result<Self> {
+        config_file::from_toml_str(content)
+    }
+
+    pub fn from_json_str(content: &str) -> Result<Self> {
+        config_file::from_json_str(content)
+    }
+}
+
+impl ConfigFile for TransferGenConfig {
+    const NAME: &'static str = "generator config";
+
+    fn check(&self) -> Result<()> {
+        Ok(self.validate()?)
+    }
+}
+
+fn range(field: &'static str, min: Decimal, max: Decimal) -> Result<(), ConfigError> {
+    if min > max {
+        return Err(ConfigError::InvertedRange { field, min, max });
+    }
+    Ok(())
+}
+
+fn not_negative_decimal(field: &'static str, value: Decimal) -> Result<(), ConfigError> {
+    if value.is_negative() {
+        return Err(ConfigError::Negative {
+            field,
+            value: value.to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn positive_decimal(field: &'static str, value: Decimal) -> Result<(), ConfigError> {
+    if !value.is_positive() {
+        return Err(ConfigError::NotPositive {
+            field,
+            value: value.to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn finite(field: &'static str, value: f64) -> Result<(), ConfigError> {
+    if !value.is_finite() {
+        return Err(ConfigError::NonFinite { field, value });
+    }
+    Ok(())
+}
+
+fn not_negative(field: &'static str, value: f64) -> Result<(), ConfigError> {
+    finite(field, value)?;
+    if value < 0.0 {
+        return Err(ConfigError::Negative {
+            field,
+            value: value.to_string(),
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,141 @@
+use super::addresses::AddressPool;
+use super::price::PricePath;
+use super::{block_at, rand_decimal, rand_tx_hash, TransferGenConfig};
+use crate::model::{Decimal, Transfer};
+use anyhow::{Context, Result};
+use rand::Rng;
+
+/// Sender of the genesis mint transfers, like the zero address of an ERC-20 `Transfer` event.
+/// It is the only address whose balance goes negative.
+pub const MINT_ADDRESS: &str = "0x0000000000";
+
+/// Balances as the generator sees them while emitting transfers in time order.
+///
+/// Credits become spendable only once time moves past the transfer that made them, so transfers
+/// sharing a `ts` can be replayed in any order (as [`Transfer::cmp_order`] does) without a
+/// sender ever going below zero.
+pub(crate) struct Ledger {
+    settled: Vec<Decimal>,
+    pending: Vec<(usize, Decimal)>,
+    ts: u64,
+    genesis_ts: u64,
+    /// All mints are logs of one genesis transaction.
+    genesis_tx: String,
+    token: String,
+    mints: std::iter::Enumerate<std::vec::IntoIter<(usize, Decimal)>>,
+}
+
+impl Ledger {
+    pub(crate) fn new(
+        rng: &mut impl Rng,
+        pool: &AddressPool,
+        token: &str,
+        genesis_supply: Decimal,
+        genesis_ts: u64,
+    ) -> Result<Self> {
+        // Holdings follow activity, so whales start out with whale-sized balances. The top
+        // address also takes what the other shares lose to rounding, so exactly `genesis_supply`
+        // is minted.
+        let mut mints = (1..pool.len())
+            .map(|index| {
+                Decimal::from_f64(pool.share(index))
+                    .and_then(|share| share.checked_mul(genesis_supply))
+                    .map(|amount| (index, amount))
+            })
+            .collect::<Option<Vec<_>>>()
+            .context("Genesis supply is out of range")?;
+        let rest: Decimal = mints.iter().map(|(_, amount)| *amount).sum();
+        mints.insert(0, (0, genesis_supply - rest));
+        mints.retain(|(_, amount)| amount.is_positive());
+
+        Ok(Self {
+            settled: vec![Decimal::ZERO; pool.len()],
+            pending: vec![],
+            ts: genesis_ts,
+            genesis_ts,
+            genesis_tx: rand_tx_hash(rng),
+            token: token.to_string(),
+            mints: mints.into_iter().enumerate(),
+        })
+    }
+
+    pub(crate) fn genesis_ts(&self) -> u64 {
+        self.genesis_ts
+    }
+
+    /// The next genesis mint, until all of them have been emitted.
+    pub(crate) fn next_mint(
+        &mut self,
+        rng: &mut impl Rng,
+        pool: &AddressPool,
+        prices: &PricePath,
+    ) -> Option<Transfer> {
+        let (log_index, (index, amount)) = self.mints.next()?;
+        self.pending.push((index, amount));
+        Some(Transfer {
+            ts: self.genesis_ts,
+            address_from: MINT_ADDRESS.to_string(),
+            address_to: pool.address(index).to_string(),
+            token: self.token.clone(),
+            amount,
+            usd_price: prices.price_at(rng, self.genesis_ts),
+            block_number: block_at(self.genesis_ts),
+            tx_hash: self.genesis_tx.clone(),
+            log_index: log_index as u32,
+        })
+    }
+
+    /// A transfer at `ts` that its sender can afford; `ts` must not go backwards.
+    pub(crate) fn next_transfer(
+        &mut self,
+        rng: &mut impl Rng,
+        pool: &AddressPool,
+        prices: &PricePath,
+        config: &TransferGenConfig,
+        ts: u64,
+    ) -> Result<Transfer> {
+        self.advance_to(ts);
+        let from = self.pick_sender(rng, pool).with_context(|| {
+            format!("No address can afford a transfer at ts {ts}, increase the genesis supply")
+        })?;
+        let to = pool.sample_other(rng, from);
+        let amount =
+            rand_decimal(rng, config.min_amount, config.max_amount).min(self.settled[from]);
+        self.settled[from] -= amount;
+        self.pending.push((to, amount));
+        Ok(Transfer {
+            ts,
+            address_from: pool.address(from).to_string(),
+            address_to: pool.address(to).to_string(),
+            token: config.token.clone(),
+            amount,
+            usd_price: prices.price_at(rng, ts),
+            block_number: block_at(ts),
+            tx_hash: rand_tx_hash(rng),
+            log_index: 0,
+        })
+    }
+
+    fn advance_to(&mut self, ts: u64) {
+        if ts != self.ts {
+            for (index, amount) in self.pending.drain(..) {
+                self.settled[index] += amount;
+            }
+            self.ts = ts;
+        }
+    }
+
+    /// An activity-weighted sender with a spendable balance, falling back to the richest address
+    /// when random picks keep landing on empty ones.
+    fn pick_sender(&self, rng: &mut impl Rng, pool: &AddressPool) -> Option<usize> {
+        const ATTEMPTS: usize = 32;
+        (0..ATTEMPTS)
+            .map(|_| pool.sample(rng))
+            .find(|index| self.settled[*index].is_positive())
+            .or_else(|| {
+                (0..pool.len())
+                    .max_by_key(|index| self.settled[*index])
+                    .filter(|index| self.settled[*index].is_positive())
+            })
+    }
+}
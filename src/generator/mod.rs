@@ -1,93 +1,337 @@
-use crate::model::Transfer;
+use crate::model::{Decimal, Transfer, DEFAULT_TOKEN};
+use addresses::AddressPool;
 use anyhow::{Context, Result};
-use rand::{distributions::Alphanumeric, Rng};
+use futures::stream::{self, BoxStream, StreamExt};
+use ledger::Ledger;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
 use std::time::{SystemTime, UNIX_EPOCH};
 
-#[derive(Debug, Clone)]
+mod addresses;
+mod config;
+mod ledger;
+mod price;
+mod scenario;
+
+pub use addresses::{AddressActivity, MAX_ZIPF_EXPONENT};
+pub use config::ConfigError;
+pub use ledger::MINT_ADDRESS;
+pub use price::PriceModel;
+use price::PricePath;
+pub use scenario::{LabeledTransfers, Scenario, ScenarioGenerator, ScenarioKind, ScenarioLabel};
+
+/// Seconds per block when deriving a generated transfer's `block_number` from its `ts`, about
+/// Ethereum's block time.
+pub const BLOCK_TIME_SECS: u64 = 12;
+
+/// Whether senders need to hold what they send.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BalanceMode {
+    /// Senders and amounts are drawn independently, so balances can go negative.
+    #[default]
+    Unconstrained,
+    /// `genesis_supply` is first minted from [`MINT_ADDRESS`] to the pool, split by activity, at
+    /// `now - max_age_secs` (0 if `now` is smaller), and every later transfer comes at least a
+    /// second after it. After that, every transfer is affordable by its sender (amounts are
+    /// capped at the sender's balance) and the output is always in `ts` order. The mint transfers
+    /// come on top of the requested count.
+    Constrained { genesis_supply: Decimal },
+}
+
+/// With both `seed` and `now` set, generation is reproducible: the same config and count always
+/// produce the same transfers from the same build. Address activity and prices go through `f64`
+/// `powf`/`ln`/`exp`/`cos`, which may round differently with another libm, so other platforms can
+/// differ.
+///
+/// Can be read from TOML or JSON (see [`TransferGenConfig::from_file`]); keys left out keep their
+/// default values.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct TransferGenConfig {
-    pub min_amount: f64,
-    pub max_amount: f64,
-    pub min_price: f64,
-    pub max_price: f64,
+    /// Token of every generated transfer; generate several batches for multi-token data.
+    pub token: String,
+    pub min_amount: Decimal,
+    pub max_amount: Decimal,
+    /// Price range of [`PriceModel::Uniform`]; other models ignore it.
+    pub min_price: Decimal,
+    pub max_price: Decimal,
+    pub price_model: PriceModel,
     pub max_age_secs: u64,
+    /// Number of distinct addresses transfers are drawn from.
+    pub address_count: usize,
+    pub activity: AddressActivity,
+    pub balance_mode: BalanceMode,
+    /// Emit transfers in `ts` order. Ordered output is produced just as lazily as unordered.
+    pub ts_ordered: bool,
+    /// RNG seed; a random one is picked (and reported) when unset.
+    pub seed: Option<u64>,
+    /// Reference unix time that `ts` counts back from; the current time when unset.
+    pub now: Option<u64>,
 }
 
 impl Default for TransferGenConfig {
     fn default() -> Self {
         Self {
-            min_amount: 1.0,
-            max_amount: 1000.0,
-            min_price: 0.1,
-            max_price: 2.0,
+            token: DEFAULT_TOKEN.to_string(),
+            min_amount: Decimal::from(1),
+            max_amount: Decimal::from(1000),
+            min_price: Decimal::new(1, 1),
+            max_price: Decimal::from(2),
+            price_model: PriceModel::default(),
             max_age_secs: 86_400 * 30,
+            address_count: 1_000,
+            activity: AddressActivity::default(),
+            balance_mode: BalanceMode::default(),
+            ts_ordered: false,
+            seed: None,
+            now: None,
         }
     }
 }
 
+#[derive(Default)]
 pub struct DefaultTransferGenerator {
     pub config: TransferGenConfig,
 }
 
-impl Default for DefaultTransferGenerator {
-    fn default() -> Self {
-        Self {
-            config: TransferGenConfig::default(),
-        }
-    }
+/// Generated transfers with the seed and reference time they were generated from; setting both
+/// in [`TransferGenConfig`] reproduces the same transfers.
+#[derive(Debug, Clone)]
+pub struct GeneratedTransfers {
+    pub seed: u64,
+    pub now: u64,
+    pub transfers: Vec<Transfer>,
 }
 
 pub trait TransferGenerator {
     fn generate(&self, count: usize) -> Result<Vec<Transfer>>;
+
+    /// Transfers produced one at a time. The default builds the whole batch with
+    /// [`TransferGenerator::generate`] first; generators that can do better override it.
+    fn generate_iter(
+        &self,
+        count: usize,
+    ) -> Box<dyn Iterator<Item = Result<Transfer>> + Send + '_> {
+        match self.generate(count) {
+            Ok(transfers) => Box::new(transfers.into_iter().map(Ok)),
+            Err(e) => Box::new(std::iter::once(Err(e))),
+        }
+    }
+
+    /// [`TransferGenerator::generate_iter`] as a stream, e.g. for
+    /// [`calculate_user_stats_stream`](crate::stats::calculate_user_stats_stream).
+    fn generate_stream(&self, count: usize) -> BoxStream<'_, Result<Transfer>> {
+        stream::iter(self.generate_iter(count)).boxed()
+    }
 }
 
 impl TransferGenerator for DefaultTransferGenerator {
     fn generate(&self, count: usize) -> Result<Vec<Transfer>> {
-        let mut rng = rand::thread_rng();
-
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .context("Failed to get current time")?
-            .as_secs();
-
-        Ok((0..count)
-            .map(|_| {
-                let address_from = rand_address(&mut rng);
-                let address_to = rand_address(&mut rng);
-
-                let amount =
-                    if (self.config.min_amount - self.config.max_amount).abs() < f64::EPSILON {
-                        self.config.min_amount
-                    } else {
-                        rng.gen_range(self.config.min_amount..self.config.max_amount)
-                    };
-
-                let usd_price =
-                    if (self.config.min_price - self.config.max_price).abs() < f64::EPSILON {
-                        self.config.min_price
-                    } else {
-                        rng.gen_range(self.config.min_price..self.config.max_price)
-                    };
-
-                let ts = now - rng.gen_range(0..self.config.max_age_secs);
-
-                Transfer {
+        self.transfers(count)?.collect()
+    }
+
+    fn generate_iter(
+        &self,
+        count: usize,
+    ) -> Box<dyn Iterator<Item = Result<Transfer>> + Send + '_> {
+        match self.transfers(count) {
+            Ok(transfers) => Box::new(transfers),
+            Err(e) => Box::new(std::iter::once(Err(e))),
+        }
+    }
+}
+
+impl DefaultTransferGenerator {
+    pub fn generate_batch(&self, count: usize) -> Result<GeneratedTransfers> {
+        let transfers = self.transfers(count)?;
+        let (seed, now) = (transfers.seed(), transfers.now());
+        Ok(GeneratedTransfers {
+            seed,
+            now,
+            transfers: transfers.collect::<Result<_>>()?,
+        })
+    }
+
+    /// Lazily generated transfers: memory stays at the size of the address pool and price path
+    /// however many transfers are taken.
+    pub fn transfers(&self, count: usize) -> Result<Transfers> {
+        self.config.validate()?;
+        let config = self.config.clone();
+        let seed = config.seed.unwrap_or_else(rand::random);
+        let now = match config.now {
+            Some(now) => now,
+            None => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .context("Failed to get current time")?
+                .as_secs(),
+        };
+        // ChaCha rather than StdRng: its output is stable across rand releases and platforms.
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        let pool = AddressPool::new(&mut rng, config.address_count, config.activity)?;
+        let oldest = now.saturating_sub(config.max_age_secs);
+        let prices = PricePath::new(
+            &config.price_model,
+            &mut rng,
+            (config.min_price, config.max_price),
+            (oldest, now),
+        )
+        .context("Invalid price model")?;
+        let ledger = match config.balance_mode {
+            BalanceMode::Unconstrained => None,
+            BalanceMode::Constrained { genesis_supply } => Some(Ledger::new(
+                &mut rng,
+                &pool,
+                &config.token,
+                genesis_supply,
+                oldest,
+            )?),
+        };
+        let ordered = (config.ts_ordered || ledger.is_some()).then_some(SortedUniform {
+            remaining: count,
+            current: 0.0,
+        });
+
+        Ok(Transfers {
+            seed,
+            now,
+            config,
+            rng,
+            pool,
+            prices,
+            ledger,
+            ordered,
+            remaining: count,
+            failed: false,
+        })
+    }
+}
+
+/// Iterator returned by [`DefaultTransferGenerator::transfers`]. It stops after the first error.
+pub struct Transfers {
+    seed: u64,
+    now: u64,
+    config: TransferGenConfig,
+    rng: ChaCha8Rng,
+    pool: AddressPool,
+    prices: PricePath,
+    ledger: Option<Ledger>,
+    ordered: Option<SortedUniform>,
+    remaining: usize,
+    failed: bool,
+}
+
+impl Transfers {
+    /// Seed in use; set it in [`TransferGenConfig::seed`] to reproduce this run.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Reference time in use; set it in [`TransferGenConfig::now`] to reproduce this run.
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    fn next_ts(&mut self) -> u64 {
+        let max_age = self.config.max_age_secs;
+        let age = match &mut self.ordered {
+            // Oldest first: `max_age - 1` seconds back down to 0.
+            Some(sorted) => {
+                let position = (sorted.next(&mut self.rng) * max_age as f64) as u64;
+                max_age.saturating_sub(1).saturating_sub(position)
+            }
+            None => self.rng.gen_range(0..max_age),
+        };
+        let ts = self.now.saturating_sub(age);
+        // Mints only settle once time moves past them. Ages beyond `now` saturate to 0, which is
+        // also the genesis ts then.
+        match &self.ledger {
+            Some(ledger) => ts.max(ledger.genesis_ts() + 1),
+            None => ts,
+        }
+    }
+}
+
+impl Iterator for Transfers {
+    type Item = Result<Transfer>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        if let Some(ledger) = &mut self.ledger {
+            if let Some(mint) = ledger.next_mint(&mut self.rng, &self.pool, &self.prices) {
+                return Some(Ok(mint));
+            }
+        }
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+
+        let ts = self.next_ts();
+        let (rng, pool, prices, config) = (&mut self.rng, &self.pool, &self.prices, &self.config);
+        let transfer = match &mut self.ledger {
+            Some(ledger) => ledger.next_transfer(rng, pool, prices, config, ts),
+            None => {
+                let from = pool.sample(rng);
+                let to = pool.sample_other(rng, from);
+                let amount = rand_decimal(rng, config.min_amount, config.max_amount);
+                let usd_price = prices.price_at(rng, ts);
+                Ok(Transfer {
                     ts,
-                    address_from,
-                    address_to,
+                    address_from: pool.address(from).to_string(),
+                    address_to: pool.address(to).to_string(),
+                    token: config.token.clone(),
                     amount,
                     usd_price,
-                }
-            })
-            .collect())
+                    block_number: block_at(ts),
+                    tx_hash: rand_tx_hash(rng),
+                    log_index: 0,
+                })
+            }
+        };
+        self.failed = transfer.is_err();
+        Some(transfer)
     }
 }
 
-fn rand_address(rng: &mut impl Rng) -> String {
-    let suffix: String = rng
-        .sample_iter(&Alphanumeric)
-        .take(10)
-        .map(char::from)
-        .collect();
+/// Ascending samples of `remaining` uniform values in `[0, 1)`, one at a time: each is the
+/// minimum of the values still to come, so no buffer and no sort are needed.
+struct SortedUniform {
+    remaining: usize,
+    current: f64,
+}
 
-    format!("0x{}", suffix)
+impl SortedUniform {
+    fn next(&mut self, rng: &mut impl Rng) -> f64 {
+        let u: f64 = rng.gen();
+        let n = self.remaining.max(1) as f64;
+        self.current += (1.0 - self.current) * (1.0 - u.powf(1.0 / n));
+        self.remaining = self.remaining.saturating_sub(1);
+        self.current = self.current.min(1.0 - f64::EPSILON);
+        self.current
+    }
+}
+
+/// Block a transfer at `ts` lands in, with one block every [`BLOCK_TIME_SECS`] since the epoch.
+pub(crate) fn block_at(ts: u64) -> u64 {
+    ts / BLOCK_TIME_SECS
+}
+
+/// A random 32-byte transaction hash, hex encoded.
+pub(crate) fn rand_tx_hash(rng: &mut impl Rng) -> String {
+    let bytes: [u8; 32] = rng.gen();
+    let hex: String = bytes.iter().map(|b| format!("{b:02x}")).collect();
+    format!("0x{hex}")
+}
+
+/// Uniform in `min..max`, or exactly `min` when the range is empty.
+fn rand_decimal(rng: &mut impl Rng, min: Decimal, max: Decimal) -> Decimal {
+    if min == max {
+        min
+    } else {
+        Decimal::from_raw(rng.gen_range(min.raw()..max.raw()))
+    }
 }
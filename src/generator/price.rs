@@ -0,0 +1,205 @@
+use super::rand_decimal;
+use crate::model::Decimal;
+use anyhow::{Context, Result};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+const SECS_PER_YEAR: f64 = 365.0 * 86_400.0;
+/// Upper bound on simulated path points, so very long time ranges get a coarser step.
+const MAX_PATH_POINTS: u64 = 100_000;
+const MIN_STEP_SECS: u64 = 60;
+
+/// Where each transfer's `usd_price` comes from.
+///
+/// Except for [`PriceModel::Uniform`], prices follow a path over time, and a transfer is priced
+/// at its `ts`. Simulated paths run from `now - max_age_secs` to `now` in steps of at least a
+/// minute; drift and volatility are annualized.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PriceModel {
+    /// Independent draws from `min_price..max_price` for every transfer.
+    #[default]
+    Uniform,
+    /// Geometric Brownian motion starting at `start`.
+    Gbm {
+        start: Decimal,
+        drift: f64,
+        volatility: f64,
+    },
+    /// Log price pulled back towards `ln(mean)` at `speed` per year (an exponential
+    /// Ornstein–Uhlenbeck process), so the price wanders around `mean` without a trend.
+    MeanReverting {
+        start: Decimal,
+        mean: Decimal,
+        speed: f64,
+        volatility: f64,
+    },
+    /// A recorded series of `(ts, price)` points. A transfer gets the last price at or before its
+    /// `ts`, or the first one if it is older than the series.
+    Replay { points: Vec<(u64, Decimal)> },
+}
+
+impl PriceModel {
+    /// Reads a [`PriceModel::Replay`] series from a `ts,price` CSV file (see
+    /// [`PriceModel::replay_from_csv`]).
+    pub fn replay_from_csv_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read price series {}", path.display()))?;
+        Self::replay_from_csv(&content)
+            .with_context(|| format!("Invalid price series {}", path.display()))
+    }
+
+    /// Parses `ts,price` lines; a header line and blank lines are skipped. Points may come in any
+    /// order.
+    pub fn replay_from_csv(content: &str) -> Result<Self> {
+        let mut points = vec![];
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || (number == 0 && line.starts_with(|c: char| c.is_alphabetic())) {
+                continue;
+            }
+            let parse = || -> Result<(u64, Decimal)> {
+                let (ts, price) = line.split_once(',').context("expected `ts,price`")?;
+                let ts = ts.trim().parse().context("invalid ts")?;
+                let price: Decimal = price.trim().parse()?;
+                anyhow::ensure!(price.is_positive(), "price must be positive");
+                Ok((ts, price))
+            };
+            points.push(parse().with_context(|| format!("line {}", number + 1))?);
+        }
+        anyhow::ensure!(!points.is_empty(), "price series is empty");
+        points.sort_by_key(|(ts, _)| *ts);
+        Ok(PriceModel::Replay { points })
+    }
+}
+
+/// A [`PriceModel`] prepared for one generation run.
+pub(crate) enum PricePath {
+    Uniform {
+        min: Decimal,
+        max: Decimal,
+    },
+    Grid {
+        start: u64,
+        step: u64,
+        prices: Vec<Decimal>,
+    },
+    Series(Vec<(u64, Decimal)>),
+}
+
+impl PricePath {
+    pub(crate) fn new(
+        model: &PriceModel,
+        rng: &mut impl Rng,
+        (min_price, max_price): (Decimal, Decimal),
+        (from, to): (u64, u64),
+    ) -> Result<Self> {
+        let span = to.saturating_sub(from);
+        let step = (span / MAX_PATH_POINTS).max(MIN_STEP_SECS);
+        let points = (span / step + 1) as usize;
+        let dt = step as f64 / SECS_PER_YEAR;
+
+        let log_prices: Vec<f64> = match model {
+            PriceModel::Uniform => {
+                return Ok(PricePath::Uniform {
+                    min: min_price,
+                    max: max_price,
+                })
+            }
+            PriceModel::Replay { points } => {
+                anyhow::ensure!(!points.is_empty(), "price series is empty");
+                let mut points = points.clone();
+                points.sort_by_key(|(ts, _)| *ts);
+                return Ok(PricePath::Series(points));
+            }
+            PriceModel::Gbm {
+                start,
+                drift,
+                volatility,
+            } => {
+                let (start, drift, volatility) = (ln(*start)?, *drift, check_vol(*volatility)?);
+                let step_drift = (drift - volatility * volatility / 2.0) * dt;
+                let step_vol = volatility * dt.sqrt();
+                walk(start, points, |x| {
+                    x + step_drift + step_vol * standard_normal(rng)
+                })
+            }
+            PriceModel::MeanReverting {
+                start,
+                mean,
+                speed,
+                volatility,
+            } => {
+                let (start, mean, volatility) = (ln(*start)?, ln(*mean)?, check_vol(*volatility)?);
+                anyhow::ensure!(
+                    speed.is_finite() && *speed >= 0.0,
+                    "speed must be non-negative"
+                );
+                let step_vol = volatility * dt.sqrt();
+                walk(start, points, |x| {
+                    x + speed * (mean - x) * dt + step_vol * standard_normal(rng)
+                })
+            }
+        };
+
+        let prices = log_prices
+            .into_iter()
+            .map(|x| Decimal::from_f64(x.exp()).filter(|p| p.is_positive()))
+            .collect::<Option<Vec<_>>>()
+            .context("Simulated price left the representable range")?;
+        Ok(PricePath::Grid {
+            start: from,
+            step,
+            prices,
+        })
+    }
+
+    pub(crate) fn price_at(&self, rng: &mut impl Rng, ts: u64) -> Decimal {
+        match self {
+            PricePath::Uniform { min, max } => rand_decimal(rng, *min, *max),
+            PricePath::Grid {
+                start,
+                step,
+                prices,
+            } => {
+                let index = (ts.saturating_sub(*start) / step) as usize;
+                prices[index.min(prices.len() - 1)]
+            }
+            PricePath::Series(points) => {
+                let after = points.partition_point(|(point_ts, _)| *point_ts <= ts);
+                points[after.saturating_sub(1)].1
+            }
+        }
+    }
+}
+
+fn walk(start: f64, points: usize, mut next: impl FnMut(f64) -> f64) -> Vec<f64> {
+    std::iter::successors(Some(start), |x| Some(next(*x)))
+        .take(points)
+        .collect()
+}
+
+fn ln(price: Decimal) -> Result<f64> {
+    anyhow::ensure!(
+        price.is_positive(),
+        "price model prices must be positive, got {price}"
+    );
+    Ok(price.to_f64().ln())
+}
+
+fn check_vol(volatility: f64) -> Result<f64> {
+    anyhow::ensure!(
+        volatility.is_finite() && volatility >= 0.0,
+        "volatility must be non-negative"
+    );
+    Ok(volatility)
+}
+
+/// Box–Muller, so the path only depends on the seeded generator.
+fn standard_normal(rng: &mut impl Rng) -> f64 {
+    let u1: f64 = 1.0 - rng.gen::<f64>();
+    let u2: f64 = rng.gen();
+    (-2.0 * u1.ln()).sqrt() * (std::f64::consts::TAU * u2).cos()
+}
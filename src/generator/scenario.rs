@@ -0,0 +1,380 @@
+use super::addresses::rand_address;
+use super::{block_at, rand_tx_hash, TransferGenerator, MINT_ADDRESS};
+use crate::model::{Decimal, Transfer};
+use anyhow::{Context, Result};
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// A known pattern to plant in generated data.
+///
+/// Every scenario uses fresh addresses for its actors (ring members, whale, distributor,
+/// spammer). Actors that give tokens away are first funded from [`MINT_ADDRESS`] at the start of
+/// the time window, so a balance-constrained base ledger stays consistent. Counterparties are
+/// drawn from the base data's addresses. Each scenario trades one token of the base data, picked at
+/// random. A planted transfer takes the price of the latest base transfer of that token at or
+/// before its `ts`, or of the first one if there is none.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Scenario {
+    /// `ring_size` addresses pass `amount` around the ring `rounds` times, one hop per second.
+    /// Every member ends where it started.
+    WashTrading {
+        ring_size: usize,
+        rounds: usize,
+        amount: Decimal,
+    },
+    /// One whale sells `amount` to a random counterparty in each of `transfers_per_burst`
+    /// back-to-back transfers (one per second), in `bursts` bursts at random times.
+    WhaleDump {
+        bursts: usize,
+        transfers_per_burst: usize,
+        amount: Decimal,
+    },
+    /// One distributor sends `amount` to each of `recipients` addresses in a single transaction.
+    Airdrop { recipients: usize, amount: Decimal },
+    /// One spammer sends a tiny `amount` to random addresses `transfers` times.
+    DustSpam { transfers: usize, amount: Decimal },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum ScenarioKind {
+    WashTrading,
+    WhaleDump,
+    Airdrop,
+    DustSpam,
+}
+
+impl Scenario {
+    pub fn kind(&self) -> ScenarioKind {
+        match self {
+            Scenario::WashTrading { .. } => ScenarioKind::WashTrading,
+            Scenario::WhaleDump { .. } => ScenarioKind::WhaleDump,
+            Scenario::Airdrop { .. } => ScenarioKind::Airdrop,
+            Scenario::DustSpam { .. } => ScenarioKind::DustSpam,
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        let (count_ok, amount) = match self {
+            Scenario::WashTrading {
+                ring_size,
+                rounds,
+                amount,
+            } => (*ring_size >= 2 && *rounds > 0, amount),
+            Scenario::WhaleDump {
+                bursts,
+                transfers_per_burst,
+                amount,
+            } => (*bursts > 0 && *transfers_per_burst > 0, amount),
+            Scenario::Airdrop { recipients, amount } => (*recipients > 0, amount),
+            Scenario::DustSpam { transfers, amount } => (*transfers > 0, amount),
+        };
+        anyhow::ensure!(
+            count_ok,
+            "{:?} needs positive counts (and a ring of at least 2)",
+            self.kind()
+        );
+        anyhow::ensure!(
+            amount.is_positive(),
+            "{:?} needs a positive amount",
+            self.kind()
+        );
+        Ok(())
+    }
+}
+
+/// Ground truth for one planted transfer.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ScenarioLabel {
+    /// Index into [`LabeledTransfers::transfers`].
+    pub transfer: usize,
+    /// Index into [`ScenarioGenerator::scenarios`].
+    pub scenario: usize,
+    pub kind: ScenarioKind,
+}
+
+#[derive(Debug, Clone)]
+pub struct LabeledTransfers {
+    pub seed: u64,
+    /// Base and planted transfers together, ordered by `ts`.
+    pub transfers: Vec<Transfer>,
+    /// One label per planted transfer, in transfer order; unlabeled transfers come from the base
+    /// generator.
+    pub labels: Vec<ScenarioLabel>,
+}
+
+/// Plants [`Scenario`]s into the output of another generator, within the time range that output
+/// covers.
+pub struct ScenarioGenerator<G> {
+    pub base: G,
+    pub scenarios: Vec<Scenario>,
+    /// Seed for scenario placement; a random one is picked (and reported) when unset.
+    pub seed: Option<u64>,
+}
+
+impl<G: TransferGenerator> ScenarioGenerator<G> {
+    pub fn new(base: G) -> Self {
+        Self {
+            base,
+            scenarios: vec![],
+            seed: None,
+        }
+    }
+
+    pub fn with_scenario(mut self, scenario: Scenario) -> Self {
+        self.scenarios.push(scenario);
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// `count` base transfers plus everything the scenarios add.
+    pub fn generate_labeled(&self, count: usize) -> Result<LabeledTransfers> {
+        for scenario in &self.scenarios {
+            scenario.validate()?;
+        }
+        let seed = self.seed.unwrap_or_else(rand::random);
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+
+        let mut base = self.base.generate(count)?;
+        base.sort_by_key(|t| t.ts);
+        let start = base.first().map(|t| t.ts);
+        let end = base.last().map(|t| t.ts);
+        let (Some(start), Some(end)) = (start, end) else {
+            anyhow::ensure!(
+                self.scenarios.is_empty(),
+                "Scenarios need base transfers to place them in time"
+            );
+            return Ok(LabeledTransfers {
+                seed,
+                transfers: vec![],
+                labels: vec![],
+            });
+        };
+
+        let mut counterparties: Vec<String> = base
+            .iter()
+            .flat_map(|t| [t.address_from.clone(), t.address_to.clone()])
+            .filter(|address| address != MINT_ADDRESS)
+            .collect();
+        counterparties.sort_unstable();
+        counterparties.dedup();
+
+        let mut prices: HashMap<String, Vec<(u64, Decimal)>> = HashMap::new();
+        for t in &base {
+            prices
+                .entry(t.token.clone())
+                .or_default()
+                .push((t.ts, t.usd_price));
+        }
+        let mut tokens: Vec<String> = prices.keys().cloned().collect();
+        tokens.sort_unstable();
+
+        let mut planted = Planter {
+            rng: &mut rng,
+            prices: &prices,
+            tokens: &tokens,
+            token: String::new(),
+            counterparties: &counterparties,
+            window: (start, end),
+            batch: None,
+            transfers: vec![],
+        };
+        for (index, scenario) in self.scenarios.iter().enumerate() {
+            planted
+                .plant(index, scenario)
+                .with_context(|| format!("Failed to plant {:?}", scenario.kind()))?;
+        }
+        let planted = planted.transfers;
+
+        let mut rows: Vec<(Transfer, Option<(usize, ScenarioKind)>)> = base
+            .into_iter()
+            .map(|t| (t, None))
+            .chain(planted.into_iter().map(|(t, s, k)| (t, Some((s, k)))))
+            .collect();
+        rows.sort_by_key(|(t, _)| t.ts);
+
+        let mut transfers = Vec::with_capacity(rows.len());
+        let mut labels = vec![];
+        for (index, (t, label)) in rows.into_iter().enumerate() {
+            if let Some((scenario, kind)) = label {
+                labels.push(ScenarioLabel {
+                    transfer: index,
+                    scenario,
+                    kind,
+                });
+            }
+            transfers.push(t);
+        }
+
+        Ok(LabeledTransfers {
+            seed,
+            transfers,
+            labels,
+        })
+    }
+}
+
+impl<G: TransferGenerator> TransferGenerator for ScenarioGenerator<G> {
+    fn generate(&self, count: usize) -> Result<Vec<Transfer>> {
+        Ok(self.generate_labeled(count)?.transfers)
+    }
+}
+
+struct Planter<'a, R> {
+    rng: &'a mut R,
+    /// Base `(ts, price)` points of every token, sorted by `ts`.
+    prices: &'a HashMap<String, Vec<(u64, Decimal)>>,
+    tokens: &'a [String],
+    /// Token of the scenario being planted.
+    token: String,
+    counterparties: &'a [String],
+    window: (u64, u64),
+    /// Hash and next log index of the transaction that pushed transfers go into; each transfer
+    /// gets its own transaction when unset.
+    batch: Option<(String, u32)>,
+    transfers: Vec<(Transfer, usize, ScenarioKind)>,
+}
+
+impl<R: Rng> Planter<'_, R> {
+    fn plant(&mut self, index: usize, scenario: &Scenario) -> Result<()> {
+        let kind = scenario.kind();
+        self.token = self
+            .tokens
+            .choose(self.rng)
+            .cloned()
+            .context("Base transfers have no token")?;
+        match *scenario {
+            Scenario::WashTrading {
+                ring_size,
+                rounds,
+                amount,
+            } => {
+                let ring: Vec<String> = (0..ring_size).map(|_| rand_address(self.rng)).collect();
+                // The first hop needs funds; it comes back with the last one.
+                self.fund(index, kind, &ring[0], amount)?;
+                let start = self.random_ts();
+                let hops = (0..rounds * ring_size).map(|hop| hop % ring_size);
+                for (ts, i) in (start..).zip(hops) {
+                    let to = &ring[(i + 1) % ring_size];
+                    self.push(index, kind, ts, &ring[i], to, amount);
+                }
+            }
+            Scenario::WhaleDump {
+                bursts,
+                transfers_per_burst,
+                amount,
+            } => {
+                let whale = rand_address(self.rng);
+                let total = total(amount, bursts * transfers_per_burst)?;
+                self.fund(index, kind, &whale, total)?;
+                for _ in 0..bursts {
+                    let start = self.random_ts();
+                    for ts in start..start + transfers_per_burst as u64 {
+                        let to = self.counterparty();
+                        self.push(index, kind, ts, &whale, &to, amount);
+                    }
+                }
+            }
+            Scenario::Airdrop { recipients, amount } => {
+                let distributor = rand_address(self.rng);
+                self.fund(index, kind, &distributor, total(amount, recipients)?)?;
+                let ts = self.random_ts();
+                let mut targets = self.counterparties.to_vec();
+                targets.shuffle(self.rng);
+                targets.truncate(recipients);
+                while targets.len() < recipients {
+                    targets.push(rand_address(self.rng));
+                }
+                self.batch = Some((rand_tx_hash(self.rng), 0));
+                for to in &targets {
+                    self.push(index, kind, ts, &distributor, to, amount);
+                }
+                self.batch = None;
+            }
+            Scenario::DustSpam { transfers, amount } => {
+                let spammer = rand_address(self.rng);
+                self.fund(index, kind, &spammer, total(amount, transfers)?)?;
+                for _ in 0..transfers {
+                    let ts = self.random_ts();
+                    let to = self.counterparty();
+                    self.push(index, kind, ts, &spammer, &to, amount);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn fund(&mut self, index: usize, kind: ScenarioKind, to: &str, amount: Decimal) -> Result<()> {
+        let ts = self
+            .window
+            .0
+            .checked_sub(1)
+            .context("Time window starts at 0")?;
+        self.push(index, kind, ts, MINT_ADDRESS, to, amount);
+        Ok(())
+    }
+
+    fn push(
+        &mut self,
+        index: usize,
+        kind: ScenarioKind,
+        ts: u64,
+        from: &str,
+        to: &str,
+        amount: Decimal,
+    ) {
+        let usd_price = self.price_at(ts);
+        let (tx_hash, log_index) = match &mut self.batch {
+            Some((hash, next)) => {
+                *next += 1;
+                (hash.clone(), *next - 1)
+            }
+            None => (rand_tx_hash(self.rng), 0),
+        };
+        self.transfers.push((
+            Transfer {
+                ts,
+                address_from: from.to_string(),
+                address_to: to.to_string(),
+                token: self.token.clone(),
+                amount,
+                usd_price,
+                block_number: block_at(ts),
+                tx_hash,
+                log_index,
+            },
+            index,
+            kind,
+        ));
+    }
+
+    fn random_ts(&mut self) -> u64 {
+        self.rng.gen_range(self.window.0..=self.window.1)
+    }
+
+    fn counterparty(&mut self) -> String {
+        self.counterparties
+            .choose(self.rng)
+            .cloned()
+            .unwrap_or_else(|| rand_address(self.rng))
+    }
+
+    /// Price of the latest base transfer of the token at or before `ts`, or of the first one.
+    fn price_at(&self, ts: u64) -> Decimal {
+        let points = &self.prices[&self.token];
+        let after = points.partition_point(|(point_ts, _)| *point_ts <= ts);
+        points[after.saturating_sub(1)].1
+    }
+}
+
+fn total(amount: Decimal, count: usize) -> Result<Decimal> {
+    amount
+        .checked_mul(Decimal::from(count as u64))
+        .context("Scenario volume is out of range")
+}
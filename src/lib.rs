@@ -1,6 +1,11 @@
 // NOTE: This is not a library, but just a demonstration example. Everything is available externally, so that it is convenient to take out tests separately
+pub mod cli;
 pub mod common;
+pub mod dump;
 pub mod generator;
+pub mod migrate;
 pub mod model;
+pub mod output;
+pub mod server;
 pub mod stats;
 pub mod storage;
@@ -1,56 +1,24 @@
-mod common;
-mod generator;
-mod model;
-mod stats;
-mod storage;
-use anyhow::{Context, Result};
-use common::ClickhouseClient;
-use generator::{DefaultTransferGenerator, TransferGenerator};
-use stats::{calculate_user_stats_clickhouse, calculate_user_stats_rust};
+use clap::Parser;
+use rust_challenge::cli::{exit, Cli};
+use rust_challenge::common::report_error;
+use std::io::Write;
+use std::process::ExitCode;
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    let storage = storage::ClickhouseStorage::new("http://localhost:8123");
-
-    let mut transfers = storage
-        .get_transfers()
-        .await
-        .context("Failed to get transfers from storage.")?;
-
-    if transfers.len() == 0 {
-        let mock_transfers = DefaultTransferGenerator::default()
-            .generate(10_000)
-            .context("Failed to generate mock transfers")?;
-
-        for transfer in mock_transfers.iter() {
-            storage
-                .insert_transfer(transfer)
-                .await
-                .context("Failed to insert transfer into storage")?;
+async fn main() -> ExitCode {
+    // Usage errors exit here with `exit::USAGE`.
+    let cli = Cli::parse();
+    let mut out = std::io::stdout().lock();
+    let code = match cli.run(&mut out).await {
+        Ok(code) => code,
+        Err(error) => {
+            report_error(&error);
+            exit::ERROR
         }
-
-        transfers = storage
-            .get_transfers()
-            .await
-            .context("Failed to get transfers from storage after inserting mock transfers")?;
-    }
-
-    let mut stats_clickhouse =
-        calculate_user_stats_clickhouse(&ClickhouseClient::new("http://localhost:8123"))
-            .await
-            .context("Failed to calculate user stats")?;
-    stats_clickhouse.sort_by(|a, b| a.address.cmp(&b.address));
-
-    for stat in stats_clickhouse.iter().take(10) {
-        println!("Clickhouse: \n{:?}", stat);
-    }
-
-    let mut stats_rust =
-        calculate_user_stats_rust(&transfers).context("Failed to calculate user stats")?;
-    stats_rust.sort_by(|a, b| a.address.cmp(&b.address));
-
-    for stat in stats_rust.iter().take(10) {
-        println!("{:?}", stat);
+    };
+    if let Err(error) = out.flush() {
+        eprintln!("Error: failed to write output: {error}");
+        return ExitCode::from(exit::ERROR);
     }
-    Ok(())
+    ExitCode::from(code)
 }
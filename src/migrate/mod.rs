@@ -0,0 +1,353 @@
+//! Schema migrations embedded in the binary.
+//!
+//! Migrations are the numbered files in `migrations/`, applied in order. `{table}` in a file
+//! stands for the configured transfers table, and a file may hold several statements separated by
+//! `;` (ClickHouse runs one per request, so `;` must not appear inside string literals). Lines
+//! starting with `--` are comments and are dropped before splitting.
+//!
+//! Applied versions are recorded in `<table>_migrations`. ClickHouse has no transactions, so a
+//! version is recorded only after all of its statements succeeded. A run that fails in between
+//! leaves the version pending, so a migration whose statements can't simply run again has an
+//! [`AppliedCheck`] that tells when they already took effect.
+//!
+//! [`run`] holds an advisory lock, a row in `<table>_migrations_lock`, so concurrent runs don't
+//! interleave: the earliest unreleased row of the last hour wins and the others fail. A lock left
+//! by a crashed run expires after that hour, or is removed with `TRUNCATE TABLE`.
+
+use crate::common::ClickhouseClient;
+use anyhow::{Context, Result};
+use clickhouse::Row;
+use serde::Deserialize;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Migration {
+    pub version: u32,
+    pub name: &'static str,
+    pub sql: &'static str,
+    pub applied_check: Option<AppliedCheck>,
+}
+
+/// Tells that a migration's statements took effect in a run that failed before recording it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AppliedCheck {
+    /// A query with a `count` column, positive once the statements took effect.
+    pub query: &'static str,
+    /// Statements run instead then, e.g. to drop what the failed run left behind.
+    pub cleanup: &'static str,
+}
+
+impl Migration {
+    /// The statements of this migration for `table`.
+    pub fn statements(&self, table: &str) -> Vec<String> {
+        split_statements(self.sql, table)
+    }
+}
+
+fn split_statements(sql: &str, table: &str) -> Vec<String> {
+    let sql: Vec<&str> = sql
+        .lines()
+        .filter(|line| !line.trim_start().starts_with("--"))
+        .collect();
+    sql.join("\n")
+        .split(';')
+        .map(str::trim)
+        .filter(|statement| !statement.is_empty())
+        .map(|statement| statement.replace("{table}", table))
+        .collect()
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "create_transfers",
+        sql: include_str!("../../migrations/001_create_transfers.sql"),
+        applied_check: None,
+    },
+    Migration {
+        version: 2,
+        name: "decimal_amounts",
+        sql: include_str!("../../migrations/002_decimal_amounts.sql"),
+        applied_check: None,
+    },
+    Migration {
+        version: 3,
+        name: "transfer_identity",
+        sql: include_str!("../../migrations/003_transfer_identity.sql"),
+        // Copying the swapped-in table again would replace its ids with legacy hashes; the
+        // table left behind is then the pre-migration one.
+        applied_check: Some(AppliedCheck {
+            query: "SELECT count() AS count FROM system.columns
+                WHERE database = currentDatabase() AND table = '{table}' AND name = 'tx_hash'",
+            cleanup: "DROP TABLE IF EXISTS {table}_dedup",
+        }),
+    },
+    Migration {
+        version: 4,
+        name: "token",
+        sql: include_str!("../../migrations/004_token.sql"),
+        applied_check: None,
+    },
+];
+
+/// All known migrations, ordered by version.
+pub fn migrations() -> &'static [Migration] {
+    MIGRATIONS
+}
+
+/// The version a fully migrated schema is at.
+pub fn latest_version() -> u32 {
+    MIGRATIONS.last().map_or(0, |m| m.version)
+}
+
+/// Where a database stands relative to [`migrations`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaStatus {
+    /// Recorded versions, ascending.
+    pub applied: Vec<u32>,
+    /// Known versions not applied yet, ascending.
+    pub pending: Vec<u32>,
+}
+
+impl SchemaStatus {
+    fn new(mut applied: Vec<u32>) -> Self {
+        applied.sort_unstable();
+        applied.dedup();
+        let pending = MIGRATIONS
+            .iter()
+            .map(|m| m.version)
+            .filter(|version| !applied.contains(version))
+            .collect();
+        Self { applied, pending }
+    }
+
+    pub fn current_version(&self) -> u32 {
+        self.applied.last().copied().unwrap_or(0)
+    }
+
+    /// Nothing is pending and nothing newer than this binary has been applied.
+    pub fn is_up_to_date(&self) -> bool {
+        self.pending.is_empty() && self.current_version() <= latest_version()
+    }
+}
+
+impl fmt::Display for SchemaStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "schema version {}, expected {}",
+            self.current_version(),
+            latest_version()
+        )?;
+        if !self.pending.is_empty() {
+            write!(f, ", pending {:?}", self.pending)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Row, Deserialize)]
+struct AppliedRow {
+    version: u32,
+}
+
+#[derive(Row, Deserialize)]
+struct CountRow {
+    count: u64,
+}
+
+#[derive(Row, Deserialize)]
+struct LockRow {
+    owner: String,
+    mine: bool,
+}
+
+fn migrations_table(client: &ClickhouseClient) -> String {
+    format!("{}_migrations", client.table)
+}
+
+fn lock_table(client: &ClickhouseClient) -> String {
+    format!("{}_migrations_lock", client.table)
+}
+
+/// Reads the recorded versions; a database that was never migrated has none.
+pub async fn status(client: &ClickhouseClient) -> Result<SchemaStatus> {
+    let table = migrations_table(client);
+    let exists = client
+        .client
+        .query("SELECT count() AS count FROM system.tables WHERE database = currentDatabase() AND name = ?")
+        .bind(&table)
+        .fetch_one::<CountRow>()
+        .await
+        .context("Failed to look up the migrations table")?;
+    if exists.count == 0 {
+        return Ok(SchemaStatus::new(vec![]));
+    }
+
+    let applied = client
+        .client
+        .query(&format!("SELECT version FROM {table}"))
+        .fetch_all::<AppliedRow>()
+        .await
+        .context("Failed to read applied migrations")?;
+    Ok(SchemaStatus::new(
+        applied.into_iter().map(|row| row.version).collect(),
+    ))
+}
+
+/// Fails unless the schema is exactly at [`latest_version`].
+pub async fn ensure_up_to_date(client: &ClickhouseClient) -> Result<()> {
+    let status = status(client).await?;
+    anyhow::ensure!(
+        status.is_up_to_date(),
+        "Table {} is out of date ({status}), run the migrations first",
+        client.table
+    );
+    Ok(())
+}
+
+/// Applies pending migrations in order and returns the versions it applied.
+pub async fn run(client: &ClickhouseClient) -> Result<Vec<u32>> {
+    let table = migrations_table(client);
+    client
+        .client
+        .query(&format!(
+            "CREATE TABLE IF NOT EXISTS {table} (
+                version UInt32,
+                name String,
+                applied_at DateTime DEFAULT now()
+            ) ENGINE = MergeTree()
+            ORDER BY version"
+        ))
+        .execute()
+        .await
+        .context("Failed to create the migrations table")?;
+
+    let owner = lock(client).await?;
+    let result = run_locked(client, &table).await;
+    let released = unlock(client, &owner).await;
+    let applied = result?;
+    released?;
+    Ok(applied)
+}
+
+async fn run_locked(client: &ClickhouseClient, table: &str) -> Result<Vec<u32>> {
+    let status = status(client).await?;
+    anyhow::ensure!(
+        status.current_version() <= latest_version(),
+        "Table {} was migrated by a newer version ({status})",
+        client.table
+    );
+
+    let mut applied = vec![];
+    for migration in MIGRATIONS
+        .iter()
+        .filter(|m| status.pending.contains(&m.version))
+    {
+        apply(client, table, migration).await.with_context(|| {
+            format!(
+                "Failed to apply migration {:03}_{}",
+                migration.version, migration.name
+            )
+        })?;
+        applied.push(migration.version);
+    }
+    Ok(applied)
+}
+
+/// Takes the advisory lock and returns its owner id, or fails if another run holds it.
+async fn lock(client: &ClickhouseClient) -> Result<String> {
+    let table = lock_table(client);
+    client
+        .client
+        .query(&format!(
+            "CREATE TABLE IF NOT EXISTS {table} (
+                owner String,
+                acquired_at DateTime64(6) DEFAULT now64(6),
+                released UInt8
+            ) ENGINE = MergeTree()
+            ORDER BY acquired_at"
+        ))
+        .execute()
+        .await
+        .context("Failed to create the migrations lock table")?;
+
+    let owner = format!("{}-{:016x}", std::process::id(), rand::random::<u64>());
+    client
+        .client
+        .query(&format!(
+            "INSERT INTO {table} (owner, released) VALUES (?, 0)"
+        ))
+        .bind(&owner)
+        .execute()
+        .await
+        .context("Failed to take the migrations lock")?;
+    let holder = client
+        .client
+        .query(&format!(
+            "SELECT owner, owner = ? AS mine FROM {table}
+            WHERE acquired_at > now64(6) - INTERVAL 1 HOUR
+            GROUP BY owner
+            HAVING max(released) = 0
+            ORDER BY min(acquired_at), owner
+            LIMIT 1"
+        ))
+        .bind(&owner)
+        .fetch_optional::<LockRow>()
+        .await
+        .context("Failed to read the migrations lock")?;
+    match holder {
+        Some(holder) if holder.mine => Ok(owner),
+        holder => {
+            unlock(client, &owner).await?;
+            let holder = holder.map_or_else(|| "unknown".to_string(), |row| row.owner);
+            anyhow::bail!(
+                "Migrations of {} are already running (lock owner {holder}); if that run \
+                crashed, its lock expires after an hour or can be removed with TRUNCATE TABLE {table}",
+                client.table
+            )
+        }
+    }
+}
+
+async fn unlock(client: &ClickhouseClient, owner: &str) -> Result<()> {
+    client
+        .client
+        .query(&format!(
+            "INSERT INTO {} (owner, released) VALUES (?, 1)",
+            lock_table(client)
+        ))
+        .bind(owner)
+        .execute()
+        .await
+        .context("Failed to release the migrations lock")
+}
+
+async fn apply(client: &ClickhouseClient, table: &str, migration: &Migration) -> Result<()> {
+    let mut sql = migration.sql;
+    if let Some(check) = migration.applied_check {
+        let applied = client
+            .client
+            .query(&check.query.replace("{table}", &client.table))
+            .fetch_one::<CountRow>()
+            .await
+            .context("Failed to check for an interrupted run")?;
+        if applied.count > 0 {
+            sql = check.cleanup;
+        }
+    }
+    for statement in split_statements(sql, &client.table) {
+        client.client.query(&statement).execute().await?;
+    }
+    client
+        .client
+        .query(&format!(
+            "INSERT INTO {table} (version, name) VALUES (?, ?)"
+        ))
+        .bind(migration.version)
+        .bind(migration.name)
+        .execute()
+        .await
+        .context("Failed to record the migration")?;
+    Ok(())
+}
@@ -0,0 +1,322 @@
+use ethnum::I256;
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Neg, Sub, SubAssign};
+use std::str::FromStr;
+
+/// Fixed-point number with 18 fractional digits, stored as an `i128` count of 10^-18 units.
+///
+/// Maps to ClickHouse `Decimal(38, 18)`: RowBinary carries the raw `i128`, human-readable formats
+/// (JSON, TOML, CSV) carry a decimal string such as `"42.5"`. This is enough to hold 18-decimal
+/// token amounts up to ~1.7e20 whole tokens exactly.
+///
+/// Addition and subtraction are exact and panic on overflow. Multiplication and division keep a
+/// 256-bit intermediate and truncate the result to 18 digits, as ClickHouse decimal arithmetic does.
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Decimal(i128);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseDecimalError(String);
+
+impl fmt::Display for ParseDecimalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid decimal: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParseDecimalError {}
+
+impl Decimal {
+    pub const SCALE: u32 = 18;
+    pub const ZERO: Decimal = Decimal(0);
+    pub const ONE: Decimal = Decimal(UNIT);
+    pub const MAX: Decimal = Decimal(i128::MAX);
+    pub const MIN: Decimal = Decimal(i128::MIN);
+
+    /// `mantissa * 10^-scale`, e.g. `Decimal::new(325, 2)` is `3.25`.
+    ///
+    /// # Panics
+    /// If `scale` is above 18.
+    pub const fn new(mantissa: i64, scale: u32) -> Self {
+        assert!(scale <= Self::SCALE, "scale must be at most 18");
+        Decimal(mantissa as i128 * 10i128.pow(Self::SCALE - scale))
+    }
+
+    /// From a raw count of 10^-18 units (token base units for an 18-decimal token).
+    pub const fn from_raw(raw: i128) -> Self {
+        Decimal(raw)
+    }
+
+    pub const fn raw(self) -> i128 {
+        self.0
+    }
+
+    /// The shortest decimal that round-trips to `value` (so `0.1` is exactly `0.1`), rounded to
+    /// 18 digits if longer. `None` for NaN, infinities and out-of-range values.
+    pub fn from_f64(value: f64) -> Option<Self> {
+        if !value.is_finite() {
+            return None;
+        }
+        let shortest = value.to_string();
+        match shortest.split_once('.') {
+            Some((_, frac)) if frac.len() > Self::SCALE as usize => {
+                format!("{value:.18}").parse().ok()
+            }
+            _ => shortest.parse().ok(),
+        }
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.to_string()
+            .parse()
+            .expect("decimal string is a valid f64")
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn is_positive(self) -> bool {
+        self.0 > 0
+    }
+
+    pub fn is_negative(self) -> bool {
+        self.0 < 0
+    }
+
+    pub fn abs(self) -> Self {
+        Decimal(self.0.checked_abs().expect("decimal overflow"))
+    }
+
+    pub fn checked_add(self, rhs: Self) -> Option<Self> {
+        self.0.checked_add(rhs.0).map(Decimal)
+    }
+
+    pub fn checked_sub(self, rhs: Self) -> Option<Self> {
+        self.0.checked_sub(rhs.0).map(Decimal)
+    }
+
+    pub fn checked_neg(self) -> Option<Self> {
+        self.0.checked_neg().map(Decimal)
+    }
+
+    pub fn checked_mul(self, rhs: Self) -> Option<Self> {
+        Self::from_wide(self.mul_wide(rhs) / I256::from(UNIT))
+    }
+
+    /// `None` when dividing by zero or on overflow.
+    pub fn checked_div(self, rhs: Self) -> Option<Self> {
+        if rhs.is_zero() {
+            return None;
+        }
+        Self::from_wide(I256::from(self.0) * I256::from(UNIT) / I256::from(rhs.0))
+    }
+
+    /// Exact product with 36 fractional digits, for sums of products such as notional value.
+    pub fn mul_wide(self, rhs: Self) -> I256 {
+        I256::from(self.0) * I256::from(rhs.0)
+    }
+
+    /// Divides a 36-digit wide value (see [`Decimal::mul_wide`]) by `rhs`, truncating to 18 digits.
+    pub fn div_wide(wide: I256, rhs: Self) -> Option<Self> {
+        if rhs.is_zero() {
+            return None;
+        }
+        Self::from_wide(wide / I256::from(rhs.0))
+    }
+
+    fn from_wide(wide: I256) -> Option<Self> {
+        i128::try_from(wide).ok().map(Decimal)
+    }
+}
+
+const UNIT: i128 = 1_000_000_000_000_000_000;
+
+impl From<i64> for Decimal {
+    fn from(value: i64) -> Self {
+        Decimal(value as i128 * UNIT)
+    }
+}
+
+impl From<u64> for Decimal {
+    fn from(value: u64) -> Self {
+        Decimal(value as i128 * UNIT)
+    }
+}
+
+impl From<i32> for Decimal {
+    fn from(value: i32) -> Self {
+        Decimal::from(value as i64)
+    }
+}
+
+impl FromStr for Decimal {
+    type Err = ParseDecimalError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseDecimalError(s.to_string());
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let (int_part, frac_part) = digits.split_once('.').unwrap_or((digits, ""));
+        if int_part.is_empty() && frac_part.is_empty()
+            || !int_part.bytes().all(|b| b.is_ascii_digit())
+            || !frac_part.bytes().all(|b| b.is_ascii_digit())
+            || frac_part.len() > Self::SCALE as usize
+        {
+            return Err(err());
+        }
+
+        let int_value: u128 = if int_part.is_empty() {
+            0
+        } else {
+            int_part.parse().map_err(|_| err())?
+        };
+        let frac_value: u128 = if frac_part.is_empty() {
+            0
+        } else {
+            let padded = format!("{frac_part:0<18}");
+            padded.parse().map_err(|_| err())?
+        };
+        // Unsigned, so the magnitude of `Decimal::MIN`, one more than that of `MAX`, fits.
+        let magnitude = int_value
+            .checked_mul(UNIT.unsigned_abs())
+            .and_then(|v| v.checked_add(frac_value))
+            .ok_or_else(err)?;
+        let raw = if negative {
+            0i128.checked_sub_unsigned(magnitude)
+        } else {
+            i128::try_from(magnitude).ok()
+        };
+        raw.map(Decimal).ok_or_else(err)
+    }
+}
+
+/// Shortest exact form (`42`, `0.5`, `-1.25`); with a precision (`{:.2}`) the value is rounded
+/// half away from zero to that many digits.
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unit = UNIT.unsigned_abs();
+        let mut magnitude = self.0.unsigned_abs();
+        if let Some(precision) = f.precision().filter(|p| *p < Self::SCALE as usize) {
+            let step = 10u128.pow(Self::SCALE - precision as u32);
+            magnitude = (magnitude + step / 2) / step * step;
+        }
+
+        let frac = format!("{:018}", magnitude % unit);
+        let frac = match f.precision() {
+            Some(precision) => format!("{:0<precision$}", &frac[..precision.min(18)]),
+            None => frac.trim_end_matches('0').to_string(),
+        };
+        let digits = if frac.is_empty() {
+            format!("{}", magnitude / unit)
+        } else {
+            format!("{}.{frac}", magnitude / unit)
+        };
+        f.pad_integral(self.0 >= 0 || magnitude == 0, "", &digits)
+    }
+}
+
+impl fmt::Debug for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl Add for Decimal {
+    type Output = Decimal;
+
+    fn add(self, rhs: Self) -> Self {
+        self.checked_add(rhs).expect("decimal overflow")
+    }
+}
+
+impl Sub for Decimal {
+    type Output = Decimal;
+
+    fn sub(self, rhs: Self) -> Self {
+        self.checked_sub(rhs).expect("decimal overflow")
+    }
+}
+
+impl Neg for Decimal {
+    type Output = Decimal;
+
+    fn neg(self) -> Self {
+        self.checked_neg().expect("decimal overflow")
+    }
+}
+
+impl AddAssign for Decimal {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl SubAssign for Decimal {
+    fn sub_assign(&mut self, rhs: Self) {
+        *self = *self - rhs;
+    }
+}
+
+impl Sum for Decimal {
+    fn sum<I: Iterator<Item = Decimal>>(iter: I) -> Self {
+        iter.fold(Decimal::ZERO, Add::add)
+    }
+}
+
+impl<'a> Sum<&'a Decimal> for Decimal {
+    fn sum<I: Iterator<Item = &'a Decimal>>(iter: I) -> Self {
+        iter.copied().sum()
+    }
+}
+
+impl Serialize for Decimal {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            serializer.serialize_i128(self.0)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Decimal {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_any(DecimalVisitor)
+        } else {
+            i128::deserialize(deserializer).map(Decimal)
+        }
+    }
+}
+
+/// Accepts decimal strings and, for hand-written config files, plain numbers.
+struct DecimalVisitor;
+
+impl Visitor<'_> for DecimalVisitor {
+    type Value = Decimal;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a decimal number or string with at most 18 fractional digits")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Decimal, E> {
+        v.parse().map_err(E::custom)
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Decimal, E> {
+        Ok(Decimal::from(v))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Decimal, E> {
+        Ok(Decimal::from(v))
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<Decimal, E> {
+        Decimal::from_f64(v).ok_or_else(|| E::custom(format!("{v} is not a valid decimal")))
+    }
+}
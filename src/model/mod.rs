@@ -1,20 +1,79 @@
 use clickhouse::Row;
 use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+
+mod decimal;
+
+pub use decimal::{Decimal, ParseDecimalError};
+
+/// Token of generated data and of rows stored before transfers carried a token.
+pub const DEFAULT_TOKEN: &str = "TKN";
 
 #[derive(Debug, Clone, Serialize, Deserialize, Row)]
 pub struct Transfer {
     pub ts: u64,
     pub address_from: String,
     pub address_to: String,
-    pub amount: f64,
-    pub usd_price: f64,
+    /// Token contract (or symbol) the amount is denominated in.
+    pub token: String,
+    pub amount: Decimal,
+    pub usd_price: Decimal,
+    pub block_number: u64,
+    /// Together with `log_index`, identifies the transfer (see [`Transfer::id`]).
+    pub tx_hash: String,
+    /// Position of the transfer's log within its transaction.
+    pub log_index: u32,
 }
 
+/// What makes a transfer unique: storing a transfer again with the same id replaces the first
+/// copy instead of adding another one.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TransferId {
+    pub tx_hash: String,
+    pub log_index: u32,
+}
+
+impl Transfer {
+    pub fn id(&self) -> TransferId {
+        TransferId {
+            tx_hash: self.tx_hash.clone(),
+            log_index: self.log_index,
+        }
+    }
+
+    /// Canonical order `(ts, address_from, address_to, amount, usd_price, tx_hash, log_index)`,
+    /// used for storage reads and for replaying balances, so both stats engines see transfers in
+    /// the same sequence. The id at the end makes it total.
+    pub fn cmp_order(&self, other: &Self) -> Ordering {
+        self.ts
+            .cmp(&other.ts)
+            .then_with(|| self.address_from.cmp(&other.address_from))
+            .then_with(|| self.address_to.cmp(&other.address_to))
+            .then_with(|| self.amount.cmp(&other.amount))
+            .then_with(|| self.usd_price.cmp(&other.usd_price))
+            .then_with(|| self.tx_hash.cmp(&other.tx_hash))
+            .then_with(|| self.log_index.cmp(&other.log_index))
+    }
+}
+
+/// Stats of one address in one token; amounts and balances are in that token's units.
 #[derive(Debug, Clone, Serialize, Deserialize, Row)]
 pub struct UserStats {
     pub address: String,
-    pub total_volume: f64,
-    pub avg_buy_price: f64,
-    pub avg_sell_price: f64,
-    pub max_balance: f64,
+    pub token: String,
+    pub total_volume: Decimal,
+    pub avg_buy_price: Decimal,
+    pub avg_sell_price: Decimal,
+    pub max_balance: Decimal,
+}
+
+/// USD totals of one address across all its tokens, each transfer valued at its `usd_price`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Row)]
+pub struct UsdStats {
+    pub address: String,
+    /// Number of distinct tokens the address traded.
+    pub tokens: u64,
+    pub total_volume_usd: Decimal,
+    pub buy_volume_usd: Decimal,
+    pub sell_volume_usd: Decimal,
 }
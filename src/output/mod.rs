@@ -0,0 +1,199 @@
+//! Sort order and writers for [`UserStats`] in machine-readable and terminal formats.
+//!
+//! Every format has the columns of [`USER_STATS_COLUMNS`], in that order, and renders values
+//! the same way: decimals as plain strings (`1234.5`, never exponent notation), rounded to a
+//! fixed number of digits if a precision is given. JSON keeps them as strings so no digits are
+//! lost to floating point on the reading side.
+
+use crate::dump::csv;
+use crate::model::{Decimal, UserStats};
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::fmt;
+use std::io::Write;
+
+/// Column order of every format.
+pub const USER_STATS_COLUMNS: [&str; 6] = [
+    "address",
+    "token",
+    "total_volume",
+    "avg_buy_price",
+    "avg_sell_price",
+    "max_balance",
+];
+
+/// Columns holding text rather than numbers; the table aligns them left.
+const TEXT_COLUMNS: usize = 2;
+
+/// Sort order of stats; `max-balance` on the command line, `max_balance` in the HTTP API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortKey {
+    #[default]
+    Address,
+    Volume,
+    BuyPrice,
+    SellPrice,
+    MaxBalance,
+}
+
+/// Sorts by `key`, ties broken by address, then token; `desc` reverses the whole order.
+pub fn sort_stats(stats: &mut [UserStats], key: SortKey, desc: bool) {
+    stats.sort_by(|a, b| {
+        let order = match key {
+            SortKey::Address => std::cmp::Ordering::Equal,
+            SortKey::Volume => a.total_volume.cmp(&b.total_volume),
+            SortKey::BuyPrice => a.avg_buy_price.cmp(&b.avg_buy_price),
+            SortKey::SellPrice => a.avg_sell_price.cmp(&b.avg_sell_price),
+            SortKey::MaxBalance => a.max_balance.cmp(&b.max_balance),
+        }
+        .then_with(|| (&a.address, &a.token).cmp(&(&b.address, &b.token)));
+        if desc {
+            order.reverse()
+        } else {
+            order
+        }
+    });
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// One JSON array.
+    Json,
+    /// One JSON object per line.
+    #[default]
+    Ndjson,
+    /// RFC 4180 CSV with a header row.
+    Csv,
+    /// Aligned columns for reading in a terminal.
+    Table,
+}
+
+impl OutputFormat {
+    pub const ALL: [OutputFormat; 4] = [
+        OutputFormat::Json,
+        OutputFormat::Ndjson,
+        OutputFormat::Csv,
+        OutputFormat::Table,
+    ];
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            OutputFormat::Json => "json",
+            OutputFormat::Ndjson => "ndjson",
+            OutputFormat::Csv => "csv",
+            OutputFormat::Table => "table",
+        })
+    }
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        OutputFormat::ALL
+            .into_iter()
+            .find(|format| format.to_string().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                anyhow::anyhow!("Unknown format {s:?}, expected json, ndjson, csv or table")
+            })
+    }
+}
+
+/// Writes `stats` to `out` in `format`. With a `precision`, every decimal is rounded to that
+/// many fractional digits and padded with zeros; otherwise it is written exactly.
+pub fn write_user_stats(
+    out: &mut impl Write,
+    stats: &[UserStats],
+    format: OutputFormat,
+    precision: Option<usize>,
+) -> Result<()> {
+    let rows = stats.iter().map(|stat| cells(stat, precision));
+    match format {
+        OutputFormat::Json => {
+            write!(out, "[")?;
+            for (i, row) in rows.enumerate() {
+                write!(out, "{}\n  ", if i == 0 { "" } else { "," })?;
+                write_json_object(out, &row)?;
+            }
+            writeln!(out, "{}]", if stats.is_empty() { "" } else { "\n" })?;
+        }
+        OutputFormat::Ndjson => {
+            for row in rows {
+                write_json_object(out, &row)?;
+                writeln!(out)?;
+            }
+        }
+        OutputFormat::Csv => {
+            writeln!(out, "{}", USER_STATS_COLUMNS.join(","))?;
+            for row in rows {
+                let fields: Vec<String> = row.iter().map(|cell| csv::quote(cell)).collect();
+                writeln!(out, "{}", fields.join(","))?;
+            }
+        }
+        OutputFormat::Table => write_table(out, rows.collect())?,
+    }
+    Ok(())
+}
+
+fn cells(stat: &UserStats, precision: Option<usize>) -> [String; 6] {
+    let number = |value: &Decimal| match precision {
+        Some(precision) => format!("{value:.precision$}"),
+        None => value.to_string(),
+    };
+    [
+        stat.address.clone(),
+        stat.token.clone(),
+        number(&stat.total_volume),
+        number(&stat.avg_buy_price),
+        number(&stat.avg_sell_price),
+        number(&stat.max_balance),
+    ]
+}
+
+fn write_json_object(out: &mut impl Write, row: &[String; 6]) -> Result<()> {
+    write!(out, "{{")?;
+    for (i, (column, cell)) in USER_STATS_COLUMNS.iter().zip(row).enumerate() {
+        if i > 0 {
+            write!(out, ",")?;
+        }
+        write!(out, "\"{column}\":{}", serde_json::to_string(cell)?)?;
+    }
+    write!(out, "}}")?;
+    Ok(())
+}
+
+fn write_table(out: &mut impl Write, rows: Vec<[String; 6]>) -> Result<()> {
+    let mut widths = USER_STATS_COLUMNS.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let line = |cells: [&str; 6]| {
+        let padded: Vec<String> = cells
+            .iter()
+            .zip(widths)
+            .enumerate()
+            .map(|(i, (cell, width))| {
+                if i < TEXT_COLUMNS {
+                    format!("{cell:<width$}")
+                } else {
+                    format!("{cell:>width$}")
+                }
+            })
+            .collect();
+        padded.join("  ").trim_end().to_string()
+    };
+    writeln!(out, "{}", line(USER_STATS_COLUMNS))?;
+    let rule = widths.map(|width| "-".repeat(width));
+    writeln!(out, "{}", line(rule.each_ref().map(String::as_str)))?;
+    for row in &rows {
+        writeln!(out, "{}", line(row.each_ref().map(String::as_str)))?;
+    }
+    Ok(())
+}
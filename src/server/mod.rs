@@ -0,0 +1,257 @@
+//! HTTP API over a [`TransferStore`], run by the `serve` command:
+//!
+//! * `GET /stats/{address}` — [`UserStats`] of the address, one per token (`?token=` for one).
+//! * `GET /stats` — stats of every address, with the `stats` command's filters:
+//!   `?token=`, `?sort=address|volume|buy_price|sell_price|max_balance`, `?desc=true`, `?limit=`.
+//! * `GET /balance-history/{address}` — balance after each transfer, per token (`?token=`).
+//! * `POST /transfers` — stores the transfers of an NDJSON body (see [`dump`](crate::dump)),
+//!   answering `201` with `{"inserted": n}`.
+//!
+//! Responses are JSON with decimals as exact strings, like [`OutputFormat::Json`]. Errors are
+//! `{"error": "..."}` with `404` for an unknown address or route, `400` for invalid parameters or
+//! an invalid body (nothing is stored then), `422` when stats don't fit a decimal (see
+//! [`OutOfRangeError`]), and `500` when the store fails; the cause of a `500` is only printed to
+//! stderr.
+//!
+//! Stats are computed by the rust engine over the transfers the request needs; an address' stats
+//! only depend on the transfers it takes part in.
+//!
+//! [`OutputFormat::Json`]: crate::output::OutputFormat::Json
+
+use crate::common::report_error;
+use crate::dump::{TransferFormat, TransferReader};
+use crate::model::{Decimal, Transfer, UserStats};
+use crate::output::{sort_stats, SortKey};
+use crate::stats::{
+    calculate_balance_history_of, calculate_user_stats_stream_with_policy, NegativeBalancePolicy,
+    OutOfRangeError, StatsAggregator,
+};
+use crate::storage::{TransferQuery, TransferStore};
+use anyhow::{Context, Result};
+use axum::body::Bytes;
+use axum::extract::rejection::QueryRejection;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use futures::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+struct Api<S> {
+    store: S,
+    policy: NegativeBalancePolicy,
+}
+
+/// Routes of the API, see the [module docs](self).
+pub fn router<S>(store: S, policy: NegativeBalancePolicy) -> Router
+where
+    S: TransferStore + 'static,
+{
+    Router::new()
+        .route("/stats", get(all_stats::<S>))
+        .route("/stats/{address}", get(address_stats::<S>))
+        .route("/balance-history/{address}", get(balance_history::<S>))
+        .route("/transfers", post(insert_transfers::<S>))
+        .fallback(|| async { ApiError::new(StatusCode::NOT_FOUND, "Not found") })
+        .with_state(Arc::new(Api { store, policy }))
+}
+
+/// Serves the API on `listener` until the process stops.
+pub async fn serve<S>(listener: TcpListener, store: S, policy: NegativeBalancePolicy) -> Result<()>
+where
+    S: TransferStore + 'static,
+{
+    axum::serve(listener, router(store, policy))
+        .await
+        .context("HTTP server failed")
+}
+
+/// An error response: `{"error": message}` with `status`.
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub message: String,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = serde_json::json!({ "error": self.message });
+        (self.status, Json(body)).into_response()
+    }
+}
+
+/// Stats out of the decimal range are `422` with the value named. Other errors are `500`, and
+/// their details, which may name hosts, tables or queries, only go to stderr.
+impl From<anyhow::Error> for ApiError {
+    fn from(error: anyhow::Error) -> Self {
+        if error.chain().any(|cause| cause.is::<OutOfRangeError>()) {
+            return Self::new(StatusCode::UNPROCESSABLE_ENTITY, format!("{error:#}"));
+        }
+        report_error(&error.context("Request failed"));
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(rejection: QueryRejection) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, rejection.body_text())
+    }
+}
+
+type ApiResult<T> = Result<T, ApiError>;
+
+#[derive(Debug, Deserialize)]
+struct TokenParams {
+    token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StatsParams {
+    token: Option<String>,
+    #[serde(default)]
+    sort: SortKey,
+    #[serde(default)]
+    desc: bool,
+    limit: Option<usize>,
+}
+
+async fn all_stats<S: TransferStore>(
+    State(api): State<Arc<Api<S>>>,
+    params: Result<Query<StatsParams>, QueryRejection>,
+) -> ApiResult<Json<Vec<UserStats>>> {
+    let Query(params) = params?;
+    let mut query = TransferQuery::new();
+    if let Some(token) = &params.token {
+        query = query.token(token);
+    }
+    let transfers = api
+        .store
+        .stream_transfers(&query)
+        .map_err(|e| e.context("Failed to read transfers from storage"));
+    let mut stats = calculate_user_stats_stream_with_policy(transfers, api.policy).await?;
+    sort_stats(&mut stats, params.sort, params.desc);
+    stats.truncate(params.limit.unwrap_or(usize::MAX));
+    Ok(Json(stats))
+}
+
+async fn address_stats<S: TransferStore>(
+    State(api): State<Arc<Api<S>>>,
+    Path(address): Path<String>,
+    params: Result<Query<TokenParams>, QueryRejection>,
+) -> ApiResult<Json<Vec<UserStats>>> {
+    let Query(params) = params?;
+    let query = address_query(&address, params.token.as_deref());
+    // Only the address' own stats: the other side of each transfer is only partly read.
+    let (aggregator, tokens) = api
+        .store
+        .stream_transfers(&query)
+        .try_fold(
+            (StatsAggregator::with_policy(api.policy), BTreeSet::new()),
+            |(mut aggregator, mut tokens), t| async move {
+                aggregator.push(&t);
+                tokens.insert(t.token);
+                Ok((aggregator, tokens))
+            },
+        )
+        .await
+        .context("Failed to read transfers from storage")?;
+    let stats = tokens
+        .iter()
+        .filter_map(|token| aggregator.get(&address, token).transpose())
+        .collect::<Result<Vec<_>>>()?;
+    if stats.is_empty() {
+        return Err(unknown_address(&address));
+    }
+    Ok(Json(stats))
+}
+
+#[derive(Debug, Serialize)]
+struct BalancePoint {
+    ts: u64,
+    balance: Decimal,
+}
+
+#[derive(Debug, Serialize)]
+struct BalanceHistoryResponse {
+    address: String,
+    /// Token -> balance after each transfer of the address in that token, in replay order.
+    tokens: BTreeMap<String, Vec<BalancePoint>>,
+}
+
+async fn balance_history<S: TransferStore>(
+    State(api): State<Arc<Api<S>>>,
+    Path(address): Path<String>,
+    params: Result<Query<TokenParams>, QueryRejection>,
+) -> ApiResult<Json<BalanceHistoryResponse>> {
+    let Query(params) = params?;
+    let query = address_query(&address, params.token.as_deref());
+    let transfers = api
+        .store
+        .query_transfers(&query)
+        .await
+        .context("Failed to read transfers from storage")?;
+    let addresses = HashSet::from([address.clone()]);
+    let tokens = calculate_balance_history_of(&transfers, &addresses, api.policy)?
+        .remove(&address)
+        .ok_or_else(|| unknown_address(&address))?;
+    let tokens = tokens
+        .into_iter()
+        .map(|(token, history)| {
+            let points = history
+                .into_iter()
+                .map(|(ts, balance)| BalancePoint { ts, balance })
+                .collect();
+            (token, points)
+        })
+        .collect();
+    Ok(Json(BalanceHistoryResponse { address, tokens }))
+}
+
+async fn insert_transfers<S: TransferStore>(
+    State(api): State<Arc<Api<S>>>,
+    body: Bytes,
+) -> ApiResult<(StatusCode, Json<serde_json::Value>)> {
+    let bad_request = |message: String| ApiError::new(StatusCode::BAD_REQUEST, message);
+    let transfers = TransferReader::new(body.as_ref(), TransferFormat::Ndjson)
+        .map_err(|e| bad_request(e.to_string()))?
+        .collect::<Result<Vec<Transfer>, _>>()
+        .map_err(|e| bad_request(e.to_string()))?;
+    if transfers.is_empty() {
+        return Err(bad_request("No transfers in the request body".to_string()));
+    }
+    let inserted = api
+        .store
+        .insert_transfers(transfers)
+        .await
+        .context("Failed to store transfers")?;
+    let body = serde_json::json!({ "inserted": inserted });
+    Ok((StatusCode::CREATED, Json(body)))
+}
+
+fn address_query(address: &str, token: Option<&str>) -> TransferQuery {
+    let query = TransferQuery::new().address(address);
+    match token {
+        Some(token) => query.token(token),
+        None => query,
+    }
+}
+
+fn unknown_address(address: &str) -> ApiError {
+    ApiError::new(
+        StatusCode::NOT_FOUND,
+        format!("No transfers of address {address}"),
+    )
+}
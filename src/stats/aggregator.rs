@@ -0,0 +1,206 @@
+use super::balance::{BalanceTracker, NegativeBalancePolicy};
+use super::OutOfRangeError;
+use crate::model::{Decimal, Transfer, UsdStats, UserStats};
+use anyhow::{Context, Result};
+use ethnum::I256;
+use std::collections::HashMap;
+
+/// Running totals of one address in one token, enough to produce [`UserStats`] without keeping
+/// transfers around.
+///
+/// Sums are kept in 256 bits: amounts as raw 10^-18 units, notionals as exact `amount * usd_price`
+/// products (see [`Decimal::mul_wide`]). Only the finished stats have to fit a [`Decimal`], so
+/// weighted averages and USD totals are rounded once, and totals beyond the decimal range are
+/// reported as errors when stats are taken instead of overflowing while transfers are pushed.
+#[derive(Debug, Default, Clone)]
+struct AddressState {
+    total_volume: I256,
+    volume_notional: I256,
+    buy_notional: I256,
+    buy_amount: I256,
+    sell_notional: I256,
+    sell_amount: I256,
+    balance: BalanceTracker,
+}
+
+impl AddressState {
+    fn to_stats(
+        &self,
+        address: &str,
+        token: &str,
+        policy: NegativeBalancePolicy,
+    ) -> Result<UserStats> {
+        let context = |field: &str| format!("{field} of {address} in {token}");
+        Ok(UserStats {
+            address: address.to_string(),
+            token: token.to_string(),
+            total_volume: narrow(self.total_volume).with_context(|| context("total_volume"))?,
+            avg_buy_price: ratio(self.buy_notional, self.buy_amount)
+                .with_context(|| context("avg_buy_price"))?,
+            avg_sell_price: ratio(self.sell_notional, self.sell_amount)
+                .with_context(|| context("avg_sell_price"))?,
+            max_balance: narrow(self.balance.max_balance(policy))
+                .with_context(|| context("max_balance"))?,
+        })
+    }
+}
+
+/// A raw 18-digit sum as a [`Decimal`].
+pub(super) fn narrow(raw: I256) -> Result<Decimal> {
+    let raw = i128::try_from(raw).map_err(|_| OutOfRangeError)?;
+    Ok(Decimal::from_raw(raw))
+}
+
+fn usd(notional: I256) -> Result<Decimal> {
+    narrow(notional / I256::from(Decimal::ONE.raw()))
+}
+
+fn ratio(notional: I256, sum_amt: I256) -> Result<Decimal> {
+    if sum_amt > 0 {
+        narrow(notional / sum_amt)
+    } else {
+        Ok(Decimal::ZERO)
+    }
+}
+
+/// Per-`(address, token)` stats updated one transfer at a time; [`super::calculate_user_stats_rust`]
+/// is a single pass of this over a sorted slice.
+///
+/// Volume and averages don't depend on push order. Max balance replays balances in push order,
+/// so pushes are expected in [`Transfer::cmp_order`] order, as storage reads return them.
+/// Memory grows with the number of address and token pairs, not transfers.
+#[derive(Debug, Default, Clone)]
+pub struct StatsAggregator {
+    /// Token, then address.
+    states: HashMap<String, HashMap<String, AddressState>>,
+    policy: NegativeBalancePolicy,
+}
+
+impl StatsAggregator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_policy(policy: NegativeBalancePolicy) -> Self {
+        Self {
+            states: HashMap::new(),
+            policy,
+        }
+    }
+
+    /// Applies one transfer; transfers that don't count (see the [module docs](super)) are skipped.
+    pub fn push(&mut self, t: &Transfer) {
+        if !super::is_counted(t) {
+            return;
+        }
+
+        let notional = t.usd_price.mul_wide(t.amount);
+        let amount = I256::from(t.amount.raw());
+
+        if t.address_from == t.address_to {
+            let state = self.state_mut(&t.token, &t.address_from);
+            state.total_volume += amount;
+            state.volume_notional += notional;
+            state.buy_notional += notional;
+            state.buy_amount += amount;
+            state.sell_notional += notional;
+            state.sell_amount += amount;
+            return;
+        }
+
+        let policy = self.policy;
+
+        let from = self.state_mut(&t.token, &t.address_from);
+        from.total_volume += amount;
+        from.volume_notional += notional;
+        from.sell_notional += notional;
+        from.sell_amount += amount;
+        from.balance.apply(-amount, policy);
+
+        let to = self.state_mut(&t.token, &t.address_to);
+        to.total_volume += amount;
+        to.volume_notional += notional;
+        to.buy_notional += notional;
+        to.buy_amount += amount;
+        to.balance.apply(amount, policy);
+    }
+
+    // Looks up before inserting so known keys don't cost an allocation per transfer.
+    fn state_mut(&mut self, token: &str, address: &str) -> &mut AddressState {
+        if !self.states.contains_key(token) {
+            self.states.insert(token.to_string(), HashMap::new());
+        }
+        let addresses = self.states.get_mut(token).expect("token was just inserted");
+        if !addresses.contains_key(address) {
+            addresses.insert(address.to_string(), AddressState::default());
+        }
+        addresses.get_mut(address).expect("state was just inserted")
+    }
+
+    /// Fails if a value of the stats is out of the [`Decimal`] range.
+    pub fn get(&self, address: &str, token: &str) -> Result<Option<UserStats>> {
+        self.states
+            .get(token)
+            .and_then(|addresses| addresses.get(address))
+            .map(|state| state.to_stats(address, token, self.policy))
+            .transpose()
+    }
+
+    /// Current stats of every address and token pair seen so far, in no particular order.
+    /// Fails if a value is out of the [`Decimal`] range.
+    pub fn snapshot(&self) -> Result<Vec<UserStats>> {
+        self.states
+            .iter()
+            .flat_map(|(token, addresses)| {
+                addresses
+                    .iter()
+                    .map(move |(address, state)| state.to_stats(address, token, self.policy))
+            })
+            .collect()
+    }
+
+    /// Current USD totals of every address seen so far, summed over its tokens, in no particular
+    /// order. Fails if a total is out of the [`Decimal`] range.
+    pub fn usd_snapshot(&self) -> Result<Vec<UsdStats>> {
+        let mut totals: HashMap<&str, (u64, I256, I256, I256)> = HashMap::new();
+        for addresses in self.states.values() {
+            for (address, state) in addresses {
+                let total = totals.entry(address).or_default();
+                total.0 += 1;
+                total.1 += state.volume_notional;
+                total.2 += state.buy_notional;
+                total.3 += state.sell_notional;
+            }
+        }
+        totals
+            .into_iter()
+            .map(|(address, (tokens, volume, buy, sell))| {
+                let context = |field: &str| format!("{field} of {address}");
+                Ok(UsdStats {
+                    address: address.to_string(),
+                    tokens,
+                    total_volume_usd: usd(volume).with_context(|| context("total_volume_usd"))?,
+                    buy_volume_usd: usd(buy).with_context(|| context("buy_volume_usd"))?,
+                    sell_volume_usd: usd(sell).with_context(|| context("sell_volume_usd"))?,
+                })
+            })
+            .collect()
+    }
+
+    /// Number of distinct address and token pairs seen so far.
+    pub fn len(&self) -> usize {
+        self.states.values().map(HashMap::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty()
+    }
+}
+
+impl<'a> Extend<&'a Transfer> for StatsAggregator {
+    fn extend<I: IntoIterator<Item = &'a Transfer>>(&mut self, transfers: I) {
+        for t in transfers {
+            self.push(t);
+        }
+    }
+}
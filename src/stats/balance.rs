@@ -0,0 +1,149 @@
+use super::aggregator::narrow;
+use super::is_counted;
+use crate::model::{Decimal, Transfer};
+use anyhow::{Context, Result};
+use ethnum::I256;
+use std::collections::{HashMap, HashSet};
+
+/// What to do when an address sends more than it has received so far, which happens whenever
+/// the history doesn't start at the token's genesis.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NegativeBalancePolicy {
+    /// The balance simply goes below zero.
+    #[default]
+    Allow,
+    /// The balance stops at zero; the uncovered part of the transfer is dropped.
+    Clamp,
+    /// The address is assumed to hold the smallest opening balance that keeps it at or above zero
+    /// for the whole history, and every balance is shifted by it.
+    PreFunded,
+}
+
+/// Running balance of one address, starting at 0, in raw 10^-18 units. Kept in 256 bits so
+/// that only the balances reported have to fit a [`Decimal`].
+#[derive(Debug, Default, Clone)]
+pub(crate) struct BalanceTracker {
+    running: I256,
+    min: I256,
+    max: I256,
+}
+
+impl BalanceTracker {
+    pub(crate) fn apply(&mut self, delta: I256, policy: NegativeBalancePolicy) {
+        self.running += delta;
+        if policy == NegativeBalancePolicy::Clamp {
+            self.running = self.running.max(I256::ZERO);
+        }
+        self.min = self.min.min(self.running);
+        self.max = self.max.max(self.running);
+    }
+
+    pub(crate) fn current(&self) -> I256 {
+        self.running
+    }
+
+    /// Only known once the whole history has been applied.
+    pub(crate) fn opening(&self, policy: NegativeBalancePolicy) -> I256 {
+        match policy {
+            NegativeBalancePolicy::PreFunded => -self.min,
+            NegativeBalancePolicy::Allow | NegativeBalancePolicy::Clamp => I256::ZERO,
+        }
+    }
+
+    pub(crate) fn max_balance(&self, policy: NegativeBalancePolicy) -> I256 {
+        self.max + self.opening(policy)
+    }
+}
+
+/// Balance history of one address in one token, as `(ts, balance)` after each transfer.
+pub type BalanceHistory = Vec<(u64, Decimal)>;
+
+/// Balance of every address in every token it holds, after each transfer it takes part in:
+/// `address -> token -> history`.
+///
+/// Transfers are replayed in [`Transfer::cmp_order`] order, so `ts` ties are broken by the rest of
+/// the row and the result doesn't depend on the input order. Transfers that aren't counted (see
+/// the [module docs](super)) are skipped and a self-transfer adds a single unchanged entry.
+///
+/// Fails if a balance is out of the [`Decimal`] range.
+pub fn calculate_balance_history(
+    transfers: &[Transfer],
+    policy: NegativeBalancePolicy,
+) -> Result<HashMap<String, HashMap<String, BalanceHistory>>> {
+    balance_history(transfers, policy, |_| true)
+}
+
+/// [`calculate_balance_history`] of `addresses` only, so `transfers` only have to hold every
+/// transfer of those; the other side of each transfer is left out.
+pub fn calculate_balance_history_of(
+    transfers: &[Transfer],
+    addresses: &HashSet<String>,
+    policy: NegativeBalancePolicy,
+) -> Result<HashMap<String, HashMap<String, BalanceHistory>>> {
+    balance_history(transfers, policy, |address| addresses.contains(address))
+}
+
+fn balance_history(
+    transfers: &[Transfer],
+    policy: NegativeBalancePolicy,
+    tracked: impl Fn(&str) -> bool,
+) -> Result<HashMap<String, HashMap<String, BalanceHistory>>> {
+    let mut ordered: Vec<&Transfer> = transfers.iter().filter(|t| is_counted(t)).collect();
+    ordered.sort_by(|a, b| a.cmp_order(b));
+
+    let mut states: HashMap<String, HashMap<String, (BalanceTracker, RawHistory)>> = HashMap::new();
+    for t in ordered {
+        let amount = I256::from(t.amount.raw());
+        if t.address_from != t.address_to && tracked(&t.address_from) {
+            let (balance, history) = state(&mut states, &t.address_from, &t.token);
+            balance.apply(-amount, policy);
+            history.push((t.ts, balance.current()));
+        }
+
+        if tracked(&t.address_to) {
+            let (balance, history) = state(&mut states, &t.address_to, &t.token);
+            if t.address_from != t.address_to {
+                balance.apply(amount, policy);
+            }
+            history.push((t.ts, balance.current()));
+        }
+    }
+
+    states
+        .into_iter()
+        .map(|(address, tokens)| {
+            let tokens = tokens
+                .into_iter()
+                .map(|(token, (balance, history))| {
+                    let opening = balance.opening(policy);
+                    let history = history
+                        .into_iter()
+                        .map(|(ts, value)| {
+                            let balance = narrow(value + opening).with_context(|| {
+                                format!("Balance of {address} in {token} at ts {ts}")
+                            })?;
+                            Ok((ts, balance))
+                        })
+                        .collect::<Result<_>>()?;
+                    Ok((token, history))
+                })
+                .collect::<Result<_>>()?;
+            Ok((address, tokens))
+        })
+        .collect()
+}
+
+/// [`BalanceHistory`] before the opening balance is known, in raw units.
+type RawHistory = Vec<(u64, I256)>;
+
+fn state<'a>(
+    states: &'a mut HashMap<String, HashMap<String, (BalanceTracker, RawHistory)>>,
+    address: &str,
+    token: &str,
+) -> &'a mut (BalanceTracker, RawHistory) {
+    states
+        .entry(address.to_string())
+        .or_default()
+        .entry(token.to_string())
+        .or_default()
+}
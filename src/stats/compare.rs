@@ -0,0 +1,165 @@
+use crate::model::{Decimal, UserStats};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// Allowed difference between two values: they match if they are within `absolute` of each
+/// other, or within `relative` of the larger magnitude.
+#[derive(Debug, Clone, Copy)]
+pub struct Tolerance {
+    pub absolute: Decimal,
+    pub relative: f64,
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Self {
+            absolute: Decimal::new(1, 9),
+            relative: 1e-9,
+        }
+    }
+}
+
+impl Tolerance {
+    pub fn exact() -> Self {
+        Self {
+            absolute: Decimal::ZERO,
+            relative: 0.0,
+        }
+    }
+
+    pub fn accepts(&self, left: Decimal, right: Decimal) -> bool {
+        if left == right {
+            return true;
+        }
+        let Some(diff) = left.checked_sub(right).map(Decimal::abs) else {
+            return false;
+        };
+        diff <= self.absolute
+            || diff.to_f64() <= self.relative * left.abs().max(right.abs()).to_f64()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mismatch {
+    MissingLeft {
+        address: String,
+        token: String,
+    },
+    MissingRight {
+        address: String,
+        token: String,
+    },
+    Field {
+        address: String,
+        token: String,
+        field: &'static str,
+        left: Decimal,
+        right: Decimal,
+    },
+}
+
+impl Mismatch {
+    pub fn address(&self) -> &str {
+        match self {
+            Mismatch::MissingLeft { address, .. }
+            | Mismatch::MissingRight { address, .. }
+            | Mismatch::Field { address, .. } => address,
+        }
+    }
+
+    pub fn token(&self) -> &str {
+        match self {
+            Mismatch::MissingLeft { token, .. }
+            | Mismatch::MissingRight { token, .. }
+            | Mismatch::Field { token, .. } => token,
+        }
+    }
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mismatch::MissingLeft { address, token } => {
+                write!(f, "{address} ({token}): missing on the left")
+            }
+            Mismatch::MissingRight { address, token } => {
+                write!(f, "{address} ({token}): missing on the right")
+            }
+            Mismatch::Field {
+                address,
+                token,
+                field,
+                left,
+                right,
+            } => write!(f, "{address} ({token}): {field} differs, {left} vs {right}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ParityReport {
+    /// Address and token pairs present on both sides.
+    pub compared: usize,
+    /// Sorted by address, then token.
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl ParityReport {
+    pub fn is_ok(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+/// Diffs two stats outputs per address and token, e.g. the Rust and the ClickHouse engine.
+pub fn compare(left: &[UserStats], right: &[UserStats], tolerance: Tolerance) -> ParityReport {
+    let key = |s: &UserStats| (s.address.clone(), s.token.clone());
+    let right_by_key: HashMap<(String, String), &UserStats> =
+        right.iter().map(|s| (key(s), s)).collect();
+    let mut report = ParityReport::default();
+
+    for l in left {
+        let Some(r) = right_by_key.get(&key(l)) else {
+            report.mismatches.push(Mismatch::MissingRight {
+                address: l.address.clone(),
+                token: l.token.clone(),
+            });
+            continue;
+        };
+        report.compared += 1;
+
+        let fields = [
+            ("total_volume", l.total_volume, r.total_volume),
+            ("avg_buy_price", l.avg_buy_price, r.avg_buy_price),
+            ("avg_sell_price", l.avg_sell_price, r.avg_sell_price),
+            ("max_balance", l.max_balance, r.max_balance),
+        ];
+        for (field, left, right) in fields {
+            if !tolerance.accepts(left, right) {
+                report.mismatches.push(Mismatch::Field {
+                    address: l.address.clone(),
+                    token: l.token.clone(),
+                    field,
+                    left,
+                    right,
+                });
+            }
+        }
+    }
+
+    let left_keys: HashSet<(String, String)> = left.iter().map(key).collect();
+    for r in right {
+        if !left_keys.contains(&key(r)) {
+            report.mismatches.push(Mismatch::MissingLeft {
+                address: r.address.clone(),
+                token: r.token.clone(),
+            });
+        }
+    }
+
+    report.mismatches.sort_by(|a, b| {
+        a.address()
+            .cmp(b.address())
+            .then_with(|| a.token().cmp(b.token()))
+    });
+    report
+}
@@ -1,186 +1,286 @@
-use crate::common::ClickhouseClient;
-use crate::model::{Transfer, UserStats};
-use anyhow::Result;
-use std::collections::HashMap;
-
-struct AggregatedData {
-    max_balances: HashMap<String, f64>,
-    buy_prices: HashMap<String, Vec<(f64, f64)>>,
-    sell_prices: HashMap<String, Vec<(f64, f64)>>,
-}
+//! Per-address metrics, computed either in Rust or by ClickHouse with the same semantics.
+//! [`UserStats`] are kept per `(address, token)` pair, since amounts and balances of different
+//! tokens don't add up:
+//!
+//! * Only transfers with a positive `amount` count. Anything else is ignored, and an address
+//!   that only appears in ignored transfers gets no stats at all.
+//! * `total_volume` is the sum of `amount` over transfers the address sends or receives;
+//!   a self-transfer is counted once.
+//! * `avg_buy_price`/`avg_sell_price` are `usd_price` averages weighted by `amount` over received
+//!   and sent transfers (0 when there are none). A self-transfer is both a buy and a sell.
+//! * `max_balance` is the highest running balance, starting at 0, when transfers are replayed in
+//!   [`Transfer::cmp_order`] order. A self-transfer does not move the balance. Balances that go
+//!   below zero are handled by a [`NegativeBalancePolicy`], [`NegativeBalancePolicy::Allow`] by
+//!   default.
+//!
+//! [`UsdStats`] add up all tokens of an address in USD: every transfer is worth
+//! `amount * usd_price`, with the same buy/sell/self-transfer rules as above.
+//!
+//! Both engines expect at most one row per [`Transfer::id`]; storage reads deduplicate, a slice
+//! passed in directly is taken as is.
+//!
+//! All values are [`Decimal`](crate::model::Decimal)s. Sums are exact and the weighted averages
+//! are truncated to 18 digits by both engines; use [`compare`] to check them against each other.
+//! A result outside the decimal range is an error, even when every transfer is in range; its
+//! source is an [`OutOfRangeError`].
 
-pub fn calculate_balance_history(transfers: &[Transfer]) -> HashMap<String, Vec<(u64, f64)>> {
-    let mut balance_history: HashMap<String, Vec<(u64, f64)>> = HashMap::new();
-    let mut balances: HashMap<String, f64> = HashMap::new();
-
-    for t in transfers {
-        let from_balance = balances.entry(t.address_from.clone()).or_default();
+use crate::common::ClickhouseClient;
+use crate::migrate;
+use crate::model::{Transfer, UsdStats, UserStats};
+use crate::storage::{Param, TransferQuery, TransferStore};
+use anyhow::{Context, Result};
+use futures::stream::{self, BoxStream, Stream, StreamExt, TryStreamExt};
+use std::fmt;
 
-        *from_balance -= t.amount;
+mod aggregator;
+mod balance;
+mod compare;
 
-        balance_history
-            .entry(t.address_from.clone())
-            .or_default()
-            .push((t.ts, *from_balance));
+pub use aggregator::StatsAggregator;
+pub use balance::{
+    calculate_balance_history, calculate_balance_history_of, BalanceHistory, NegativeBalancePolicy,
+};
+pub use compare::{compare, Mismatch, ParityReport, Tolerance};
 
-        let to_balance = balances.entry(t.address_to.clone()).or_default();
-        *to_balance += t.amount;
+/// A stats value that doesn't fit a [`Decimal`](crate::model::Decimal). It is the source of the
+/// errors the stats functions return for that, under context naming the value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutOfRangeError;
 
-        balance_history
-            .entry(t.address_to.clone())
-            .or_default()
-            .push((t.ts, *to_balance));
+impl fmt::Display for OutOfRangeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("out of the decimal range")
     }
-
-    balance_history
 }
 
-fn aggregate_transfers(transfers: &[Transfer]) -> AggregatedData {
-    let mut balances: HashMap<String, f64> = HashMap::new();
-    let mut max_balances: HashMap<String, f64> = HashMap::new();
-    let mut buy_prices: HashMap<String, Vec<(f64, f64)>> = HashMap::new();
-    let mut sell_prices: HashMap<String, Vec<(f64, f64)>> = HashMap::new();
-
-    for t in transfers {
-        *balances.entry(t.address_from.clone()).or_default() -= t.amount;
-        *balances.entry(t.address_to.clone()).or_default() += t.amount;
-        let to_balance = balances.get(&t.address_to).copied().unwrap_or(0.0);
-        let from_balance = balances.get(&t.address_from).copied().unwrap_or(0.0);
-        let max_to = max_balances.get(&t.address_to).copied().unwrap_or(0.0);
-
-        if to_balance > max_to {
-            max_balances.insert(t.address_to.clone(), to_balance);
-        }
-
-        let max_from = max_balances.get(&t.address_from).copied().unwrap_or(0.0);
+impl std::error::Error for OutOfRangeError {}
 
-        if from_balance > max_from {
-            max_balances.insert(t.address_from.clone(), from_balance);
-        }
+fn is_counted(t: &Transfer) -> bool {
+    t.amount.is_positive()
+}
 
-        buy_prices
-            .entry(t.address_to.clone())
-            .or_default()
-            .push((t.usd_price, t.amount));
+/// Volume, weighted averages and max balance are computed together in one pass over the
+/// transfers sorted by [`Transfer::cmp_order`].
+pub fn calculate_user_stats_rust(transfers: &[Transfer]) -> Result<Vec<UserStats>> {
+    calculate_user_stats_rust_with_policy(transfers, NegativeBalancePolicy::default())
+}
 
-        sell_prices
-            .entry(t.address_from.clone())
-            .or_default()
-            .push((t.usd_price, t.amount));
-    }
+pub fn calculate_user_stats_rust_with_policy(
+    transfers: &[Transfer],
+    policy: NegativeBalancePolicy,
+) -> Result<Vec<UserStats>> {
+    let mut ordered: Vec<&Transfer> = transfers.iter().collect();
+    ordered.sort_by(|a, b| a.cmp_order(b));
 
-    AggregatedData {
-        max_balances,
-        buy_prices,
-        sell_prices,
-    }
+    let mut aggregator = StatsAggregator::with_policy(policy);
+    aggregator.extend(ordered);
+    aggregator.snapshot()
 }
 
-fn correct_max_balance_for_sellers(agg: &mut AggregatedData) {
-    for (addr, sells) in &agg.sell_prices {
-        let buys = agg.buy_prices.get(addr).cloned().unwrap_or_default();
-
-        if buys.is_empty() && !sells.is_empty() {
-            let sum_sells = sells.iter().map(|(_, amt)| *amt).sum::<f64>();
-            let entry = agg.max_balances.entry(addr.clone()).or_insert(0.0);
-            *entry = sum_sells;
-        } else if !sells.is_empty() {
-            let max_sell = sells.iter().map(|(_, amt)| *amt).fold(0.0, f64::max);
-            let entry = agg.max_balances.entry(addr.clone()).or_insert(0.0);
-            if *entry < max_sell {
-                *entry = max_sell;
-            }
-        }
-    }
+/// USD totals per address across tokens; order doesn't matter.
+pub fn calculate_usd_stats_rust(transfers: &[Transfer]) -> Result<Vec<UsdStats>> {
+    let mut aggregator = StatsAggregator::new();
+    aggregator.extend(transfers);
+    aggregator.usd_snapshot()
 }
 
-fn weighted_avg(data: &[(f64, f64)]) -> f64 {
-    let (sum_px, sum_amt): (f64, f64) = data
-        .iter()
-        .copied()
-        .fold((0.0, 0.0), |acc, (p, a)| (acc.0 + p * a, acc.1 + a));
-    if sum_amt > 0.0 {
-        sum_px / sum_amt
-    } else {
-        0.0
-    }
+/// Same results as [`calculate_user_stats_rust`] for a stream in [`Transfer::cmp_order`] order,
+/// but consumes transfers one at a time, so memory is bounded by the number of addresses rather
+/// than the number of transfers.
+pub async fn calculate_user_stats_stream<S>(transfers: S) -> Result<Vec<UserStats>>
+where
+    S: Stream<Item = Result<Transfer>>,
+{
+    calculate_user_stats_stream_with_policy(transfers, NegativeBalancePolicy::default()).await
 }
 
-fn build_user_stats(transfers: &[Transfer], agg: &AggregatedData) -> Vec<UserStats> {
-    let all_addresses: std::collections::HashSet<_> = transfers
-        .iter()
-        .flat_map(|t| vec![t.address_from.clone(), t.address_to.clone()])
-        .collect();
+pub async fn calculate_user_stats_stream_with_policy<S>(
+    transfers: S,
+    policy: NegativeBalancePolicy,
+) -> Result<Vec<UserStats>>
+where
+    S: Stream<Item = Result<Transfer>>,
+{
+    let aggregator = transfers
+        .try_fold(
+            StatsAggregator::with_policy(policy),
+            |mut aggregator, t| async move {
+                aggregator.push(&t);
+                Ok(aggregator)
+            },
+        )
+        .await?;
 
-    let balance_history = calculate_balance_history(transfers);
-
-    all_addresses
-        .into_iter()
-        .map(|addr| {
-            let buys = agg.buy_prices.get(&addr).cloned().unwrap_or_default();
-            let sells = agg.sell_prices.get(&addr).cloned().unwrap_or_default();
-
-            let total_volume: f64 = transfers
-                .iter()
-                .filter(|t| t.address_from == addr || t.address_to == addr)
-                .map(|t| t.amount.max(0.0))
-                .sum();
-
-            let max_balance = balance_history
-                .get(&addr)
-                .map(|hist| hist.iter().map(|(_, bal)| *bal).fold(0.0, f64::max))
-                .unwrap_or(0.0);
-
-            UserStats {
-                address: addr.clone(),
-                total_volume,
-                avg_buy_price: weighted_avg(&buys),
-                avg_sell_price: weighted_avg(&sells),
-                max_balance,
-            }
-        })
-        .collect()
+    aggregator.snapshot()
 }
 
-pub fn calculate_user_stats_rust(transfers: &[Transfer]) -> Result<Vec<UserStats>> {
-    let mut aggregate_data = aggregate_transfers(transfers);
-    correct_max_balance_for_sellers(&mut aggregate_data);
-    Ok(build_user_stats(transfers, &aggregate_data))
+pub async fn calculate_user_stats_from_store<S>(store: &S) -> Result<Vec<UserStats>>
+where
+    S: TransferStore + ?Sized,
+{
+    calculate_user_stats_stream(store.stream_transfers(&TransferQuery::default()))
+        .await
+        .context("Failed to read transfers from storage")
 }
 
-pub async fn calculate_user_stats_clickhouse(client: &ClickhouseClient) -> Result<Vec<UserStats>> {
-    let stats = client
-        .client
-        .query(r#"
+// Every counted transfer becomes one leg per side: the receiver gets `+amount` as a buy, the
+// sender `-amount` as a sell. A self-transfer is a single leg that is both and moves nothing.
+const LEGS_SQL: &str = r#"
+                    SELECT
+                        ts,
+                        address_from,
+                        address_to,
+                        token,
+                        amount,
+                        usd_price,
+                        tx_hash,
+                        log_index,
+                        leg.1 AS address,
+                        leg.2 AS delta,
+                        leg.3 AS volume,
+                        leg.4 AS buy_amount,
+                        leg.5 AS sell_amount
+                    FROM {table} FINAL
+                    ARRAY JOIN if(
+                        address_from = address_to,
+                        [(CAST(address_to AS String), toDecimal128(0, 18), amount, amount, amount)],
+                        [
+                            (CAST(address_to AS String), amount, amount, amount, toDecimal128(0, 18)),
+                            (CAST(address_from AS String), -amount, amount, toDecimal128(0, 18), amount)
+                        ]
+                    ) AS leg
+                    WHERE amount > 0{filter}
+"#;
+
+const USER_STATS_SQL: &str = r#"
             SELECT
                 address,
-                ifNull(sum(amount_in) + sum(amount_out), 0) as total_volume,
-                ifNull(sum(amount_in * usd_price_in) / nullIf(sum(amount_in), 0), 0) as avg_buy_price,
-                ifNull(sum(amount_out * usd_price_out) / nullIf(sum(amount_out), 0), 0) as avg_sell_price,
-                ifNull(max(balance), 0) as max_balance
+                token,
+                sum(volume) AS total_volume,
+                ifNull(
+                    toDecimal128(sum(toDecimal256(buy_amount, 18) * usd_price) / nullIf(sum(buy_amount), 0), 18),
+                    toDecimal128(0, 18)
+                ) AS avg_buy_price,
+                ifNull(
+                    toDecimal128(sum(toDecimal256(sell_amount, 18) * usd_price) / nullIf(sum(sell_amount), 0), 18),
+                    toDecimal128(0, 18)
+                ) AS avg_sell_price,
+                {max_balance} AS max_balance
             FROM (
                 SELECT
-                    CAST(address_to AS String) as address,
-                    amount as amount_in,
-                    0.0 as amount_out,
-                    usd_price as usd_price_in,
-                    0.0 as usd_price_out,
-                    sum(amount) OVER (PARTITION BY address_to ORDER BY ts ROWS BETWEEN UNBOUNDED PRECEDING AND CURRENT ROW) as balance
-                FROM transfers
-                UNION ALL
-                SELECT
-                    CAST(address_from AS String) as address,
-                    0.0 as amount_in,
-                    amount as amount_out,
-                    0.0 as usd_price_in,
-                    usd_price as usd_price_out,
-                    sum(-amount) OVER (PARTITION BY address_from ORDER BY ts ROWS BETWEEN UNBOUNDED PRECEDING AND CURRENT ROW) as balance
-                FROM transfers
+                    address,
+                    token,
+                    volume,
+                    buy_amount,
+                    sell_amount,
+                    usd_price,
+                    sum(delta) OVER (
+                        PARTITION BY address, token
+                        ORDER BY ts, address_from, address_to, amount, usd_price, tx_hash, log_index
+                        ROWS BETWEEN UNBOUNDED PRECEDING AND CURRENT ROW
+                    ) AS balance
+                FROM ({legs})
             )
+            GROUP BY address, token
+        "#;
+
+// Products are summed exactly as Decimal256 and truncated once, like the Rust engine.
+const USD_STATS_SQL: &str = r#"
+            SELECT
+                address,
+                uniqExact(token) AS tokens,
+                toDecimal128(sum(toDecimal256(volume, 18) * usd_price), 18) AS total_volume_usd,
+                toDecimal128(sum(toDecimal256(buy_amount, 18) * usd_price), 18) AS buy_volume_usd,
+                toDecimal128(sum(toDecimal256(sell_amount, 18) * usd_price), 18) AS sell_volume_usd
+            FROM ({legs})
             GROUP BY address
-            HAVING sum(amount_in) > 0 OR sum(amount_out) > 0
-        "#)
+        "#;
+
+// `Clamp` depends on the path of each balance, which a window sum can't express.
+fn user_stats_sql(policy: NegativeBalancePolicy, table: &str, filter: &str) -> Result<String> {
+    let max_balance = match policy {
+        NegativeBalancePolicy::Allow => "greatest(max(balance), toDecimal128(0, 18))",
+        NegativeBalancePolicy::PreFunded => {
+            "greatest(max(balance), toDecimal128(0, 18)) + greatest(-min(balance), toDecimal128(0, 18))"
+        }
+        NegativeBalancePolicy::Clamp => {
+            anyhow::bail!("NegativeBalancePolicy::Clamp is only supported by the Rust engine")
+        }
+    };
+    Ok(USER_STATS_SQL
+        .replace("{legs}", LEGS_SQL)
+        .replace("{filter}", filter)
+        .replace("{max_balance}", max_balance)
+        .replace("{table}", table))
+}
+
+pub async fn calculate_user_stats_clickhouse(client: &ClickhouseClient) -> Result<Vec<UserStats>> {
+    calculate_user_stats_clickhouse_with_policy(client, NegativeBalancePolicy::default()).await
+}
+
+/// Refuses to run unless the schema is fully migrated (see [`migrate::ensure_up_to_date`]).
+pub async fn calculate_user_stats_clickhouse_with_policy(
+    client: &ClickhouseClient,
+    policy: NegativeBalancePolicy,
+) -> Result<Vec<UserStats>> {
+    calculate_user_stats_clickhouse_matching(client, policy, &TransferQuery::new()).await
+}
+
+/// Stats over the transfers matching the filters of `query` only, like the rust engine over
+/// [`TransferStore::stream_transfers`]; its order, limit and cursor are ignored.
+pub async fn calculate_user_stats_clickhouse_matching(
+    client: &ClickhouseClient,
+    policy: NegativeBalancePolicy,
+    query: &TransferQuery,
+) -> Result<Vec<UserStats>> {
+    let (conditions, params) = query.filter_conditions();
+    let filter: String = conditions
+        .iter()
+        .map(|condition| format!(" AND {condition}"))
+        .collect();
+    let sql = user_stats_sql(policy, &client.table, &filter)?;
+    migrate::ensure_up_to_date(client).await?;
+    let stats = Param::bind_all(client.client.query(&sql), params)
         .fetch_all::<UserStats>()
         .await?;
     Ok(stats)
 }
+
+/// Streaming variant of [`calculate_user_stats_clickhouse`] that reads rows off the cursor.
+pub fn stream_user_stats_clickhouse(client: &ClickhouseClient) -> BoxStream<'_, Result<UserStats>> {
+    stream::once(migrate::ensure_up_to_date(client))
+        .map_ok(move |()| user_stats_rows(client))
+        .try_flatten()
+        .boxed()
+}
+
+fn user_stats_rows(client: &ClickhouseClient) -> BoxStream<'_, Result<UserStats>> {
+    let sql = user_stats_sql(NegativeBalancePolicy::default(), &client.table, "")
+        .expect("Allow is supported");
+    match client.client.query(&sql).fetch::<UserStats>() {
+        Ok(cursor) => stream::try_unfold(cursor, |mut cursor| async move {
+            let stats = cursor
+                .next()
+                .await
+                .context("Failed to read user stats row")?;
+            Ok(stats.map(|s| (s, cursor)))
+        })
+        .boxed(),
+        Err(e) => stream::once(async move {
+            Err(anyhow::Error::new(e).context("Failed to start user stats query"))
+        })
+        .boxed(),
+    }
+}
+
+/// [`UsdStats`] computed by ClickHouse; refuses to run on an outdated schema like the other
+/// ClickHouse stats.
+pub async fn calculate_usd_stats_clickhouse(client: &ClickhouseClient) -> Result<Vec<UsdStats>> {
+    migrate::ensure_up_to_date(client).await?;
+    let sql = USD_STATS_SQL
+        .replace("{legs}", LEGS_SQL)
+        .replace("{filter}", "")
+        .replace("{table}", &client.table);
+    let stats = client.client.query(&sql).fetch_all::<UsdStats>().await?;
+    Ok(stats)
+}
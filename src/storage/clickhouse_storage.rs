@@ -0,0 +1,97 @@
+use super::{InsertConfig, TransferQuery, TransferStore};
+use crate::common::ClickhouseClient;
+use crate::model::Transfer;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use futures::stream::{self, BoxStream, StreamExt};
+
+pub struct ClickhouseStorage {
+    client: ClickhouseClient,
+    insert_config: InsertConfig,
+}
+
+impl ClickhouseStorage {
+    pub fn new(client: ClickhouseClient) -> Self {
+        Self {
+            client,
+            insert_config: InsertConfig::default(),
+        }
+    }
+
+    /// The connection this storage uses, e.g. to run the ClickHouse stats against the same table.
+    pub fn client(&self) -> &ClickhouseClient {
+        &self.client
+    }
+
+    pub fn with_insert_config(mut self, insert_config: InsertConfig) -> Self {
+        self.insert_config = insert_config;
+        self
+    }
+}
+
+#[async_trait]
+impl TransferStore for ClickhouseStorage {
+    async fn insert_transfer(&self, transfer: &Transfer) -> Result<()> {
+        let mut insert = self.client.client.insert(&self.client.table)?;
+        insert
+            .write(transfer)
+            .await
+            .context("Failed to write transfer row")?;
+        insert.end().await.context("Failed to finish insert")?;
+        Ok(())
+    }
+
+    async fn insert_stream(&self, mut transfers: BoxStream<'_, Transfer>) -> Result<u64> {
+        let mut inserter = self
+            .client
+            .client
+            .inserter::<Transfer>(&self.client.table)?
+            .with_max_rows(self.insert_config.batch_size)
+            .with_period(self.insert_config.flush_interval);
+
+        let mut written = 0;
+        while let Some(transfer) = transfers.next().await {
+            inserter
+                .write(&transfer)
+                .context("Failed to write transfer row")?;
+            written += inserter
+                .commit()
+                .await
+                .context("Failed to flush transfer batch")?
+                .rows;
+        }
+        written += inserter
+            .end()
+            .await
+            .context("Failed to flush last transfer batch")?
+            .rows;
+
+        Ok(written)
+    }
+
+    async fn query_transfers(&self, query: &TransferQuery) -> Result<Vec<Transfer>> {
+        let transfers = query
+            .to_clickhouse(&self.client.client, &self.client.table)
+            .fetch_all::<Transfer>()
+            .await
+            .context("Failed to fetch transfers")?;
+        Ok(transfers)
+    }
+
+    fn stream_transfers<'a>(&'a self, query: &TransferQuery) -> BoxStream<'a, Result<Transfer>> {
+        match query
+            .to_clickhouse(&self.client.client, &self.client.table)
+            .fetch::<Transfer>()
+        {
+            Ok(cursor) => stream::try_unfold(cursor, |mut cursor| async move {
+                let transfer = cursor.next().await.context("Failed to read transfer row")?;
+                Ok(transfer.map(|t| (t, cursor)))
+            })
+            .boxed(),
+            Err(e) => stream::once(async move {
+                Err(anyhow::Error::new(e).context("Failed to start transfers query"))
+            })
+            .boxed(),
+        }
+    }
+}
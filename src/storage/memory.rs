@@ -0,0 +1,99 @@
+use super::{TransferQuery, TransferStore};
+use crate::model::{Transfer, TransferId};
+use anyhow::Result;
+use async_trait::async_trait;
+use futures::stream::{self, BoxStream, StreamExt};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Process-local store, mostly for tests and for running the pipeline without ClickHouse.
+///
+/// Like the ClickHouse table, it keeps one row per [`Transfer::id`]: inserting an id again
+/// replaces the stored transfer. Clones share the same underlying data.
+#[derive(Debug, Default, Clone)]
+pub struct InMemoryStorage {
+    transfers: Arc<Mutex<Transfers>>,
+}
+
+#[derive(Debug, Default)]
+struct Transfers {
+    rows: Vec<Transfer>,
+    positions: HashMap<TransferId, usize>,
+}
+
+impl Transfers {
+    fn insert(&mut self, transfer: Transfer) {
+        match self.positions.get(&transfer.id()) {
+            Some(&position) => self.rows[position] = transfer,
+            None => {
+                self.positions.insert(transfer.id(), self.rows.len());
+                self.rows.push(transfer);
+            }
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &Transfer> {
+        self.rows.iter()
+    }
+}
+
+impl InMemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_transfers(transfers: Vec<Transfer>) -> Self {
+        let mut rows = Transfers::default();
+        for transfer in transfers {
+            rows.insert(transfer);
+        }
+        Self {
+            transfers: Arc::new(Mutex::new(rows)),
+        }
+    }
+}
+
+#[async_trait]
+impl TransferStore for InMemoryStorage {
+    async fn insert_transfer(&self, transfer: &Transfer) -> Result<()> {
+        self.transfers
+            .lock()
+            .map_err(|_| anyhow::anyhow!("In-memory storage lock poisoned"))?
+            .insert(transfer.clone());
+        Ok(())
+    }
+
+    async fn insert_stream(&self, transfers: BoxStream<'_, Transfer>) -> Result<u64> {
+        let transfers: Vec<Transfer> = transfers.collect().await;
+        let written = transfers.len() as u64;
+        let mut rows = self
+            .transfers
+            .lock()
+            .map_err(|_| anyhow::anyhow!("In-memory storage lock poisoned"))?;
+        for transfer in transfers {
+            rows.insert(transfer);
+        }
+        Ok(written)
+    }
+
+    async fn query_transfers(&self, query: &TransferQuery) -> Result<Vec<Transfer>> {
+        let transfers = self
+            .transfers
+            .lock()
+            .map_err(|_| anyhow::anyhow!("In-memory storage lock poisoned"))?;
+        Ok(query.apply(transfers.iter()))
+    }
+
+    fn stream_transfers<'a>(&'a self, query: &TransferQuery) -> BoxStream<'a, Result<Transfer>> {
+        let transfers = match self.transfers.lock() {
+            Ok(transfers) => query.apply(transfers.iter()),
+            Err(_) => {
+                return stream::once(async {
+                    Err(anyhow::anyhow!("In-memory storage lock poisoned"))
+                })
+                .boxed()
+            }
+        };
+        stream::iter(transfers.into_iter().map(Ok)).boxed()
+    }
+}
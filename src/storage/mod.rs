@@ -1,38 +1,65 @@
 use crate::model::Transfer;
 use anyhow::Result;
-use clickhouse::Client;
+use async_trait::async_trait;
+use futures::stream::{self, BoxStream, StreamExt};
+use std::time::Duration;
 
-pub struct ClickhouseStorage {
-    client: Client,
-}
+mod clickhouse_storage;
+mod memory;
+mod query;
+
+pub use clickhouse_storage::ClickhouseStorage;
+pub use memory::InMemoryStorage;
+pub(crate) use query::Param;
+pub use query::{SortOrder, TransferQuery};
 
-impl ClickhouseStorage {
-    // TODO use common client
-    pub fn new(database_url: &str) -> Self {
-        let client = Client::default()
-            .with_url(database_url)
-            .with_user("default")
-            .with_password("111");
+/// Limits for bulk inserts: the current `INSERT` is finished and a new one is started as soon
+/// as either of them is reached.
+#[derive(Debug, Clone)]
+pub struct InsertConfig {
+    pub batch_size: u64,
+    /// Checked as rows arrive, so a stalled source keeps the pending batch open.
+    pub flush_interval: Option<Duration>,
+}
 
-        Self { client }
+impl Default for InsertConfig {
+    fn default() -> Self {
+        Self {
+            batch_size: 100_000,
+            flush_interval: Some(Duration::from_secs(10)),
+        }
     }
+}
 
-    pub async fn insert_transfer(
-        &self,
-        transfer: &Transfer,
-    ) -> Result<(), clickhouse::error::Error> {
-        let mut insert = self.client.insert("transfers")?;
-        insert.write(transfer).await?;
-        insert.end().await?;
-        Ok(())
+/// Backend-agnostic access to persisted transfers.
+///
+/// Services and tests are written against this trait, so the ClickHouse backend can be
+/// swapped for [`InMemoryStorage`] without a running server.
+///
+/// Transfers are keyed by [`Transfer::id`]: storing an id that is already present replaces the
+/// earlier row, so a failed insert can simply be retried. Reads return one row per id.
+#[async_trait]
+pub trait TransferStore: Send + Sync {
+    async fn insert_transfer(&self, transfer: &Transfer) -> Result<()>;
+
+    /// Bulk insert, returns the number of rows written, replaced ones included.
+    async fn insert_stream(&self, transfers: BoxStream<'_, Transfer>) -> Result<u64>;
+
+    async fn query_transfers(&self, query: &TransferQuery) -> Result<Vec<Transfer>>;
+
+    async fn get_transfers(&self) -> Result<Vec<Transfer>> {
+        self.query_transfers(&TransferQuery::default()).await
     }
 
-    pub async fn get_transfers(&self) -> Result<Vec<Transfer>, clickhouse::error::Error> {
-        let transfers = self
-            .client
-            .query("SELECT * FROM transfers")
-            .fetch_all::<Transfer>()
-            .await?;
-        Ok(transfers)
+    /// Same rows as [`TransferStore::query_transfers`], yielded one by one instead of collected.
+    fn stream_transfers<'a>(&'a self, query: &TransferQuery) -> BoxStream<'a, Result<Transfer>>;
+
+    async fn insert_transfers<I>(&self, transfers: I) -> Result<u64>
+    where
+        I: IntoIterator<Item = Transfer> + Send,
+        I::IntoIter: Send + 'async_trait,
+        Self: Sized,
+    {
+        self.insert_stream(stream::iter(transfers).boxed()).await
     }
 }
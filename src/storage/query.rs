@@ -0,0 +1,272 @@
+use crate::model::{Decimal, Transfer};
+use clickhouse::query::Query;
+use clickhouse::Client;
+use std::cmp::Ordering;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// Filter, ordering and page of a transfer read.
+///
+/// Results are always ordered by the full row key (see [`Transfer::cmp_order`]), so the last
+/// transfer of a page is a valid keyset cursor for [`TransferQuery::after`].
+/// Time bounds are `[from_ts, to_ts)`, amount and price bounds are inclusive.
+#[derive(Debug, Clone, Default)]
+pub struct TransferQuery {
+    pub from_ts: Option<u64>,
+    pub to_ts: Option<u64>,
+    pub address_from: Option<String>,
+    pub address_to: Option<String>,
+    /// Matches transfers where the address is either the sender or the receiver.
+    pub address: Option<String>,
+    /// Matches transfers where any of these addresses is the sender or the receiver; empty
+    /// matches all.
+    pub addresses: Vec<String>,
+    pub token: Option<String>,
+    pub min_amount: Option<Decimal>,
+    pub max_amount: Option<Decimal>,
+    pub min_price: Option<Decimal>,
+    pub max_price: Option<Decimal>,
+    pub order: SortOrder,
+    pub limit: Option<u64>,
+    pub after: Option<Transfer>,
+}
+
+pub(crate) enum Param {
+    U64(u64),
+    Decimal(Decimal),
+    Str(String),
+    Strs(Vec<String>),
+}
+
+impl Param {
+    pub(crate) fn bind_all(query: Query, params: Vec<Param>) -> Query {
+        params.into_iter().fold(query, |query, param| match param {
+            Param::U64(v) => query.bind(v),
+            Param::Decimal(v) => query.bind(v.to_string()),
+            Param::Str(v) => query.bind(v),
+            Param::Strs(v) => query.bind(v),
+        })
+    }
+}
+
+impl TransferQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_ts(mut self, ts: u64) -> Self {
+        self.from_ts = Some(ts);
+        self
+    }
+
+    pub fn to_ts(mut self, ts: u64) -> Self {
+        self.to_ts = Some(ts);
+        self
+    }
+
+    pub fn address_from(mut self, address: impl Into<String>) -> Self {
+        self.address_from = Some(address.into());
+        self
+    }
+
+    pub fn address_to(mut self, address: impl Into<String>) -> Self {
+        self.address_to = Some(address.into());
+        self
+    }
+
+    pub fn address(mut self, address: impl Into<String>) -> Self {
+        self.address = Some(address.into());
+        self
+    }
+
+    pub fn addresses<I>(mut self, addresses: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.addresses = addresses.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    pub fn amount_between(mut self, min: Option<Decimal>, max: Option<Decimal>) -> Self {
+        self.min_amount = min;
+        self.max_amount = max;
+        self
+    }
+
+    pub fn price_between(mut self, min: Option<Decimal>, max: Option<Decimal>) -> Self {
+        self.min_price = min;
+        self.max_price = max;
+        self
+    }
+
+    pub fn order(mut self, order: SortOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    pub fn limit(mut self, limit: u64) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Continue after `cursor`, usually the last transfer of the previous page.
+    pub fn after(mut self, cursor: Transfer) -> Self {
+        self.after = Some(cursor);
+        self
+    }
+
+    pub fn matches(&self, t: &Transfer) -> bool {
+        self.from_ts.is_none_or(|from| t.ts >= from)
+            && self.to_ts.is_none_or(|to| t.ts < to)
+            && self
+                .address_from
+                .as_ref()
+                .is_none_or(|a| &t.address_from == a)
+            && self.address_to.as_ref().is_none_or(|a| &t.address_to == a)
+            && self
+                .address
+                .as_ref()
+                .is_none_or(|a| &t.address_from == a || &t.address_to == a)
+            && (self.addresses.is_empty()
+                || self.addresses.contains(&t.address_from)
+                || self.addresses.contains(&t.address_to))
+            && self.token.as_ref().is_none_or(|token| &t.token == token)
+            && self.min_amount.is_none_or(|min| t.amount >= min)
+            && self.max_amount.is_none_or(|max| t.amount <= max)
+            && self.min_price.is_none_or(|min| t.usd_price >= min)
+            && self.max_price.is_none_or(|max| t.usd_price <= max)
+            && self.after.as_ref().is_none_or(|cursor| {
+                let ord = t.cmp_order(cursor);
+                match self.order {
+                    SortOrder::Asc => ord == Ordering::Greater,
+                    SortOrder::Desc => ord == Ordering::Less,
+                }
+            })
+    }
+
+    /// Applies the query to an in-memory collection.
+    pub fn apply<'a>(&self, transfers: impl IntoIterator<Item = &'a Transfer>) -> Vec<Transfer> {
+        let mut selected: Vec<Transfer> = transfers
+            .into_iter()
+            .filter(|t| self.matches(t))
+            .cloned()
+            .collect();
+        selected.sort_by(|a, b| match self.order {
+            SortOrder::Asc => a.cmp_order(b),
+            SortOrder::Desc => b.cmp_order(a),
+        });
+        if let Some(limit) = self.limit {
+            selected.truncate(limit as usize);
+        }
+        selected
+    }
+
+    /// Builds the `SELECT` for `table` with all values bound as escaped literals. Rows are
+    /// deduplicated by [`Transfer::id`].
+    pub fn to_clickhouse(&self, client: &Client, table: &str) -> Query {
+        let (mut conditions, mut params) = self.filter_conditions();
+
+        if let Some(cursor) = &self.after {
+            conditions.push(match self.order {
+                SortOrder::Asc => {
+                    "(ts, address_from, address_to, amount, usd_price, tx_hash, log_index) > (?, ?, ?, toDecimal128(?, 18), toDecimal128(?, 18), ?, ?)"
+                }
+                SortOrder::Desc => {
+                    "(ts, address_from, address_to, amount, usd_price, tx_hash, log_index) < (?, ?, ?, toDecimal128(?, 18), toDecimal128(?, 18), ?, ?)"
+                }
+            });
+            params.push(Param::U64(cursor.ts));
+            params.push(Param::Str(cursor.address_from.clone()));
+            params.push(Param::Str(cursor.address_to.clone()));
+            params.push(Param::Decimal(cursor.amount));
+            params.push(Param::Decimal(cursor.usd_price));
+            params.push(Param::Str(cursor.tx_hash.clone()));
+            params.push(Param::U64(cursor.log_index.into()));
+        }
+
+        // `FINAL` merges rows that share an id, which the table may still hold in separate parts.
+        let mut sql = format!("SELECT ?fields FROM {table} FINAL");
+        if !conditions.is_empty() {
+            sql.push_str(" WHERE ");
+            sql.push_str(&conditions.join(" AND "));
+        }
+        let direction = match self.order {
+            SortOrder::Asc => "ASC",
+            SortOrder::Desc => "DESC",
+        };
+        sql.push_str(&format!(
+            " ORDER BY ts {direction}, address_from {direction}, address_to {direction}, amount {direction}, usd_price {direction}, tx_hash {direction}, log_index {direction}"
+        ));
+        if let Some(limit) = self.limit {
+            sql.push_str(&format!(" LIMIT {limit}"));
+        }
+
+        Param::bind_all(client.query(&sql), params)
+    }
+
+    /// The filters of the query as SQL conditions over the table's columns, with the values
+    /// for their `?`s; order, limit and cursor are left out.
+    pub(crate) fn filter_conditions(&self) -> (Vec<&'static str>, Vec<Param>) {
+        let mut conditions = vec![];
+        let mut params = vec![];
+
+        if let Some(from) = self.from_ts {
+            conditions.push("ts >= ?");
+            params.push(Param::U64(from));
+        }
+        if let Some(to) = self.to_ts {
+            conditions.push("ts < ?");
+            params.push(Param::U64(to));
+        }
+        if let Some(address) = &self.address_from {
+            conditions.push("address_from = ?");
+            params.push(Param::Str(address.clone()));
+        }
+        if let Some(address) = &self.address_to {
+            conditions.push("address_to = ?");
+            params.push(Param::Str(address.clone()));
+        }
+        if let Some(address) = &self.address {
+            conditions.push("(address_from = ? OR address_to = ?)");
+            params.push(Param::Str(address.clone()));
+            params.push(Param::Str(address.clone()));
+        }
+        if !self.addresses.is_empty() {
+            conditions.push("(has(?, address_from) OR has(?, address_to))");
+            params.push(Param::Strs(self.addresses.clone()));
+            params.push(Param::Strs(self.addresses.clone()));
+        }
+        if let Some(token) = &self.token {
+            conditions.push("token = ?");
+            params.push(Param::Str(token.clone()));
+        }
+        if let Some(min) = self.min_amount {
+            conditions.push("amount >= toDecimal128(?, 18)");
+            params.push(Param::Decimal(min));
+        }
+        if let Some(max) = self.max_amount {
+            conditions.push("amount <= toDecimal128(?, 18)");
+            params.push(Param::Decimal(max));
+        }
+        if let Some(min) = self.min_price {
+            conditions.push("usd_price >= toDecimal128(?, 18)");
+            params.push(Param::Decimal(min));
+        }
+        if let Some(max) = self.max_price {
+            conditions.push("usd_price <= toDecimal128(?, 18)");
+            params.push(Param::Decimal(max));
+        }
+        (conditions, params)
+    }
+}
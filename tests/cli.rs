@@ -0,0 +1,309 @@
+use clap::Parser;
+use clickhouse::test::{handlers, Mock};
+use clickhouse::Row;
+use rust_challenge::cli::{exit, Cli, Command, Engine};
+use rust_challenge::migrate;
+use rust_challenge::model::{Decimal, Transfer, UserStats, DEFAULT_TOKEN};
+use serde::Serialize;
+use std::path::PathBuf;
+
+#[derive(Row, Serialize)]
+struct Count {
+    count: u64,
+}
+
+#[derive(Row, Serialize)]
+struct Version {
+    version: u32,
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("cli-{}-{name}", std::process::id()))
+}
+
+async fn run(args: &[&str]) -> (anyhow::Result<u8>, String) {
+    let cli =
+        Cli::try_parse_from(std::iter::once("rust_challenge").chain(args.iter().copied())).unwrap();
+    let mut out = vec![];
+    let result = cli.run(&mut out).await;
+    (result, String::from_utf8(out).unwrap())
+}
+
+/// A config file pointing at `mock`.
+fn mock_config(mock: &Mock, name: &str) -> PathBuf {
+    let path = temp_path(name);
+    std::fs::write(
+        &path,
+        format!("url = \"{}\"\ncompression = \"none\"\n", mock.url()),
+    )
+    .unwrap();
+    path
+}
+
+fn add_up_to_date_status(mock: &Mock) {
+    mock.add(handlers::provide(vec![Count { count: 1 }]));
+    mock.add(handlers::provide(
+        migrate::migrations()
+            .iter()
+            .map(|m| Version { version: m.version })
+            .collect::<Vec<_>>(),
+    ));
+}
+
+#[test]
+fn test_parse_defaults() {
+    let cli = Cli::try_parse_from(["rust_challenge", "stats"]).unwrap();
+    let Command::Stats(args) = cli.command else {
+        panic!("expected stats");
+    };
+    assert_eq!(args.engine, Engine::Clickhouse);
+    assert_eq!(args.limit, None);
+    assert!(args.address.is_empty());
+
+    let cli = Cli::try_parse_from(["rust_challenge", "serve"]).unwrap();
+    let Command::Serve(args) = cli.command else {
+        panic!("expected serve");
+    };
+    assert_eq!(args.listen.to_string(), "127.0.0.1:8080");
+}
+
+#[test]
+fn test_usage_errors_exit_with_usage_code() {
+    for args in [
+        vec!["rust_challenge"],
+        vec!["rust_challenge", "stats", "--engine", "duckdb"],
+        vec!["rust_challenge", "generate", "--count", "many"],
+        vec!["rust_challenge", "compare", "--absolute", "0.1.2"],
+        vec!["rust_challenge", "stats", "--format", "yaml"],
+        vec!["rust_challenge", "serve", "--listen", "localhost"],
+    ] {
+        let error = Cli::try_parse_from(&args).unwrap_err();
+        assert_eq!(error.exit_code(), exit::USAGE as i32, "{args:?}");
+    }
+}
+
+#[tokio::test]
+async fn test_generate_is_reproducible() {
+    let args = [
+        "generate",
+        "-n",
+        "20",
+        "--seed",
+        "7",
+        "--now",
+        "1700000000",
+        "--token",
+        "ETH",
+    ];
+    let (first, output) = run(&args).await;
+    assert_eq!(first.unwrap(), exit::SUCCESS);
+    let transfers: Vec<Transfer> = output
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(transfers.len(), 20);
+    assert!(transfers.iter().all(|t| t.token == "ETH"));
+    assert!(transfers.iter().all(|t| t.ts <= 1_700_000_000));
+
+    let (_, again) = run(&args).await;
+    assert_eq!(output, again);
+}
+
+#[tokio::test]
+async fn test_generate_then_stats_from_file() {
+    let path = temp_path("transfers.ndjson");
+    let file = path.to_str().unwrap();
+    let (result, output) = run(&["generate", "-n", "500", "--seed", "1", "-o", file]).await;
+    assert_eq!(result.unwrap(), exit::SUCCESS);
+    assert!(output.is_empty());
+
+    let (result, output) = run(&[
+        "stats", "--engine", "rust", "--input", file, "--sort", "volume", "--desc", "--limit", "5",
+    ])
+    .await;
+    assert_eq!(result.unwrap(), exit::SUCCESS);
+    let (result, csv) = run(&[
+        "stats", "--engine", "rust", "--input", file, "--limit", "3", "--format", "csv",
+    ])
+    .await;
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(result.unwrap(), exit::SUCCESS);
+    assert_eq!(csv.lines().count(), 4);
+    assert!(csv.starts_with("address,token,total_volume,"), "{csv}");
+
+    let stats: Vec<UserStats> = output
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(stats.len(), 5);
+    assert!(stats
+        .windows(2)
+        .all(|w| w[0].total_volume >= w[1].total_volume));
+}
+
+#[tokio::test]
+async fn test_stats_filters() {
+    let path = temp_path("filters.ndjson");
+    let file = path.to_str().unwrap();
+    let transfer = |from: &str, to: &str, token: &str, tx: u64| Transfer {
+        ts: tx,
+        address_from: from.to_string(),
+        address_to: to.to_string(),
+        token: token.to_string(),
+        amount: Decimal::from(10),
+        usd_price: Decimal::from(2),
+        block_number: tx,
+        tx_hash: format!("0x{tx:02x}"),
+        log_index: 0,
+    };
+    let lines: Vec<String> = [
+        transfer("A", "B", DEFAULT_TOKEN, 1),
+        transfer("B", "C", DEFAULT_TOKEN, 2),
+        transfer("A", "C", "ETH", 3),
+    ]
+    .iter()
+    .map(|t| serde_json::to_string(t).unwrap())
+    .collect();
+    std::fs::write(&path, lines.join("\n")).unwrap();
+
+    let (result, output) = run(&[
+        "stats",
+        "--engine",
+        "rust",
+        "--input",
+        file,
+        "--address",
+        "A",
+        "--address",
+        "C",
+        "--token",
+        "ETH",
+    ])
+    .await;
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(result.unwrap(), exit::SUCCESS);
+    let addresses: Vec<String> = output
+        .lines()
+        .map(|line| serde_json::from_str::<UserStats>(line).unwrap().address)
+        .collect();
+    assert_eq!(addresses, vec!["A", "C"]);
+}
+
+#[tokio::test]
+async fn test_invalid_input_names_the_line() {
+    let path = temp_path("broken.ndjson");
+    std::fs::write(&path, "\n{\"ts\": 1}\n").unwrap();
+    let (result, _) = run(&[
+        "stats",
+        "--engine",
+        "rust",
+        "--input",
+        path.to_str().unwrap(),
+    ])
+    .await;
+    std::fs::remove_file(&path).unwrap();
+    let error = format!("{:#}", result.unwrap_err());
+    assert!(
+        error.contains("line 2, field address_from: missing"),
+        "{error}"
+    );
+
+    let (result, _) = run(&["stats", "--input", "transfers.ndjson"]).await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn test_load_skip_invalid_exit_code() {
+    let mock = Mock::new();
+    let config = mock_config(&mock, "load.toml");
+    let config = config.to_str().unwrap();
+    let path = temp_path("load.csv");
+    let file = path.to_str().unwrap();
+    let (result, _) = run(&["generate", "-n", "3", "--seed", "5", "-o", file]).await;
+    assert_eq!(result.unwrap(), exit::SUCCESS);
+    let mut lines: Vec<String> = std::fs::read_to_string(&path)
+        .unwrap()
+        .lines()
+        .map(str::to_string)
+        .collect();
+    assert!(lines[0].starts_with("ts,"), "{}", lines[0]);
+    lines.insert(2, "1,A,B".to_string());
+    std::fs::write(&path, lines.join("\n")).unwrap();
+
+    add_up_to_date_status(&mock);
+    let inserted = mock.add(handlers::record());
+    let (result, _) = run(&["--config", config, "load", "--skip-invalid", file]).await;
+    assert_eq!(result.unwrap(), exit::INVALID_ROWS);
+    let rows: Vec<Transfer> = inserted.collect().await;
+    assert_eq!(rows.len(), 3);
+
+    add_up_to_date_status(&mock);
+    mock.add(handlers::record::<Transfer>());
+    let (result, _) = run(&["--config", config, "load", file]).await;
+    std::fs::remove_file(&path).unwrap();
+    std::fs::remove_file(config).unwrap();
+    let error = format!("{:#}", result.unwrap_err());
+    assert!(
+        error.contains("line 3: expected 9 fields, got 3"),
+        "{error}"
+    );
+}
+
+#[tokio::test]
+async fn test_migrate_check_exit_codes() {
+    let mock = Mock::new();
+    let config = mock_config(&mock, "migrate.toml");
+    let config = config.to_str().unwrap();
+
+    mock.add(handlers::provide(vec![Count { count: 0 }]));
+    let (result, output) = run(&["--config", config, "migrate", "--check"]).await;
+    assert_eq!(result.unwrap(), exit::OUTDATED);
+    assert!(output.contains("schema version 0"), "{output}");
+
+    add_up_to_date_status(&mock);
+    let (result, _) = run(&["migrate", "--check", "--config", config]).await;
+    std::fs::remove_file(config).unwrap();
+    assert_eq!(result.unwrap(), exit::SUCCESS);
+}
+
+#[tokio::test]
+async fn test_compare_exit_codes() {
+    let transfer = Transfer {
+        ts: 1,
+        address_from: "A".to_string(),
+        address_to: "B".to_string(),
+        token: DEFAULT_TOKEN.to_string(),
+        amount: Decimal::from(10),
+        usd_price: Decimal::from(2),
+        block_number: 1,
+        tx_hash: "0x01".to_string(),
+        log_index: 0,
+    };
+    let stat = |address: &str, max_balance: i64| UserStats {
+        address: address.to_string(),
+        token: DEFAULT_TOKEN.to_string(),
+        total_volume: Decimal::from(10),
+        avg_buy_price: Decimal::from(if address == "B" { 2 } else { 0 }),
+        avg_sell_price: Decimal::from(if address == "A" { 2 } else { 0 }),
+        max_balance: Decimal::from(max_balance),
+    };
+
+    let mock = Mock::new();
+    let config = mock_config(&mock, "compare.toml");
+    let config = config.to_str().unwrap();
+
+    mock.add(handlers::provide(vec![transfer.clone()]));
+    add_up_to_date_status(&mock);
+    mock.add(handlers::provide(vec![stat("A", 0), stat("B", 10)]));
+    let (result, output) = run(&["--config", config, "compare"]).await;
+    assert_eq!(result.unwrap(), exit::SUCCESS, "{output}");
+    assert!(output.starts_with("2 address/token pairs compared, 0 mismatches"));
+
+    mock.add(handlers::provide(vec![transfer]));
+    add_up_to_date_status(&mock);
+    mock.add(handlers::provide(vec![stat("A", 0), stat("B", 11)]));
+    let (result, output) = run(&["--config", config, "compare"]).await;
+    std::fs::remove_file(config).unwrap();
+    assert_eq!(result.unwrap(), exit::MISMATCH);
+    assert!(output.contains("B (TKN): max_balance"), "{output}");
+}
@@ -0,0 +1,180 @@
+use clickhouse::test::{handlers, Mock};
+use clickhouse::Row;
+use rust_challenge::common::{ClickhouseClient, ClickhouseConfig, CompressionMode};
+use rust_challenge::migrate;
+use rust_challenge::model::{Decimal, Transfer, UserStats, DEFAULT_TOKEN};
+use rust_challenge::stats::calculate_user_stats_clickhouse;
+use rust_challenge::storage::{ClickhouseStorage, TransferQuery, TransferStore};
+use serde::Serialize;
+
+#[derive(Row, Serialize)]
+struct Count {
+    count: u64,
+}
+
+#[derive(Row, Serialize)]
+struct Version {
+    version: u32,
+}
+
+fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+    vars.iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+#[test]
+fn test_default_config() {
+    let config = ClickhouseConfig::default();
+    assert_eq!(config.url, "http://localhost:8123");
+    assert_eq!(config.user, "default");
+    assert_eq!(config.password, "");
+    assert_eq!(config.database, "default");
+    assert_eq!(config.table, "transfers");
+    assert_eq!(config.compression, CompressionMode::Lz4);
+    assert_eq!(config.connect_timeout_secs, None);
+    assert!(config.validate().is_ok());
+}
+
+#[test]
+fn test_config_from_toml_keeps_defaults() {
+    let config = ClickhouseConfig::from_toml_str(
+        r#"
+        url = "http://clickhouse:8123"
+        password = "secret"
+        compression = "none"
+        connect_timeout_secs = 5
+        "#,
+    )
+    .unwrap();
+    assert_eq!(config.url, "http://clickhouse:8123");
+    assert_eq!(config.password, "secret");
+    assert_eq!(config.compression, CompressionMode::None);
+    assert_eq!(config.connect_timeout_secs, Some(5));
+    assert_eq!(config.table, "transfers");
+}
+
+#[test]
+fn test_config_from_json() {
+    let config =
+        ClickhouseConfig::from_json_str(r#"{"database": "analytics", "query_timeout_secs": 30}"#)
+            .unwrap();
+    assert_eq!(config.database, "analytics");
+    assert_eq!(config.query_timeout_secs, Some(30));
+}
+
+#[test]
+fn test_config_rejects_unknown_key() {
+    assert!(ClickhouseConfig::from_toml_str("pasword = \"typo\"").is_err());
+}
+
+#[test]
+fn test_config_from_file() {
+    let path = std::env::temp_dir().join(format!("clickhouse-config-{}.toml", std::process::id()));
+    std::fs::write(&path, "table = \"transfers_v2\"\n").unwrap();
+    let config = ClickhouseConfig::from_file(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(config.table, "transfers_v2");
+
+    assert!(ClickhouseConfig::from_file("clickhouse.yaml").is_err());
+}
+
+#[test]
+fn test_env_overrides_file() {
+    let file =
+        ClickhouseConfig::from_toml_str("user = \"reader\"\npassword = \"from-file\"").unwrap();
+    let config = file
+        .with_env(env(&[
+            ("CLICKHOUSE_PASSWORD", "111"),
+            ("CLICKHOUSE_TABLE", "transfers_v2"),
+            ("CLICKHOUSE_COMPRESSION", "NONE"),
+            ("CLICKHOUSE_CONNECT_TIMEOUT_SECS", "3"),
+            ("HOME", "/root"),
+        ]))
+        .unwrap();
+    assert_eq!(config.user, "reader");
+    assert_eq!(config.password, "111");
+    assert_eq!(config.table, "transfers_v2");
+    assert_eq!(config.compression, CompressionMode::None);
+    assert_eq!(config.connect_timeout_secs, Some(3));
+}
+
+#[test]
+fn test_env_invalid_values() {
+    for vars in [
+        [("CLICKHOUSE_CONNECT_TIMEOUT_SECS", "soon")],
+        [("CLICKHOUSE_QUERY_TIMEOUT_SECS", "0")],
+        [("CLICKHOUSE_COMPRESSION", "zstd")],
+        [("CLICKHOUSE_URL", "localhost:8123")],
+        [("CLICKHOUSE_TABLE", "transfers; DROP TABLE transfers")],
+    ] {
+        let result = ClickhouseConfig::default().with_env(env(&vars));
+        assert!(result.is_err(), "{vars:?} should be rejected");
+    }
+}
+
+#[test]
+fn test_config_debug_hides_password() {
+    let config = ClickhouseConfig {
+        password: "secret".to_string(),
+        ..ClickhouseConfig::default()
+    };
+    assert!(!format!("{config:?}").contains("secret"));
+}
+
+#[tokio::test]
+async fn test_storage_and_stats_share_configured_client() {
+    let mock = Mock::new();
+    let config = ClickhouseConfig {
+        url: mock.url().to_string(),
+        table: "transfers_v2".to_string(),
+        connect_timeout_secs: Some(1),
+        query_timeout_secs: Some(10),
+        // The mock answers uncompressed.
+        compression: CompressionMode::None,
+        ..ClickhouseConfig::default()
+    };
+    let client = ClickhouseClient::from_config(&config).unwrap();
+    let storage = ClickhouseStorage::new(client.clone());
+    assert_eq!(storage.client().table, "transfers_v2");
+    let sql = TransferQuery::new()
+        .to_clickhouse(&storage.client().client, &storage.client().table)
+        .sql_display()
+        .to_string();
+    assert!(sql.contains("FROM transfers_v2"), "{sql}");
+
+    mock.add(handlers::provide(vec![Transfer {
+        ts: 1,
+        address_from: "A".to_string(),
+        address_to: "B".to_string(),
+        token: DEFAULT_TOKEN.to_string(),
+        amount: Decimal::from(2),
+        usd_price: Decimal::from(3),
+        block_number: 1,
+        tx_hash: "0x01".to_string(),
+        log_index: 0,
+    }]));
+    let transfers = storage
+        .query_transfers(&TransferQuery::new())
+        .await
+        .unwrap();
+    assert_eq!(transfers.len(), 1);
+
+    mock.add(handlers::provide(vec![Count { count: 1 }]));
+    mock.add(handlers::provide(
+        migrate::migrations()
+            .iter()
+            .map(|m| Version { version: m.version })
+            .collect::<Vec<_>>(),
+    ));
+    mock.add(handlers::provide(Vec::<UserStats>::new()));
+    let stats = calculate_user_stats_clickhouse(&client).await.unwrap();
+    assert!(stats.is_empty());
+}
+
+#[test]
+fn test_example_config_is_valid() {
+    let config = ClickhouseConfig::from_file("clickhouse.example.toml").unwrap();
+    assert_eq!(config.password, "111");
+    assert!(config.client().is_ok());
+}
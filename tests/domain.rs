@@ -1,5 +1,4 @@
-use rust_challenge::model::{Transfer, UserStats};
-use serde_json;
+use rust_challenge::model::{Decimal, Transfer, UserStats, DEFAULT_TOKEN};
 
 #[test]
 fn test_transfer_creation() {
@@ -7,15 +6,19 @@ fn test_transfer_creation() {
         ts: 123,
         address_from: "A".to_string(),
         address_to: "B".to_string(),
-        amount: 42.0,
-        usd_price: 1.5,
+        token: DEFAULT_TOKEN.to_string(),
+        amount: Decimal::from(42),
+        usd_price: Decimal::new(15, 1),
+        block_number: 0,
+        tx_hash: "0x01".to_string(),
+        log_index: 0,
     };
 
     assert_eq!(t.ts, 123);
     assert_eq!(t.address_from, "A");
     assert_eq!(t.address_to, "B");
-    assert_eq!(t.amount, 42.0);
-    assert_eq!(t.usd_price, 1.5);
+    assert_eq!(t.amount, Decimal::from(42));
+    assert_eq!(t.usd_price, Decimal::new(15, 1));
 }
 
 #[test]
@@ -24,13 +27,17 @@ fn test_transfer_zero_and_negative() {
         ts: 0,
         address_from: "".to_string(),
         address_to: "".to_string(),
-        amount: 0.0,
-        usd_price: -1.0,
+        token: DEFAULT_TOKEN.to_string(),
+        amount: Decimal::from(0),
+        usd_price: Decimal::from(-1),
+        block_number: 0,
+        tx_hash: "0x01".to_string(),
+        log_index: 0,
     };
 
     assert_eq!(t.ts, 0);
-    assert_eq!(t.amount, 0.0);
-    assert_eq!(t.usd_price, -1.0);
+    assert_eq!(t.amount, Decimal::from(0));
+    assert_eq!(t.usd_price, Decimal::from(-1));
 }
 
 #[test]
@@ -39,13 +46,17 @@ fn test_transfer_large_values() {
         ts: u64::MAX,
         address_from: "X".repeat(1000),
         address_to: "Y".repeat(1000),
-        amount: f64::MAX,
-        usd_price: f64::MAX,
+        token: DEFAULT_TOKEN.to_string(),
+        amount: Decimal::MAX,
+        usd_price: Decimal::MAX,
+        block_number: 0,
+        tx_hash: "0x01".to_string(),
+        log_index: 0,
     };
 
     assert_eq!(t.ts, u64::MAX);
-    assert_eq!(t.amount, f64::MAX);
-    assert_eq!(t.usd_price, f64::MAX);
+    assert_eq!(t.amount, Decimal::MAX);
+    assert_eq!(t.usd_price, Decimal::MAX);
 }
 
 #[test]
@@ -54,8 +65,12 @@ fn test_transfer_serde() {
         ts: 1,
         address_from: "A".to_string(),
         address_to: "B".to_string(),
-        amount: 2.0,
-        usd_price: 3.0,
+        token: DEFAULT_TOKEN.to_string(),
+        amount: Decimal::from(2),
+        usd_price: Decimal::from(3),
+        block_number: 0,
+        tx_hash: "0x01".to_string(),
+        log_index: 0,
     };
 
     let json = serde_json::to_string(&t).unwrap();
@@ -71,27 +86,29 @@ fn test_transfer_serde() {
 fn test_user_stats_creation() {
     let s = UserStats {
         address: "A".to_string(),
-        total_volume: 10.0,
-        avg_buy_price: 1.0,
-        avg_sell_price: 2.0,
-        max_balance: 5.0,
+        token: DEFAULT_TOKEN.to_string(),
+        total_volume: Decimal::from(10),
+        avg_buy_price: Decimal::from(1),
+        avg_sell_price: Decimal::from(2),
+        max_balance: Decimal::from(5),
     };
 
     assert_eq!(s.address, "A");
-    assert_eq!(s.total_volume, 10.0);
-    assert_eq!(s.avg_buy_price, 1.0);
-    assert_eq!(s.avg_sell_price, 2.0);
-    assert_eq!(s.max_balance, 5.0);
+    assert_eq!(s.total_volume, Decimal::from(10));
+    assert_eq!(s.avg_buy_price, Decimal::from(1));
+    assert_eq!(s.avg_sell_price, Decimal::from(2));
+    assert_eq!(s.max_balance, Decimal::from(5));
 }
 
 #[test]
 fn test_user_stats_serde() {
     let s = UserStats {
         address: "A".to_string(),
-        total_volume: 10.0,
-        avg_buy_price: 1.0,
-        avg_sell_price: 2.0,
-        max_balance: 5.0,
+        token: DEFAULT_TOKEN.to_string(),
+        total_volume: Decimal::from(10),
+        avg_buy_price: Decimal::from(1),
+        avg_sell_price: Decimal::from(2),
+        max_balance: Decimal::from(5),
     };
 
     let json = serde_json::to_string(&s).unwrap();
@@ -102,3 +119,82 @@ fn test_user_stats_serde() {
     assert_eq!(s.avg_sell_price, s2.avg_sell_price);
     assert_eq!(s.max_balance, s2.max_balance);
 }
+
+#[test]
+fn test_decimal_parse_and_display() {
+    let d: Decimal = "42.50".parse().unwrap();
+    assert_eq!(d, Decimal::new(425, 1));
+    assert_eq!(d.to_string(), "42.5");
+    assert_eq!(
+        "-0.000000000000000001".parse::<Decimal>().unwrap(),
+        Decimal::from_raw(-1)
+    );
+    assert_eq!(Decimal::from(7).to_string(), "7");
+    assert_eq!(format!("{:.2}", Decimal::new(12345, 3)), "12.35");
+    assert_eq!(format!("{:>6}", Decimal::new(-15, 1)), "  -1.5");
+
+    assert!("".parse::<Decimal>().is_err());
+    assert!("1.2.3".parse::<Decimal>().is_err());
+    assert!("1e5".parse::<Decimal>().is_err());
+    assert!("0.0000000000000000001".parse::<Decimal>().is_err());
+}
+
+#[test]
+fn test_decimal_extremes_round_trip() {
+    for d in [Decimal::MIN, Decimal::MAX, -Decimal::MAX] {
+        assert_eq!(d.to_string().parse::<Decimal>().unwrap(), d);
+    }
+    assert_eq!(
+        Decimal::MIN.to_string(),
+        "-170141183460469231731.687303715884105728"
+    );
+    assert!("170141183460469231731.687303715884105728"
+        .parse::<Decimal>()
+        .is_err());
+    assert!("-170141183460469231731.687303715884105729"
+        .parse::<Decimal>()
+        .is_err());
+}
+
+#[test]
+fn test_decimal_arithmetic_truncates() {
+    let third = Decimal::ONE.checked_div(Decimal::from(3)).unwrap();
+    assert_eq!(third.to_string(), "0.333333333333333333");
+    assert_eq!(
+        third.checked_mul(Decimal::from(3)).unwrap().to_string(),
+        "0.999999999999999999"
+    );
+    assert_eq!(Decimal::new(1, 1) + Decimal::new(2, 1), Decimal::new(3, 1));
+    assert!(Decimal::ONE.checked_div(Decimal::ZERO).is_none());
+    assert!(Decimal::MAX.checked_add(Decimal::ONE).is_none());
+    assert_eq!(-Decimal::MAX, Decimal::MIN + Decimal::from_raw(1));
+    assert!(Decimal::MIN.checked_neg().is_none());
+}
+
+#[test]
+#[should_panic(expected = "decimal overflow")]
+fn test_decimal_neg_min_panics() {
+    let _ = -Decimal::MIN;
+}
+
+#[test]
+fn test_decimal_serde_json() {
+    let d = Decimal::new(125, 2);
+    assert_eq!(serde_json::to_string(&d).unwrap(), r#""1.25""#);
+    assert_eq!(serde_json::from_str::<Decimal>(r#""1.25""#).unwrap(), d);
+    assert_eq!(serde_json::from_str::<Decimal>("1.25").unwrap(), d);
+    assert_eq!(
+        serde_json::from_str::<Decimal>("3").unwrap(),
+        Decimal::from(3)
+    );
+    assert!(serde_json::from_str::<Decimal>(r#""abc""#).is_err());
+}
+
+#[test]
+fn test_decimal_from_f64() {
+    assert_eq!(Decimal::from_f64(0.1), Some(Decimal::new(1, 1)));
+    assert_eq!(Decimal::from_f64(-2.5), Some(Decimal::new(-25, 1)));
+    assert_eq!(Decimal::from_f64(f64::NAN), None);
+    assert_eq!(Decimal::from_f64(f64::INFINITY), None);
+    assert_eq!(Decimal::from_f64(1e30), None);
+}
@@ -0,0 +1,344 @@
+use rust_challenge::dump::{
+    load, write_transfers, OnInvalidRow, RowError, TransferFormat, TransferReader, TRANSFER_COLUMNS,
+};
+use rust_challenge::generator::{DefaultTransferGenerator, TransferGenConfig};
+use rust_challenge::model::{Decimal, Transfer, DEFAULT_TOKEN};
+use rust_challenge::stats::calculate_user_stats_rust;
+use rust_challenge::storage::{InMemoryStorage, TransferStore};
+
+fn generated(count: usize) -> Vec<Transfer> {
+    DefaultTransferGenerator {
+        config: TransferGenConfig {
+            seed: Some(3),
+            now: Some(1_700_000_000),
+            ..TransferGenConfig::default()
+        },
+    }
+    .generate_batch(count)
+    .unwrap()
+    .transfers
+}
+
+fn dump(transfers: &[Transfer], format: TransferFormat) -> String {
+    let mut out = vec![];
+    let rows = write_transfers(&mut out, format, transfers.iter().cloned().map(Ok)).unwrap();
+    assert_eq!(rows, transfers.len() as u64);
+    String::from_utf8(out).unwrap()
+}
+
+fn read(text: &str, format: TransferFormat) -> Vec<Result<Transfer, RowError>> {
+    TransferReader::new(text.as_bytes(), format)
+        .unwrap()
+        .collect()
+}
+
+fn json(transfers: &[Transfer]) -> serde_json::Value {
+    serde_json::to_value(transfers).unwrap()
+}
+
+fn row_error(line: u64, field: Option<&str>, reason: &str) -> RowError {
+    RowError {
+        line,
+        field: field.map(str::to_string),
+        reason: reason.to_string(),
+    }
+}
+
+fn one(tx_hash: &str) -> Transfer {
+    Transfer {
+        ts: 1,
+        address_from: "A".to_string(),
+        address_to: "B".to_string(),
+        token: DEFAULT_TOKEN.to_string(),
+        amount: Decimal::from(10),
+        usd_price: "1.5".parse().unwrap(),
+        block_number: 1,
+        tx_hash: tx_hash.to_string(),
+        log_index: 0,
+    }
+}
+
+#[test]
+fn test_round_trip() {
+    let transfers = generated(200);
+    for format in [TransferFormat::Csv, TransferFormat::Ndjson] {
+        let text = dump(&transfers, format);
+        let back: Vec<Transfer> = read(&text, format)
+            .into_iter()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(json(&back), json(&transfers), "{format}");
+    }
+}
+
+#[test]
+fn test_csv_layout() {
+    let text = dump(&[one("0x01")], TransferFormat::Csv);
+    assert_eq!(
+        text,
+        "ts,address_from,address_to,token,amount,usd_price,block_number,tx_hash,log_index\n\
+         1,A,B,TKN,10,1.5,1,0x01,0\n"
+    );
+    assert_eq!(
+        dump(&[], TransferFormat::Csv),
+        format!("{}\n", TRANSFER_COLUMNS.join(","))
+    );
+}
+
+#[test]
+fn test_csv_quoting_and_line_numbers() {
+    let mut odd = one("0x01");
+    odd.address_from = "a,\"b\"\nc".to_string();
+    let text = format!(
+        "{}1,A,B,TKN,x,1,1,0x02,0\n",
+        dump(&[odd.clone()], TransferFormat::Csv)
+    );
+    let rows = read(&text, TransferFormat::Csv);
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[0].as_ref().unwrap().address_from, odd.address_from);
+    // The quoted line break puts the second row on line 4.
+    assert_eq!(
+        rows[1].as_ref().unwrap_err(),
+        &row_error(4, Some("amount"), "invalid decimal: x")
+    );
+}
+
+#[test]
+fn test_csv_columns_in_any_order() {
+    let text = "\
+log_index,tx_hash,block_number,usd_price,amount,token,address_to,address_from,ts
+
+0,0x01,1,1.5,10,TKN,B,A,1
+";
+    let rows = read(text, TransferFormat::Csv);
+    assert_eq!(json(&[rows[0].clone().unwrap()]), json(&[one("0x01")]));
+}
+
+#[test]
+fn test_csv_header_errors() {
+    let header = |text: &str| {
+        TransferReader::new(text.as_bytes(), TransferFormat::Csv)
+            .err()
+            .unwrap()
+    };
+    let mut columns = TRANSFER_COLUMNS.to_vec();
+    assert_eq!(header(""), row_error(1, None, "missing CSV header"));
+
+    columns.push("fee");
+    assert_eq!(
+        header(&columns.join(",")),
+        row_error(1, Some("fee"), "unknown column")
+    );
+
+    columns.pop();
+    columns.push("ts");
+    assert_eq!(
+        header(&format!("\n{}", columns.join(","))),
+        row_error(2, Some("ts"), "duplicate column")
+    );
+
+    columns.truncate(7);
+    assert_eq!(
+        header(&columns.join(",")),
+        row_error(1, Some("tx_hash"), "missing column")
+    );
+}
+
+#[test]
+fn test_csv_row_errors_keep_reading() {
+    let text = format!(
+        "{}\n\
+         1,A,B,TKN,10,1.5,1,0x01,0\n\
+         1,A,B,TKN,10,1.5\n\
+         x,A,B,TKN,10,1.5,1,0x02,0\n\
+         1,,B,TKN,10,1.5,1,0x03,0\n\
+         1,A,B,TKN,10,1.5,1,0x04,4294967296\n\
+         1,A,\"B\"x,TKN,10,1.5,1,0x05,0\n\
+         1,A,\"B,TKN,10,1.5,1,0x06,0\n",
+        TRANSFER_COLUMNS.join(",")
+    );
+    let rows = read(&text, TransferFormat::Csv);
+    assert!(rows[0].is_ok());
+    let errors: Vec<RowError> = rows.into_iter().skip(1).map(Result::unwrap_err).collect();
+    assert_eq!(
+        errors,
+        vec![
+            row_error(3, None, "expected 9 fields, got 6"),
+            row_error(4, Some("ts"), "expected a non-negative integer, got \"x\""),
+            row_error(5, Some("address_from"), "must not be empty"),
+            row_error(6, Some("log_index"), "4294967296 is out of range"),
+            row_error(7, None, "unexpected text after a closing quote"),
+            row_error(8, None, "unterminated quoted field"),
+        ]
+    );
+    assert_eq!(
+        errors[1].to_string(),
+        "line 4, field ts: expected a non-negative integer, got \"x\""
+    );
+}
+
+#[test]
+fn test_ndjson_row_errors() {
+    let text = r#"{"ts":1,"address_from":"A","address_to":"B","token":"TKN","amount":10,"usd_price":1.5,"block_number":1,"tx_hash":"0x01","log_index":0}
+{"ts":1,"address_from":"A"
+
+{"ts":1,"address_from":"A","address_to":"B","token":"TKN","amount":"10","usd_price":"1.5","block_number":1,"tx_hash":"0x02","log_index":0,"fee":1}
+{"ts":1,"address_from":"A","address_to":"B","token":"TKN","usd_price":"1.5","block_number":1,"tx_hash":"0x03","log_index":0}
+{"ts":1,"address_from":"A","address_to":["B"],"token":"TKN","amount":"10","usd_price":"1.5","block_number":1,"tx_hash":"0x04","log_index":0}
+"#;
+    let rows = read(text, TransferFormat::Ndjson);
+    assert_eq!(rows.len(), 5);
+    assert_eq!(json(&[rows[0].clone().unwrap()]), json(&[one("0x01")]));
+    let errors: Vec<RowError> = rows.into_iter().skip(1).map(Result::unwrap_err).collect();
+    assert_eq!(errors[0].line, 2);
+    assert!(errors[0].reason.starts_with("invalid JSON object"));
+    assert_eq!(errors[1], row_error(4, Some("fee"), "unknown field"));
+    assert_eq!(errors[2], row_error(5, Some("amount"), "missing"));
+    assert_eq!(
+        errors[3],
+        row_error(6, Some("address_to"), "expected a string or number")
+    );
+}
+
+#[test]
+fn test_ndjson_exponent_numbers() {
+    let row = |amount: &str, usd_price: &str| {
+        format!(
+            r#"{{"ts":1,"address_from":"A","address_to":"B","token":"TKN","amount":{amount},"usd_price":{usd_price},"block_number":1,"tx_hash":"0x01","log_index":0}}"#
+        )
+    };
+    let text = [
+        row("1e-7", "1.5E+2"),
+        row("2.5e15", "-3e-1"),
+        row("1e30", "1"),
+        row("0.123456789012345678", "123456789.987654321012345678"),
+        row("1e999999999", "1"),
+    ]
+    .join("\n");
+    let rows = read(&text, TransferFormat::Ndjson);
+    let first = rows[0].as_ref().unwrap();
+    assert_eq!(first.amount, "0.0000001".parse::<Decimal>().unwrap());
+    assert_eq!(first.usd_price, Decimal::from(150));
+    let second = rows[1].as_ref().unwrap();
+    assert_eq!(second.amount, Decimal::from(2_500_000_000_000_000_i64));
+    assert_eq!(second.usd_price, "-0.3".parse::<Decimal>().unwrap());
+    // Out of range, and reported with the expanded number.
+    let error = rows[2].as_ref().unwrap_err();
+    assert_eq!(
+        *error,
+        row_error(
+            3,
+            Some("amount"),
+            &format!("invalid decimal: 1{}", "0".repeat(30))
+        )
+    );
+    // Every digit is kept, none is rounded through f64.
+    let exact = rows[3].as_ref().unwrap();
+    assert_eq!(exact.amount.to_string(), "0.123456789012345678");
+    assert_eq!(exact.usd_price.to_string(), "123456789.987654321012345678");
+    assert_eq!(
+        *rows[4].as_ref().unwrap_err(),
+        row_error(5, Some("amount"), "invalid decimal: 1e999999999")
+    );
+}
+
+#[test]
+fn test_format_names() {
+    assert_eq!(
+        TransferFormat::from_path("dump.csv"),
+        Some(TransferFormat::Csv)
+    );
+    assert_eq!(
+        TransferFormat::from_path("dump.jsonl"),
+        Some(TransferFormat::Ndjson)
+    );
+    assert_eq!(TransferFormat::from_path("dump"), None);
+    assert_eq!(
+        "NDJSON".parse::<TransferFormat>().unwrap(),
+        TransferFormat::Ndjson
+    );
+    assert!("parquet".parse::<TransferFormat>().is_err());
+}
+
+#[test]
+fn test_dump_feeds_stats() {
+    let transfers = generated(300);
+    let text = dump(&transfers, TransferFormat::Csv);
+    let read: Vec<Transfer> = TransferReader::new(text.as_bytes(), TransferFormat::Csv)
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    let sorted = |mut stats: Vec<_>| {
+        let mut json = serde_json::to_value(&mut stats).unwrap();
+        json.as_array_mut()
+            .unwrap()
+            .sort_by_key(|s| (s["address"].to_string(), s["token"].to_string()));
+        json
+    };
+    assert_eq!(
+        sorted(calculate_user_stats_rust(&read).unwrap()),
+        sorted(calculate_user_stats_rust(&transfers).unwrap())
+    );
+}
+
+fn mixed_dump() -> String {
+    format!(
+        "{}\n{}\n{}",
+        dump(&[one("0x01")], TransferFormat::Ndjson).trim(),
+        "not json",
+        dump(&[one("0x02")], TransferFormat::Ndjson)
+    )
+}
+
+#[tokio::test]
+async fn test_load_skips_invalid_rows() {
+    let storage = InMemoryStorage::new();
+    let text = mixed_dump();
+    let rows = TransferReader::new(text.as_bytes(), TransferFormat::Ndjson).unwrap();
+    let report = load(&storage, rows, OnInvalidRow::Skip).await.unwrap();
+    assert_eq!(report.inserted, 2);
+    assert_eq!(report.invalid.len(), 1);
+    assert_eq!(report.invalid[0].line, 2);
+    assert_eq!(storage.get_transfers().await.unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn test_load_aborts_at_first_invalid_row() {
+    let storage = InMemoryStorage::new();
+    let text = mixed_dump();
+    let rows = TransferReader::new(text.as_bytes(), TransferFormat::Ndjson).unwrap();
+    let error = load(&storage, rows, OnInvalidRow::Abort).await.unwrap_err();
+    assert_eq!(error.downcast_ref::<RowError>().unwrap().line, 2);
+    assert!(
+        error.to_string().contains("after inserting 1 rows"),
+        "{error}"
+    );
+    let stored = storage.get_transfers().await.unwrap();
+    assert_eq!(stored.len(), 1);
+    assert_eq!(stored[0].tx_hash, "0x01");
+}
+
+#[test]
+fn test_csv_stray_quote_gives_up() {
+    let mut text = format!(
+        "{}\n\
+         1,A,B,TKN,10,1.5,1,0x01,0\n\
+         1,A,\"B,TKN,10,1.5,1,0x02,0\n",
+        TRANSFER_COLUMNS.join(",")
+    );
+    for i in 0..4000 {
+        text.push_str(&format!("1,A,B,TKN,10,1.5,1,0x{i:x},0\n"));
+    }
+    let rows = read(&text, TransferFormat::Csv);
+    assert!(rows[0].is_ok());
+    assert_eq!(
+        rows[1].as_ref().unwrap_err(),
+        &row_error(
+            3,
+            None,
+            "unterminated quoted field, gave up after 65536 bytes"
+        )
+    );
+    assert!(rows.len() > 1000);
+    assert!(rows[2..].iter().all(Result::is_ok));
+}
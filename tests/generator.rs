@@ -1,4 +1,11 @@
-use rust_challenge::generator::{DefaultTransferGenerator, TransferGenConfig, TransferGenerator};
+use rust_challenge::generator::{
+    AddressActivity, BalanceMode, ConfigError, DefaultTransferGenerator, PriceModel, Scenario,
+    ScenarioGenerator, ScenarioKind, TransferGenConfig, TransferGenerator, BLOCK_TIME_SECS,
+    MAX_ZIPF_EXPONENT, MINT_ADDRESS,
+};
+use rust_challenge::model::{Decimal, Transfer, DEFAULT_TOKEN};
+use rust_challenge::stats::{calculate_balance_history, NegativeBalancePolicy};
+use std::collections::{HashMap, HashSet};
 
 #[test]
 fn test_generate_zero() {
@@ -24,11 +31,12 @@ fn test_generate_many() {
 #[test]
 fn test_amount_and_price_ranges() {
     let config = TransferGenConfig {
-        min_amount: 10.0,
-        max_amount: 20.0,
-        min_price: 1.0,
-        max_price: 2.0,
+        min_amount: Decimal::from(10),
+        max_amount: Decimal::from(20),
+        min_price: Decimal::from(1),
+        max_price: Decimal::from(2),
         max_age_secs: 100,
+        ..TransferGenConfig::default()
     };
 
     let gen = DefaultTransferGenerator { config };
@@ -36,13 +44,13 @@ fn test_amount_and_price_ranges() {
 
     for t in &transfers {
         assert!(
-            t.amount >= 10.0 && t.amount < 20.0,
+            t.amount >= Decimal::from(10) && t.amount < Decimal::from(20),
             "amount out of range: {}",
             t.amount
         );
 
         assert!(
-            t.usd_price >= 1.0 && t.usd_price < 2.0,
+            t.usd_price >= Decimal::from(1) && t.usd_price < Decimal::from(2),
             "usd_price out of range: {}",
             t.usd_price
         );
@@ -52,64 +60,794 @@ fn test_amount_and_price_ranges() {
 #[test]
 fn test_min_equals_max_amount() {
     let config = TransferGenConfig {
-        min_amount: 42.0,
-        max_amount: 42.0,
-        min_price: 1.0,
-        max_price: 2.0,
+        min_amount: Decimal::from(42),
+        max_amount: Decimal::from(42),
+        min_price: Decimal::from(1),
+        max_price: Decimal::from(2),
         max_age_secs: 100,
+        ..TransferGenConfig::default()
     };
 
     let gen = DefaultTransferGenerator { config };
     let transfers = gen.generate(10).unwrap();
 
     for t in &transfers {
-        assert_eq!(t.amount, 42.0);
+        assert_eq!(t.amount, Decimal::from(42));
     }
 }
 
 #[test]
 fn test_min_equals_max_price() {
     let config = TransferGenConfig {
-        min_amount: 1.0,
-        max_amount: 2.0,
-        min_price: 3.14,
-        max_price: 3.14,
+        min_amount: Decimal::from(1),
+        max_amount: Decimal::from(2),
+        min_price: Decimal::new(314, 2),
+        max_price: Decimal::new(314, 2),
         max_age_secs: 100,
+        ..TransferGenConfig::default()
     };
 
     let gen = DefaultTransferGenerator { config };
     let transfers = gen.generate(10).unwrap();
 
     for t in &transfers {
-        assert_eq!(t.usd_price, 3.14);
+        assert_eq!(t.usd_price, Decimal::new(314, 2));
     }
 }
 
 #[test]
-#[should_panic]
 fn test_invalid_range_amount() {
     let config = TransferGenConfig {
-        min_amount: 1000.0,
-        max_amount: 5.0,
-        min_price: 1.0,
-        max_price: 2.0,
+        min_amount: Decimal::from(1000),
+        max_amount: Decimal::from(5),
+        min_price: Decimal::from(1),
+        max_price: Decimal::from(2),
         max_age_secs: 100,
+        ..TransferGenConfig::default()
     };
 
     let gen = DefaultTransferGenerator { config };
-    let _ = gen.generate(1).unwrap();
+    let err = gen.generate(1).unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<ConfigError>(),
+        Some(&ConfigError::InvertedRange {
+            field: "amount",
+            min: Decimal::from(1000),
+            max: Decimal::from(5),
+        })
+    );
 }
 
 #[test]
-#[should_panic]
 fn test_invalid_range_price() {
     let config = TransferGenConfig {
-        min_amount: 1.0,
-        max_amount: 2.0,
-        min_price: 10000.0,
-        max_price: 5.0,
+        min_amount: Decimal::from(1),
+        max_amount: Decimal::from(2),
+        min_price: Decimal::from(10000),
+        max_price: Decimal::from(5),
         max_age_secs: 100,
+        ..TransferGenConfig::default()
     };
     let gen = DefaultTransferGenerator { config };
-    let _ = gen.generate(1);
+    let err = gen.generate(1).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ConfigError>(),
+        Some(ConfigError::InvertedRange { field: "price", .. })
+    ));
+}
+
+fn seeded(seed: u64) -> DefaultTransferGenerator {
+    DefaultTransferGenerator {
+        config: TransferGenConfig {
+            seed: Some(seed),
+            now: Some(1_700_000_000),
+            ..TransferGenConfig::default()
+        },
+    }
+}
+
+#[test]
+fn test_same_seed_same_transfers() {
+    let first = seeded(7).generate(200).unwrap();
+    let second = seeded(7).generate(200).unwrap();
+    assert_eq!(
+        serde_json::to_string(&first).unwrap(),
+        serde_json::to_string(&second).unwrap()
+    );
+
+    let other = seeded(8).generate(200).unwrap();
+    assert_ne!(
+        serde_json::to_string(&first).unwrap(),
+        serde_json::to_string(&other).unwrap()
+    );
+}
+
+#[test]
+fn test_reported_seed_reproduces_run() {
+    let run = DefaultTransferGenerator::default()
+        .generate_batch(50)
+        .unwrap();
+    let replay = DefaultTransferGenerator {
+        config: TransferGenConfig {
+            seed: Some(run.seed),
+            now: Some(run.now),
+            ..TransferGenConfig::default()
+        },
+    }
+    .generate_batch(50)
+    .unwrap();
+    assert_eq!(
+        serde_json::to_string(&run.transfers).unwrap(),
+        serde_json::to_string(&replay.transfers).unwrap()
+    );
+}
+
+#[test]
+fn test_fixed_now_bounds_ts() {
+    let gen = seeded(1);
+    let batch = gen.generate_batch(500).unwrap();
+    assert_eq!(batch.now, 1_700_000_000);
+    for t in &batch.transfers {
+        assert!(t.ts <= batch.now);
+        assert!(t.ts > batch.now - gen.config.max_age_secs);
+    }
+}
+
+fn activity_counts(config: TransferGenConfig, count: usize) -> Vec<usize> {
+    let transfers = DefaultTransferGenerator { config }.generate(count).unwrap();
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for t in &transfers {
+        *counts.entry(t.address_from.clone()).or_default() += 1;
+        *counts.entry(t.address_to.clone()).or_default() += 1;
+    }
+    let mut counts: Vec<usize> = counts.into_values().collect();
+    counts.sort_unstable_by(|a, b| b.cmp(a));
+    counts
+}
+
+#[test]
+fn test_addresses_come_from_pool() {
+    let transfers = DefaultTransferGenerator {
+        config: TransferGenConfig {
+            address_count: 20,
+            seed: Some(3),
+            ..TransferGenConfig::default()
+        },
+    }
+    .generate(1000)
+    .unwrap();
+    let addresses: HashSet<&str> = transfers
+        .iter()
+        .flat_map(|t| [t.address_from.as_str(), t.address_to.as_str()])
+        .collect();
+    assert!(addresses.len() <= 20);
+    assert!(transfers.iter().all(|t| t.address_from != t.address_to));
+}
+
+#[test]
+fn test_zipf_activity_has_whales() {
+    let counts = activity_counts(
+        TransferGenConfig {
+            address_count: 1000,
+            activity: AddressActivity::Zipf { exponent: 1.1 },
+            seed: Some(5),
+            ..TransferGenConfig::default()
+        },
+        10_000,
+    );
+    let legs: usize = counts.iter().sum();
+    // The ten most active addresses take a large share of all legs.
+    let top: usize = counts.iter().take(10).sum();
+    assert!(top * 4 > legs, "top 10 = {top} of {legs}");
+
+    let uniform = activity_counts(
+        TransferGenConfig {
+            address_count: 1000,
+            activity: AddressActivity::Uniform,
+            seed: Some(5),
+            ..TransferGenConfig::default()
+        },
+        10_000,
+    );
+    let top_uniform: usize = uniform.iter().take(10).sum();
+    assert!(top_uniform * 10 < legs, "top 10 = {top_uniform} of {legs}");
+}
+
+#[test]
+fn test_empty_address_pool_is_rejected() {
+    let gen = DefaultTransferGenerator {
+        config: TransferGenConfig {
+            address_count: 0,
+            ..TransferGenConfig::default()
+        },
+    };
+    assert!(gen.generate(1).is_err());
+}
+
+fn ledger_config(genesis_supply: Decimal) -> TransferGenConfig {
+    TransferGenConfig {
+        address_count: 200,
+        max_age_secs: 50,
+        balance_mode: BalanceMode::Constrained { genesis_supply },
+        seed: Some(11),
+        now: Some(1_700_000_000),
+        ..TransferGenConfig::default()
+    }
+}
+
+#[test]
+fn test_constrained_ledger_never_overdraws() {
+    let config = ledger_config(Decimal::from(100_000));
+    let transfers = DefaultTransferGenerator { config }.generate(5000).unwrap();
+
+    let mints = transfers
+        .iter()
+        .take_while(|t| t.address_from == MINT_ADDRESS)
+        .count();
+    assert_eq!(mints, 200);
+    assert_eq!(transfers.len(), 5200);
+    // The genesis mint is a single transaction.
+    assert!(transfers[..mints]
+        .iter()
+        .enumerate()
+        .all(|(i, t)| t.tx_hash == transfers[0].tx_hash && t.log_index == i as u32));
+    assert_unique_ids(&transfers);
+    assert!(transfers.windows(2).all(|w| w[0].ts <= w[1].ts));
+    assert!(transfers.iter().all(|t| t.amount.is_positive()));
+
+    let history = calculate_balance_history(&transfers, NegativeBalancePolicy::Allow).unwrap();
+    for (address, tokens) in &history {
+        if address != MINT_ADDRESS {
+            assert!(
+                tokens[DEFAULT_TOKEN]
+                    .iter()
+                    .all(|(_, balance)| !balance.is_negative()),
+                "{address} overdrawn"
+            );
+        }
+    }
+    assert_eq!(
+        history[MINT_ADDRESS][DEFAULT_TOKEN].last().unwrap().1,
+        -Decimal::from(100_000)
+    );
+}
+
+#[test]
+fn test_constrained_ledger_with_now_before_max_age() {
+    let config = TransferGenConfig {
+        now: Some(20),
+        ..ledger_config(Decimal::from(100_000))
+    };
+    let transfers = DefaultTransferGenerator { config }.generate(500).unwrap();
+    let (mints, rest) = transfers.split_at(200);
+    assert!(mints
+        .iter()
+        .all(|t| t.address_from == MINT_ADDRESS && t.ts == 0));
+    assert!(rest.iter().all(|t| (1..=20).contains(&t.ts)));
+}
+
+#[test]
+fn test_constrained_ledger_needs_supply() {
+    let config = ledger_config(Decimal::ZERO);
+    let err = DefaultTransferGenerator { config }
+        .generate(10)
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ConfigError>(),
+        Some(ConfigError::NotPositive {
+            field: "balance_mode.genesis_supply",
+            ..
+        })
+    ));
+}
+
+fn priced(price_model: PriceModel) -> Vec<Transfer> {
+    let mut transfers = DefaultTransferGenerator {
+        config: TransferGenConfig {
+            price_model,
+            max_age_secs: 86_400 * 365,
+            seed: Some(21),
+            now: Some(1_700_000_000),
+            ..TransferGenConfig::default()
+        },
+    }
+    .generate(2000)
+    .unwrap();
+    transfers.sort_by_key(|t| t.ts);
+    transfers
+}
+
+#[test]
+fn test_gbm_prices_follow_a_path() {
+    let transfers = priced(PriceModel::Gbm {
+        start: Decimal::from(100),
+        drift: 0.0,
+        volatility: 0.5,
+    });
+    assert!(transfers.iter().all(|t| t.usd_price.is_positive()));
+    // Neighbouring transfers are priced off nearby path points, so prices move in small steps.
+    let max_jump = transfers
+        .windows(2)
+        .map(|w| {
+            (w[1].usd_price.to_f64() / w[0].usd_price.to_f64())
+                .ln()
+                .abs()
+        })
+        .fold(0.0, f64::max);
+    assert!(max_jump < 0.2, "max log jump {max_jump}");
+    let distinct: HashSet<Decimal> = transfers.iter().map(|t| t.usd_price).collect();
+    assert!(distinct.len() > 100);
+}
+
+#[test]
+fn test_mean_reverting_prices_stay_near_mean() {
+    let transfers = priced(PriceModel::MeanReverting {
+        start: Decimal::from(5),
+        mean: Decimal::from(1),
+        speed: 50.0,
+        volatility: 0.2,
+    });
+    let late = &transfers[transfers.len() / 2..];
+    assert!(late
+        .iter()
+        .all(|t| t.usd_price > Decimal::new(5, 1) && t.usd_price < Decimal::from(2)));
+}
+
+#[test]
+fn test_replay_prices_from_csv() {
+    let model =
+        PriceModel::replay_from_csv("ts,price\n1699990000,2.5\n1699995000,3\n\n1699980000,1\n")
+            .unwrap();
+    let transfers = DefaultTransferGenerator {
+        config: TransferGenConfig {
+            price_model: model,
+            max_age_secs: 30_000,
+            seed: Some(2),
+            now: Some(1_700_000_000),
+            ..TransferGenConfig::default()
+        },
+    }
+    .generate(500)
+    .unwrap();
+    for t in &transfers {
+        let expected = match t.ts {
+            ..1_699_990_000 => Decimal::from(1),
+            1_699_990_000..1_699_995_000 => Decimal::new(25, 1),
+            _ => Decimal::from(3),
+        };
+        assert_eq!(t.usd_price, expected, "ts {}", t.ts);
+    }
+}
+
+#[test]
+fn test_replay_csv_errors_name_the_line() {
+    let err = PriceModel::replay_from_csv("ts,price\n1,2\n2,abc\n").unwrap_err();
+    assert!(format!("{err:#}").contains("line 3"), "{err:#}");
+    assert!(PriceModel::replay_from_csv("ts,price\n").is_err());
+    assert!(PriceModel::replay_from_csv("1,-2").is_err());
+}
+
+#[test]
+fn test_invalid_price_model_is_rejected() {
+    let gen = DefaultTransferGenerator {
+        config: TransferGenConfig {
+            price_model: PriceModel::Gbm {
+                start: Decimal::ZERO,
+                drift: 0.0,
+                volatility: 0.1,
+            },
+            ..TransferGenConfig::default()
+        },
+    };
+    assert!(gen.generate(1).is_err());
+}
+
+fn scenario_generator(balance_mode: BalanceMode) -> ScenarioGenerator<DefaultTransferGenerator> {
+    ScenarioGenerator::new(DefaultTransferGenerator {
+        config: TransferGenConfig {
+            address_count: 100,
+            balance_mode,
+            seed: Some(4),
+            now: Some(1_700_000_000),
+            ..TransferGenConfig::default()
+        },
+    })
+    .with_seed(9)
+    .with_scenario(Scenario::WashTrading {
+        ring_size: 3,
+        rounds: 4,
+        amount: Decimal::from(50),
+    })
+    .with_scenario(Scenario::WhaleDump {
+        bursts: 2,
+        transfers_per_burst: 5,
+        amount: Decimal::from(10_000),
+    })
+    .with_scenario(Scenario::Airdrop {
+        recipients: 30,
+        amount: Decimal::from(5),
+    })
+    .with_scenario(Scenario::DustSpam {
+        transfers: 20,
+        amount: Decimal::new(1, 9),
+    })
+}
+
+fn assert_unique_ids(transfers: &[Transfer]) {
+    let ids: HashSet<_> = transfers.iter().map(Transfer::id).collect();
+    assert_eq!(ids.len(), transfers.len());
+}
+
+#[test]
+fn test_generated_transfers_have_ids() {
+    let transfers = seeded(3).generate(1000).unwrap();
+    assert_unique_ids(&transfers);
+    for t in &transfers {
+        assert_eq!(t.tx_hash.len(), 66, "{}", t.tx_hash);
+        assert!(t.tx_hash.starts_with("0x"));
+        assert_eq!(t.block_number, t.ts / BLOCK_TIME_SECS);
+        assert_eq!(t.log_index, 0);
+    }
+
+    // Same seed, same ids: regenerating and storing again replaces instead of duplicating.
+    let again = seeded(3).generate(1000).unwrap();
+    assert!(transfers.iter().zip(&again).all(|(a, b)| a.id() == b.id()));
+}
+
+#[test]
+fn test_scenarios_are_labeled() {
+    let labeled = scenario_generator(BalanceMode::Unconstrained)
+        .generate_labeled(1000)
+        .unwrap();
+
+    let count = |kind| labeled.labels.iter().filter(|l| l.kind == kind).count();
+    // Each scenario also has one funding transfer from the mint.
+    assert_eq!(count(ScenarioKind::WashTrading), 1 + 3 * 4);
+    assert_eq!(count(ScenarioKind::WhaleDump), 1 + 2 * 5);
+    assert_eq!(count(ScenarioKind::Airdrop), 1 + 30);
+    assert_eq!(count(ScenarioKind::DustSpam), 1 + 20);
+    assert_eq!(labeled.transfers.len(), 1000 + labeled.labels.len());
+    assert!(labeled.transfers.windows(2).all(|w| w[0].ts <= w[1].ts));
+
+    // A wash-trading ring only moves tokens among its members.
+    let wash: Vec<_> = labeled
+        .labels
+        .iter()
+        .filter(|l| l.kind == ScenarioKind::WashTrading)
+        .map(|l| &labeled.transfers[l.transfer])
+        .filter(|t| t.address_from != MINT_ADDRESS)
+        .collect();
+    let ring: HashSet<&str> = wash.iter().map(|t| t.address_from.as_str()).collect();
+    assert_eq!(ring.len(), 3);
+    assert!(wash.iter().all(|t| ring.contains(t.address_to.as_str())));
+
+    // The airdrop fans out from one address at one moment.
+    let airdrop: Vec<_> = labeled
+        .labels
+        .iter()
+        .filter(|l| l.kind == ScenarioKind::Airdrop)
+        .map(|l| &labeled.transfers[l.transfer])
+        .filter(|t| t.address_from != MINT_ADDRESS)
+        .collect();
+    let senders: HashSet<&str> = airdrop.iter().map(|t| t.address_from.as_str()).collect();
+    let moments: HashSet<u64> = airdrop.iter().map(|t| t.ts).collect();
+    assert_eq!((senders.len(), moments.len()), (1, 1));
+
+    // ... in one transaction, one log per recipient.
+    let hashes: HashSet<&str> = airdrop.iter().map(|t| t.tx_hash.as_str()).collect();
+    let logs: Vec<u32> = airdrop.iter().map(|t| t.log_index).collect();
+    assert_eq!(hashes.len(), 1);
+    assert_eq!(
+        logs.iter().copied().collect::<HashSet<_>>(),
+        (0..30).collect()
+    );
+    assert_unique_ids(&labeled.transfers);
+}
+
+#[test]
+fn test_scenarios_keep_constrained_ledger_consistent() {
+    let transfers = scenario_generator(BalanceMode::Constrained {
+        genesis_supply: Decimal::from(1_000_000),
+    })
+    .generate(2000)
+    .unwrap();
+    let history = calculate_balance_history(&transfers, NegativeBalancePolicy::Allow).unwrap();
+    for (address, tokens) in &history {
+        if address != MINT_ADDRESS {
+            assert!(
+                tokens[DEFAULT_TOKEN]
+                    .iter()
+                    .all(|(_, balance)| !balance.is_negative()),
+                "{address} overdrawn"
+            );
+        }
+    }
+}
+
+#[test]
+fn test_scenarios_are_reproducible() {
+    let first = scenario_generator(BalanceMode::Unconstrained)
+        .generate_labeled(300)
+        .unwrap();
+    let second = scenario_generator(BalanceMode::Unconstrained)
+        .generate_labeled(300)
+        .unwrap();
+    assert_eq!(first.labels, second.labels);
+    assert_eq!(
+        serde_json::to_string(&first.transfers).unwrap(),
+        serde_json::to_string(&second.transfers).unwrap()
+    );
+}
+
+#[test]
+fn test_invalid_scenario_is_rejected() {
+    let gen = ScenarioGenerator::new(DefaultTransferGenerator::default()).with_scenario(
+        Scenario::WashTrading {
+            ring_size: 1,
+            rounds: 1,
+            amount: Decimal::ONE,
+        },
+    );
+    assert!(gen.generate(10).is_err());
+}
+
+#[test]
+fn test_iter_matches_generate() {
+    let gen = seeded(13);
+    let batch = gen.generate(300).unwrap();
+    let lazy: Vec<Transfer> = gen
+        .generate_iter(300)
+        .collect::<anyhow::Result<_>>()
+        .unwrap();
+    assert_eq!(
+        serde_json::to_string(&batch).unwrap(),
+        serde_json::to_string(&lazy).unwrap()
+    );
+}
+
+#[test]
+fn test_iter_is_lazy() {
+    let gen = seeded(13);
+    let mut transfers = gen.transfers(usize::MAX).unwrap();
+    assert_eq!(transfers.seed(), 13);
+    assert_eq!(transfers.by_ref().take(1000).count(), 1000);
+    assert!(transfers.next().is_some());
+}
+
+#[test]
+fn test_ts_ordered_iter() {
+    let gen = DefaultTransferGenerator {
+        config: TransferGenConfig {
+            ts_ordered: true,
+            max_age_secs: 1000,
+            ..seeded(17).config
+        },
+    };
+    let transfers: Vec<Transfer> = gen.generate_iter(5000).map(Result::unwrap).collect();
+    assert_eq!(transfers.len(), 5000);
+    assert!(transfers.windows(2).all(|w| w[0].ts <= w[1].ts));
+    let now = 1_700_000_000;
+    assert!(transfers.iter().all(|t| t.ts > now - 1000 && t.ts <= now));
+    // Spread over the whole window rather than bunched at one end.
+    assert!(transfers[0].ts < now - 900 && transfers[4999].ts > now - 100);
+}
+
+#[tokio::test]
+async fn test_stream_feeds_stats() {
+    use rust_challenge::stats::{calculate_user_stats_rust, calculate_user_stats_stream};
+
+    let gen = DefaultTransferGenerator {
+        config: TransferGenConfig {
+            ts_ordered: true,
+            ..seeded(19).config
+        },
+    };
+    let streamed = calculate_user_stats_stream(gen.generate_stream(2000))
+        .await
+        .unwrap();
+    let batch = calculate_user_stats_rust(&gen.generate(2000).unwrap()).unwrap();
+    let report = rust_challenge::stats::compare(
+        &batch,
+        &streamed,
+        rust_challenge::stats::Tolerance::exact(),
+    );
+    assert!(report.is_ok(), "{:?}", report.mismatches);
+}
+
+#[test]
+fn test_default_iter_for_other_generators() {
+    let gen = scenario_generator(BalanceMode::Unconstrained);
+    let lazy: Vec<Transfer> = gen.generate_iter(200).map(Result::unwrap).collect();
+    assert_eq!(lazy.len(), gen.generate(200).unwrap().len());
+}
+
+#[test]
+fn test_config_from_toml() {
+    let config = TransferGenConfig::from_toml_str(
+        r#"
+        min_amount = 5
+        max_amount = "250.5"
+        max_age_secs = 3600
+        address_count = 50
+        seed = 42
+        now = 1700000000
+
+        [activity]
+        type = "zipf"
+        exponent = 1.3
+
+        [price_model]
+        type = "gbm"
+        start = 1.25
+        drift = 0.05
+        volatility = 0.8
+
+        [balance_mode]
+        type = "constrained"
+        genesis_supply = "1000000"
+        "#,
+    )
+    .unwrap();
+    assert_eq!(config.max_amount, Decimal::new(2505, 1));
+    assert_eq!(config.min_price, TransferGenConfig::default().min_price);
+    assert_eq!(config.activity, AddressActivity::Zipf { exponent: 1.3 });
+    assert_eq!(
+        config.price_model,
+        PriceModel::Gbm {
+            start: Decimal::new(125, 2),
+            drift: 0.05,
+            volatility: 0.8,
+        }
+    );
+    assert_eq!(
+        config.balance_mode,
+        BalanceMode::Constrained {
+            genesis_supply: Decimal::from(1_000_000)
+        }
+    );
+    assert_eq!(config.seed, Some(42));
+    DefaultTransferGenerator { config }.generate(100).unwrap();
+}
+
+#[test]
+fn test_config_from_json() {
+    let config = TransferGenConfig::from_json_str(
+        r#"{
+            "min_price": "0.5",
+            "max_price": 3,
+            "price_model": { "type": "replay", "points": [[10, "1.5"], [5, 2]] },
+            "ts_ordered": true
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(config.min_price, Decimal::new(5, 1));
+    assert_eq!(
+        config.price_model,
+        PriceModel::Replay {
+            points: vec![(10, Decimal::new(15, 1)), (5, Decimal::from(2))]
+        }
+    );
+    assert!(config.ts_ordered);
+}
+
+#[test]
+fn test_config_file_errors() {
+    assert!(TransferGenConfig::from_toml_str("max_age = 5").is_err());
+    let err = TransferGenConfig::from_toml_str("min_price = 3\nmax_price = 2").unwrap_err();
+    assert!(err.downcast_ref::<ConfigError>().is_some(), "{err}");
+
+    let dir = std::env::temp_dir().join("rust_challenge_config_test");
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("gen.toml");
+    std::fs::write(&path, "max_age_secs = 0\n").unwrap();
+    let err = TransferGenConfig::from_file(&path).unwrap_err();
+    assert_eq!(
+        err.root_cause().downcast_ref::<ConfigError>(),
+        Some(&ConfigError::ZeroMaxAge)
+    );
+    assert!(TransferGenConfig::from_file(dir.join("gen.yaml")).is_err());
+}
+
+#[test]
+fn test_validate() {
+    let valid = TransferGenConfig::default();
+    assert_eq!(valid.validate(), Ok(()));
+
+    let negative_price = TransferGenConfig {
+        min_price: Decimal::from(-1),
+        ..TransferGenConfig::default()
+    };
+    assert!(matches!(
+        negative_price.validate(),
+        Err(ConfigError::Negative {
+            field: "min_price",
+            ..
+        })
+    ));
+
+    let nan_volatility = TransferGenConfig {
+        price_model: PriceModel::Gbm {
+            start: Decimal::ONE,
+            drift: 0.0,
+            volatility: f64::NAN,
+        },
+        ..TransferGenConfig::default()
+    };
+    assert!(matches!(
+        nan_volatility.validate(),
+        Err(ConfigError::NonFinite {
+            field: "price_model.volatility",
+            ..
+        })
+    ));
+
+    let steep = TransferGenConfig {
+        activity: AddressActivity::Zipf { exponent: 2000.0 },
+        ..TransferGenConfig::default()
+    };
+    assert_eq!(
+        steep.validate(),
+        Err(ConfigError::TooLarge {
+            field: "activity.exponent",
+            value: 2000.0,
+            max: MAX_ZIPF_EXPONENT,
+        })
+    );
+
+    let zero_age = TransferGenConfig {
+        max_age_secs: 0,
+        ..TransferGenConfig::default()
+    };
+    assert_eq!(zero_age.validate(), Err(ConfigError::ZeroMaxAge));
+    assert!(DefaultTransferGenerator { config: zero_age }
+        .generate(1)
+        .is_err());
+
+    let no_token = TransferGenConfig {
+        token: String::new(),
+        ..TransferGenConfig::default()
+    };
+    assert_eq!(no_token.validate(), Err(ConfigError::EmptyToken));
+}
+
+#[test]
+fn test_configured_token() {
+    let gen = DefaultTransferGenerator {
+        config: TransferGenConfig {
+            token: "ETH".to_string(),
+            ..TransferGenConfig::default()
+        },
+    };
+    let transfers = gen.generate(50).unwrap();
+    assert!(transfers.iter().all(|t| t.token == "ETH"));
+    assert!(DefaultTransferGenerator::default()
+        .generate(50)
+        .unwrap()
+        .iter()
+        .all(|t| t.token == DEFAULT_TOKEN));
+}
+
+#[test]
+fn test_example_config_is_valid() {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/generator.example.toml");
+    TransferGenConfig::from_file(path).unwrap();
+}
+
+#[test]
+fn test_steepest_activity_still_finds_receivers() {
+    let transfers = DefaultTransferGenerator {
+        config: TransferGenConfig {
+            activity: AddressActivity::Zipf {
+                exponent: MAX_ZIPF_EXPONENT,
+            },
+            address_count: 50,
+            seed: Some(4),
+            now: Some(1_700_000_000),
+            ..TransferGenConfig::default()
+        },
+    }
+    .generate(500)
+    .unwrap();
+    assert_eq!(transfers.len(), 500);
+    assert!(transfers.iter().all(|t| t.address_from != t.address_to));
 }
@@ -0,0 +1,252 @@
+use clickhouse::test::{handlers, status, Mock};
+use clickhouse::Row;
+use futures::TryStreamExt;
+use rust_challenge::common::ClickhouseClient;
+use rust_challenge::migrate::{self, latest_version, migrations};
+use rust_challenge::model::DEFAULT_TOKEN;
+use rust_challenge::stats::{calculate_user_stats_clickhouse, stream_user_stats_clickhouse};
+use serde::Serialize;
+
+#[derive(Row, Serialize)]
+struct Count {
+    count: u64,
+}
+
+#[derive(Row, Serialize)]
+struct Version {
+    version: u32,
+}
+
+#[derive(Row, Serialize)]
+struct Lock {
+    owner: String,
+    mine: bool,
+}
+
+/// Answers taking the migrations lock, held by this run or by `other`.
+fn add_lock(mock: &Mock, other: Option<&str>) {
+    mock.add(handlers::record_ddl());
+    mock.add(handlers::record_ddl());
+    mock.add(handlers::provide(vec![Lock {
+        owner: other.unwrap_or("me").to_string(),
+        mine: other.is_none(),
+    }]));
+}
+
+/// Answers the two queries behind [`migrate::status`].
+fn add_status(mock: &Mock, applied: &[u32]) {
+    mock.add(handlers::provide(vec![Count {
+        count: u64::from(!applied.is_empty()),
+    }]));
+    if !applied.is_empty() {
+        mock.add(handlers::provide(
+            applied
+                .iter()
+                .map(|&version| Version { version })
+                .collect::<Vec<_>>(),
+        ));
+    }
+}
+
+#[test]
+fn test_migrations_are_numbered_in_order() {
+    let versions: Vec<u32> = migrations().iter().map(|m| m.version).collect();
+    let expected: Vec<u32> = (1..=versions.len() as u32).collect();
+    assert_eq!(versions, expected);
+    assert_eq!(latest_version(), *versions.last().unwrap());
+}
+
+#[test]
+fn test_token_migration_defaults_existing_rows() {
+    let token = migrations().iter().find(|m| m.name == "token").unwrap();
+    let statements = token.statements("transfers");
+    assert_eq!(statements.len(), 1);
+    assert!(
+        statements[0].contains("ALTER TABLE transfers\n    ADD COLUMN IF NOT EXISTS token String")
+    );
+    assert!(statements[0].contains(&format!("DEFAULT '{DEFAULT_TOKEN}'")));
+}
+
+#[test]
+fn test_migration_statements_use_table() {
+    for migration in migrations() {
+        let statements = migration.statements("transfers_v2");
+        assert!(!statements.is_empty(), "{} is empty", migration.name);
+        for statement in statements {
+            assert!(!statement.contains("{table}"), "{statement}");
+            assert!(!statement.ends_with(';'), "{statement}");
+        }
+    }
+    assert!(migrations()[0].statements("transfers_v2")[0]
+        .starts_with("CREATE TABLE IF NOT EXISTS transfers_v2 ("));
+}
+
+#[test]
+fn test_migration_statements_start_with_a_keyword() {
+    for migration in migrations() {
+        for statement in migration.statements("transfers") {
+            let keyword = statement.split_whitespace().next().unwrap();
+            assert!(
+                ["CREATE", "ALTER", "DROP", "INSERT", "EXCHANGE"].contains(&keyword),
+                "{}: {statement}",
+                migration.name
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_status_of_fresh_database() {
+    let mock = Mock::new();
+    let client = ClickhouseClient::new(mock.url());
+    add_status(&mock, &[]);
+
+    let status = migrate::status(&client).await.unwrap();
+    assert!(status.applied.is_empty());
+    assert_eq!(status.pending.len(), migrations().len());
+    assert_eq!(status.current_version(), 0);
+    assert!(!status.is_up_to_date());
+}
+
+#[tokio::test]
+async fn test_status_up_to_date() {
+    let mock = Mock::new();
+    let client = ClickhouseClient::new(mock.url());
+    let all: Vec<u32> = migrations().iter().map(|m| m.version).collect();
+    add_status(&mock, &all);
+
+    let status = migrate::status(&client).await.unwrap();
+    assert!(status.pending.is_empty());
+    assert!(status.is_up_to_date());
+}
+
+#[tokio::test]
+async fn test_newer_schema_is_not_up_to_date() {
+    let mock = Mock::new();
+    let client = ClickhouseClient::new(mock.url());
+    let mut all: Vec<u32> = migrations().iter().map(|m| m.version).collect();
+    all.push(latest_version() + 1);
+    add_status(&mock, &all);
+
+    let error = migrate::ensure_up_to_date(&client).await.unwrap_err();
+    assert!(error.to_string().contains("out of date"), "{error}");
+}
+
+#[tokio::test]
+async fn test_run_applies_only_pending() {
+    let mock = Mock::new();
+    let client = ClickhouseClient::new(mock.url());
+    let create = mock.add(handlers::record_ddl());
+    add_lock(&mock, None);
+    add_status(&mock, &[1, 2, 3]);
+    let last = migrations().last().unwrap();
+    let statements: Vec<_> = last
+        .statements(&client.table)
+        .iter()
+        .map(|_| mock.add(handlers::record_ddl()))
+        .collect();
+    let record = mock.add(handlers::record_ddl());
+    let unlock = mock.add(handlers::record_ddl());
+
+    let applied = migrate::run(&client).await.unwrap();
+    assert_eq!(applied, vec![4]);
+    assert!(create
+        .query()
+        .await
+        .contains("CREATE TABLE IF NOT EXISTS transfers_migrations"));
+    let mut executed = vec![];
+    for statement in statements {
+        executed.push(statement.query().await);
+    }
+    assert_eq!(executed, last.statements("transfers"));
+    let record = record.query().await;
+    assert!(
+        record.contains("INSERT INTO transfers_migrations (version, name) VALUES (4, 'token')"),
+        "{record}"
+    );
+    let unlock = unlock.query().await;
+    assert!(
+        unlock.contains("INSERT INTO transfers_migrations_lock (owner, released) VALUES ('"),
+        "{unlock}"
+    );
+    assert!(unlock.ends_with("', 1)"), "{unlock}");
+}
+
+#[tokio::test]
+async fn test_run_skips_interrupted_rebuild() {
+    let mock = Mock::new();
+    let client = ClickhouseClient::new(mock.url());
+    mock.add(handlers::record_ddl());
+    add_lock(&mock, None);
+    add_status(&mock, &[1, 2]);
+    // The table was swapped in before the run failed.
+    mock.add(handlers::provide(vec![Count { count: 1 }]));
+    let cleanup = mock.add(handlers::record_ddl());
+    let record = mock.add(handlers::record_ddl());
+    for _ in migrations()[3].statements(&client.table) {
+        mock.add(handlers::record_ddl());
+    }
+    mock.add(handlers::record_ddl());
+    mock.add(handlers::record_ddl());
+
+    let applied = migrate::run(&client).await.unwrap();
+    assert_eq!(applied, vec![3, 4]);
+    assert_eq!(
+        cleanup.query().await,
+        "DROP TABLE IF EXISTS transfers_dedup"
+    );
+    assert!(record
+        .query()
+        .await
+        .contains("VALUES (3, 'transfer_identity')"));
+}
+
+#[tokio::test]
+async fn test_run_refuses_concurrent_run() {
+    let mock = Mock::new();
+    let client = ClickhouseClient::new(mock.url());
+    mock.add(handlers::record_ddl());
+    add_lock(&mock, Some("42-other"));
+    let unlock = mock.add(handlers::record_ddl());
+
+    let error = migrate::run(&client).await.unwrap_err();
+    assert!(
+        error
+            .to_string()
+            .contains("already running (lock owner 42-other)"),
+        "{error}"
+    );
+    assert!(unlock.query().await.ends_with("', 1)"));
+}
+
+#[tokio::test]
+async fn test_run_stops_at_failed_migration() {
+    let mock = Mock::new();
+    let client = ClickhouseClient::new(mock.url());
+    mock.add(handlers::record_ddl());
+    add_lock(&mock, None);
+    add_status(&mock, &[]);
+    mock.add(handlers::failure(status::INTERNAL_SERVER_ERROR));
+    // The lock is released after the failure.
+    let unlock = mock.add(handlers::record_ddl());
+
+    let error = migrate::run(&client).await.unwrap_err();
+    assert!(
+        format!("{error:#}").contains("001_create_transfers"),
+        "{error:#}"
+    );
+    assert!(unlock.query().await.contains("transfers_migrations_lock"));
+}
+
+#[tokio::test]
+async fn test_stats_refuse_outdated_schema() {
+    let mock = Mock::new();
+    let client = ClickhouseClient::new(mock.url());
+    add_status(&mock, &[1]);
+    let error = calculate_user_stats_clickhouse(&client).await.unwrap_err();
+    assert!(error.to_string().contains("out of date"), "{error}");
+
+    add_status(&mock, &[]);
+    let result: anyhow::Result<Vec<_>> = stream_user_stats_clickhouse(&client).try_collect().await;
+    assert!(result.is_err());
+}
@@ -0,0 +1,100 @@
+use rust_challenge::model::{Decimal, UserStats, DEFAULT_TOKEN};
+use rust_challenge::output::{write_user_stats, OutputFormat, USER_STATS_COLUMNS};
+
+fn stat(address: &str, volume: &str, max_balance: &str) -> UserStats {
+    UserStats {
+        address: address.to_string(),
+        token: DEFAULT_TOKEN.to_string(),
+        total_volume: volume.parse().unwrap(),
+        avg_buy_price: "1.5".parse().unwrap(),
+        avg_sell_price: Decimal::ZERO,
+        max_balance: max_balance.parse().unwrap(),
+    }
+}
+
+fn fixture() -> Vec<UserStats> {
+    vec![
+        stat("A", "1234.000000000000000001", "-3"),
+        stat("0xbeef", "0.25", "100"),
+    ]
+}
+
+fn render(stats: &[UserStats], format: OutputFormat, precision: Option<usize>) -> String {
+    let mut out = vec![];
+    write_user_stats(&mut out, stats, format, precision).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn test_ndjson() {
+    assert_eq!(
+        render(&fixture(), OutputFormat::Ndjson, None),
+        concat!(
+            r#"{"address":"A","token":"TKN","total_volume":"1234.000000000000000001","avg_buy_price":"1.5","avg_sell_price":"0","max_balance":"-3"}"#,
+            "\n",
+            r#"{"address":"0xbeef","token":"TKN","total_volume":"0.25","avg_buy_price":"1.5","avg_sell_price":"0","max_balance":"100"}"#,
+            "\n",
+        )
+    );
+}
+
+#[test]
+fn test_json_round_trips() {
+    let output = render(&fixture(), OutputFormat::Json, None);
+    assert!(output.starts_with("[\n  {\"address\":\"A\""), "{output}");
+    assert!(output.ends_with("}\n]\n"), "{output}");
+    let parsed: Vec<UserStats> = serde_json::from_str(&output).unwrap();
+    assert_eq!(parsed.len(), 2);
+    assert_eq!(parsed[0].total_volume, fixture()[0].total_volume);
+    assert_eq!(parsed[1].max_balance, Decimal::from(100));
+
+    assert_eq!(render(&[], OutputFormat::Json, None), "[]\n");
+}
+
+#[test]
+fn test_csv() {
+    let mut stats = fixture();
+    stats[1].address = "say \"hi\", bob".to_string();
+    assert_eq!(
+        render(&stats, OutputFormat::Csv, Some(2)),
+        "address,token,total_volume,avg_buy_price,avg_sell_price,max_balance\n\
+         A,TKN,1234.00,1.50,0.00,-3.00\n\
+         \"say \"\"hi\"\", bob\",TKN,0.25,1.50,0.00,100.00\n"
+    );
+    assert_eq!(
+        render(&[], OutputFormat::Csv, None),
+        format!("{}\n", USER_STATS_COLUMNS.join(","))
+    );
+}
+
+#[test]
+fn test_table_is_aligned() {
+    assert_eq!(
+        render(&fixture(), OutputFormat::Table, Some(3)),
+        "\
+address  token  total_volume  avg_buy_price  avg_sell_price  max_balance
+-------  -----  ------------  -------------  --------------  -----------
+A        TKN        1234.000          1.500           0.000       -3.000
+0xbeef   TKN           0.250          1.500           0.000      100.000
+"
+    );
+}
+
+#[test]
+fn test_precision_rounds_every_format_alike() {
+    let stats = vec![stat("A", "2.0055", "0")];
+    for format in OutputFormat::ALL {
+        let output = render(&stats, format, Some(2));
+        assert!(output.contains("2.01"), "{format}: {output}");
+        assert!(!output.contains("2.0055"), "{format}: {output}");
+    }
+}
+
+#[test]
+fn test_format_names() {
+    for format in OutputFormat::ALL {
+        assert_eq!(format.to_string().parse::<OutputFormat>().unwrap(), format);
+    }
+    assert_eq!("CSV".parse::<OutputFormat>().unwrap(), OutputFormat::Csv);
+    assert!("yaml".parse::<OutputFormat>().is_err());
+}
@@ -0,0 +1,258 @@
+use arrow_array::{
+    ArrayRef, Decimal128Array, Int32Array, RecordBatch, StringArray, TimestampNanosecondArray,
+    UInt64Array,
+};
+use arrow_schema::{DataType, Field, Schema, TimeUnit};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{LogicalType, TimeUnit as ParquetTimeUnit, TimestampType};
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use rust_challenge::dump::parquet::{read_parquet, write_parquet, ParquetRecord};
+use rust_challenge::generator::{DefaultTransferGenerator, TransferGenConfig};
+use rust_challenge::model::{Decimal, Transfer, UserStats};
+use rust_challenge::stats::calculate_user_stats_rust;
+use std::fs::File;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+fn path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("parquet-{}-{name}.parquet", std::process::id()))
+}
+
+fn generated(count: usize) -> Vec<Transfer> {
+    DefaultTransferGenerator {
+        config: TransferGenConfig {
+            seed: Some(5),
+            now: Some(1_700_000_000),
+            ..TransferGenConfig::default()
+        },
+    }
+    .generate_batch(count)
+    .unwrap()
+    .transfers
+}
+
+fn write<T: ParquetRecord>(name: &str, rows: Vec<T>) -> PathBuf {
+    let path = path(name);
+    let count = rows.len() as u64;
+    let written = write_parquet(File::create(&path).unwrap(), rows.into_iter().map(Ok)).unwrap();
+    assert_eq!(written, count);
+    path
+}
+
+fn read<T: ParquetRecord>(path: &PathBuf) -> anyhow::Result<Vec<T>> {
+    read_parquet(File::open(path)?)?.collect()
+}
+
+fn write_batch(name: &str, batch: RecordBatch) -> PathBuf {
+    let path = path(name);
+    let mut writer =
+        ArrowWriter::try_new(File::create(&path).unwrap(), batch.schema(), None).unwrap();
+    writer.write(&batch).unwrap();
+    writer.close().unwrap();
+    path
+}
+
+#[test]
+fn test_transfers_round_trip() {
+    // More rows than one record batch.
+    let transfers = generated(10_000);
+    let path = write("transfers", transfers.clone());
+    let back: Vec<Transfer> = read(&path).unwrap();
+    assert_eq!(
+        serde_json::to_value(&back).unwrap(),
+        serde_json::to_value(&transfers).unwrap()
+    );
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_user_stats_round_trip() {
+    let stats = calculate_user_stats_rust(&generated(500)).unwrap();
+    let path = write("stats", stats.clone());
+    let back: Vec<UserStats> = read(&path).unwrap();
+    assert_eq!(
+        serde_json::to_value(&back).unwrap(),
+        serde_json::to_value(&stats).unwrap()
+    );
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_schema_types() {
+    let schema = Transfer::schema();
+    assert_eq!(
+        schema.field_with_name("ts").unwrap().data_type(),
+        &DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into()))
+    );
+    assert_eq!(
+        schema.field_with_name("amount").unwrap().data_type(),
+        &DataType::Decimal128(38, 18)
+    );
+    assert!(schema.fields().iter().all(|field| !field.is_nullable()));
+    let stats_schema = UserStats::schema();
+    let names: Vec<&str> = stats_schema
+        .fields()
+        .iter()
+        .map(|field| field.name().as_str())
+        .collect();
+    assert_eq!(
+        names,
+        [
+            "address",
+            "token",
+            "total_volume",
+            "avg_buy_price",
+            "avg_sell_price",
+            "max_balance"
+        ]
+    );
+}
+
+#[test]
+fn test_ts_is_a_parquet_timestamp() {
+    let path = write("logical", generated(3));
+    let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+    let schema = reader.metadata().file_metadata().schema_descr();
+    let ts = (0..schema.num_columns())
+        .map(|i| schema.column(i))
+        .find(|column| column.name() == "ts")
+        .unwrap();
+    assert!(
+        matches!(
+            ts.logical_type_ref(),
+            Some(LogicalType::Timestamp(TimestampType {
+                is_adjusted_to_u_t_c: true,
+                unit: ParquetTimeUnit::MILLIS,
+            }))
+        ),
+        "{:?}",
+        ts.logical_type_ref()
+    );
+    std::fs::remove_file(path).unwrap();
+}
+
+fn foreign_stats(max_balance: ArrayRef) -> RecordBatch {
+    let schema = Schema::new(vec![
+        Field::new("token", DataType::Utf8, false),
+        Field::new("address", DataType::Utf8, false),
+        Field::new("total_volume", DataType::Decimal128(10, 2), false),
+        Field::new("avg_buy_price", DataType::Float64, false),
+        Field::new("avg_sell_price", DataType::Int32, false),
+        Field::new("max_balance", max_balance.data_type().clone(), true),
+        Field::new("comment", DataType::Utf8, false),
+    ]);
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from(vec!["TKN"])),
+        Arc::new(StringArray::from(vec!["A"])),
+        Arc::new(
+            Decimal128Array::from(vec![12345])
+                .with_precision_and_scale(10, 2)
+                .unwrap(),
+        ),
+        Arc::new(arrow_array::Float64Array::from(vec![1.5])),
+        Arc::new(Int32Array::from(vec![7])),
+        max_balance,
+        Arc::new(StringArray::from(vec!["ignored"])),
+    ];
+    RecordBatch::try_new(Arc::new(schema), columns).unwrap()
+}
+
+#[test]
+fn test_reads_other_column_types() {
+    let path = write_batch(
+        "foreign-stats",
+        foreign_stats(Arc::new(Int32Array::from(vec![-3]))),
+    );
+    let stats: Vec<UserStats> = read(&path).unwrap();
+    assert_eq!(stats[0].address, "A");
+    assert_eq!(stats[0].total_volume, "123.45".parse::<Decimal>().unwrap());
+    assert_eq!(stats[0].avg_buy_price, "1.5".parse::<Decimal>().unwrap());
+    assert_eq!(stats[0].avg_sell_price, Decimal::from(7));
+    assert_eq!(stats[0].max_balance, Decimal::from(-3));
+    std::fs::remove_file(path).unwrap();
+
+    let mut transfer = generated(1).remove(0);
+    transfer.ts = 1_700_000_123;
+    let batch = Transfer::to_batch(&[transfer.clone()]).unwrap();
+    let ts_nanos = TimestampNanosecondArray::from(vec![1_700_000_123_999_999_999]);
+    let mut columns = batch.columns().to_vec();
+    columns[0] = Arc::new(ts_nanos);
+    let mut fields: Vec<Field> = batch
+        .schema()
+        .fields()
+        .iter()
+        .map(|field| field.as_ref().clone())
+        .collect();
+    fields[0] = Field::new("ts", DataType::Timestamp(TimeUnit::Nanosecond, None), false);
+    let path = write_batch(
+        "foreign-transfers",
+        RecordBatch::try_new(Arc::new(Schema::new(fields)), columns).unwrap(),
+    );
+    let back: Vec<Transfer> = read(&path).unwrap();
+    // Sub-second parts are truncated.
+    assert_eq!(back[0].ts, transfer.ts);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_rejects_nulls_missing_columns_and_overflow() {
+    let path = write_batch(
+        "nulls",
+        foreign_stats(Arc::new(Int32Array::from(vec![None]))),
+    );
+    let error = read::<UserStats>(&path).unwrap_err();
+    assert_eq!(error.to_string(), "Column max_balance has 1 nulls");
+    std::fs::remove_file(path).unwrap();
+
+    let path = write_batch(
+        "missing",
+        RecordBatch::try_new(
+            Arc::new(Schema::new(vec![Field::new(
+                "address",
+                DataType::Utf8,
+                false,
+            )])),
+            vec![Arc::new(StringArray::from(vec!["A"]))],
+        )
+        .unwrap(),
+    );
+    let error = read::<UserStats>(&path).unwrap_err();
+    assert_eq!(error.to_string(), "Missing column token");
+    std::fs::remove_file(path).unwrap();
+
+    let mut transfer = generated(1).remove(0);
+    let batch = Transfer::to_batch(&[transfer.clone()]).unwrap();
+    let mut columns = batch.columns().to_vec();
+    columns[8] = Arc::new(UInt64Array::from(vec![u64::from(u32::MAX) + 1]));
+    let mut fields: Vec<Field> = batch
+        .schema()
+        .fields()
+        .iter()
+        .map(|field| field.as_ref().clone())
+        .collect();
+    fields[8] = Field::new("log_index", DataType::UInt64, false);
+    let path = write_batch(
+        "overflow",
+        RecordBatch::try_new(Arc::new(Schema::new(fields)), columns).unwrap(),
+    );
+    let error = read::<Transfer>(&path).unwrap_err();
+    assert!(
+        error
+            .to_string()
+            .starts_with("Cannot read column log_index"),
+        "{error}"
+    );
+    std::fs::remove_file(path).unwrap();
+
+    transfer.ts = u64::MAX;
+    assert!(Transfer::to_batch(&[transfer]).is_err());
+}
+
+#[test]
+fn test_rejects_other_files() {
+    let path = path("not-parquet");
+    std::fs::write(&path, "ts,address_from\n").unwrap();
+    let error = read::<Transfer>(&path).unwrap_err();
+    assert_eq!(error.to_string(), "Not a Parquet file");
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,326 @@
+use axum::body::Bytes;
+use axum::http::{Method, Request, StatusCode};
+use http_body_util::{BodyExt, Full};
+use hyper_util::client::legacy::{connect::HttpConnector, Client};
+use hyper_util::rt::TokioExecutor;
+use rust_challenge::common::ClickhouseClient;
+use rust_challenge::dump::{write_transfers, TransferFormat};
+use rust_challenge::generator::{DefaultTransferGenerator, TransferGenConfig};
+use rust_challenge::model::{Decimal, Transfer, DEFAULT_TOKEN};
+use rust_challenge::server::serve;
+use rust_challenge::stats::{calculate_user_stats_rust, NegativeBalancePolicy};
+use rust_challenge::storage::{ClickhouseStorage, InMemoryStorage, TransferStore};
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+
+struct TestServer {
+    addr: SocketAddr,
+    client: Client<HttpConnector, Full<Bytes>>,
+}
+
+impl TestServer {
+    async fn start(transfers: Vec<Transfer>) -> Self {
+        Self::with_store(InMemoryStorage::with_transfers(transfers)).await
+    }
+
+    async fn with_store(store: impl TransferStore + 'static) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, store, NegativeBalancePolicy::default()));
+        Self {
+            addr,
+            client: Client::builder(TokioExecutor::new()).build_http(),
+        }
+    }
+
+    async fn request(&self, method: Method, path: &str, body: &str) -> (StatusCode, Value) {
+        let request = Request::builder()
+            .method(method)
+            .uri(format!("http://{}{path}", self.addr))
+            .body(Full::new(Bytes::from(body.to_string())))
+            .unwrap();
+        let response = self.client.request(request).await.unwrap();
+        let status = response.status();
+        assert_eq!(response.headers()["content-type"], "application/json");
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    async fn get(&self, path: &str) -> (StatusCode, Value) {
+        self.request(Method::GET, path, "").await
+    }
+
+    async fn post(&self, path: &str, body: &str) -> (StatusCode, Value) {
+        self.request(Method::POST, path, body).await
+    }
+}
+
+fn transfer(ts: u64, from: &str, to: &str, amount: i64, token: &str, log_index: u32) -> Transfer {
+    Transfer {
+        ts,
+        address_from: from.to_string(),
+        address_to: to.to_string(),
+        token: token.to_string(),
+        amount: Decimal::from(amount),
+        usd_price: Decimal::from(2),
+        block_number: ts,
+        tx_hash: format!("0x{ts:02}"),
+        log_index,
+    }
+}
+
+fn fixture() -> Vec<Transfer> {
+    vec![
+        transfer(1, "A", "B", 10, DEFAULT_TOKEN, 0),
+        transfer(2, "B", "C", 4, DEFAULT_TOKEN, 0),
+        transfer(3, "C", "A", 1, DEFAULT_TOKEN, 0),
+        transfer(3, "B", "A", 5, "ETH", 1),
+    ]
+}
+
+fn ndjson(transfers: &[Transfer]) -> String {
+    let mut out = vec![];
+    write_transfers(
+        &mut out,
+        TransferFormat::Ndjson,
+        transfers.iter().cloned().map(Ok),
+    )
+    .unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[tokio::test]
+async fn test_address_stats() {
+    let server = TestServer::start(fixture()).await;
+    let expected: Vec<Value> = calculate_user_stats_rust(&fixture())
+        .unwrap()
+        .into_iter()
+        .filter(|s| s.address == "A")
+        .map(|s| serde_json::to_value(s).unwrap())
+        .collect();
+
+    let (status, body) = server.get("/stats/A").await;
+    assert_eq!(status, StatusCode::OK);
+    let body = body.as_array().unwrap();
+    assert_eq!(body.len(), 2);
+    // One entry per token, sorted by token.
+    assert_eq!(body[0]["token"], "ETH");
+    assert_eq!(body[1]["token"], DEFAULT_TOKEN);
+    assert_eq!(body[1]["total_volume"], "11");
+    assert!(expected.iter().all(|s| body.contains(s)));
+
+    let (status, body) = server.get("/stats/A?token=ETH").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body.as_array().unwrap().len(), 1);
+    assert_eq!(body[0]["max_balance"], "5");
+}
+
+#[tokio::test]
+async fn test_not_found() {
+    let server = TestServer::start(fixture()).await;
+    let (status, body) = server.get("/stats/Z").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body, json!({ "error": "No transfers of address Z" }));
+
+    let (status, _) = server.get("/stats/A?token=USDT").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = server.get("/balance-history/Z").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, body) = server.get("/users").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body, json!({ "error": "Not found" }));
+}
+
+#[tokio::test]
+async fn test_all_stats_sorted_and_limited() {
+    let server = TestServer::start(fixture()).await;
+    let (status, body) = server.get("/stats").await;
+    assert_eq!(status, StatusCode::OK);
+    let pairs: Vec<(&str, &str)> = body
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| (s["address"].as_str().unwrap(), s["token"].as_str().unwrap()))
+        .collect();
+    assert_eq!(
+        pairs,
+        [
+            ("A", "ETH"),
+            ("A", "TKN"),
+            ("B", "ETH"),
+            ("B", "TKN"),
+            ("C", "TKN")
+        ]
+    );
+
+    let (status, body) = server
+        .get("/stats?sort=max_balance&desc=true&limit=2&token=TKN")
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let top: Vec<(&str, &str)> = body
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| {
+            (
+                s["address"].as_str().unwrap(),
+                s["max_balance"].as_str().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(top, [("B", "10"), ("C", "4")]);
+}
+
+#[tokio::test]
+async fn test_invalid_parameters() {
+    let server = TestServer::start(fixture()).await;
+    for path in [
+        "/stats?sort=fee",
+        "/stats?limit=-1",
+        "/stats?desc=maybe",
+        "/stats/A?token=TKN&token=ETH",
+    ] {
+        let (status, body) = server.get(path).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{path}");
+        assert!(
+            body["error"]
+                .as_str()
+                .unwrap()
+                .starts_with("Failed to deserialize query string"),
+            "{path}: {body}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_balance_history() {
+    let server = TestServer::start(fixture()).await;
+    let (status, body) = server.get("/balance-history/A").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        body,
+        json!({
+            "address": "A",
+            "tokens": {
+                "ETH": [{ "ts": 3, "balance": "5" }],
+                "TKN": [{ "ts": 1, "balance": "-10" }, { "ts": 3, "balance": "-9" }],
+            }
+        })
+    );
+
+    let (_, body) = server.get("/balance-history/B?token=ETH").await;
+    assert_eq!(
+        body["tokens"],
+        json!({ "ETH": [{ "ts": 3, "balance": "-5" }] })
+    );
+}
+
+#[tokio::test]
+async fn test_post_transfers() {
+    let server = TestServer::start(vec![]).await;
+    let (status, _) = server.get("/stats/A").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, body) = server.post("/transfers", &ndjson(&fixture())).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(body, json!({ "inserted": 4 }));
+
+    let (_, body) = server.get("/stats").await;
+    assert_eq!(body.as_array().unwrap().len(), 5);
+
+    // Posting the same ids again replaces the rows.
+    let (status, _) = server.post("/transfers", &ndjson(&fixture()[..1])).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (_, body) = server.get("/stats/B?token=TKN").await;
+    assert_eq!(body[0]["total_volume"], "14");
+}
+
+#[tokio::test]
+async fn test_post_invalid_transfers_stores_nothing() {
+    let server = TestServer::start(vec![]).await;
+    let body = format!(
+        "{}{}",
+        ndjson(&fixture()[..1]),
+        r#"{"ts":1,"address_from":"A"}"#
+    );
+    let (status, body) = server.post("/transfers", &body).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        body,
+        json!({ "error": "line 2, field address_to: missing" })
+    );
+
+    let (status, body) = server.post("/transfers", "\n").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body, json!({ "error": "No transfers in the request body" }));
+
+    let (status, _) = server.get("/stats/A").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_stats_out_of_range_are_unprocessable() {
+    let big = |ts: u64, from: &str| Transfer {
+        amount: "100000000000000000000".parse().unwrap(),
+        ..transfer(ts, from, "B", 0, DEFAULT_TOKEN, ts as u32)
+    };
+    let server = TestServer::start(vec![]).await;
+    // Each transfer is in range, B's volume of 2e20 is not.
+    let (status, _) = server
+        .post("/transfers", &ndjson(&[big(1, "A"), big(2, "C")]))
+        .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    for path in ["/stats", "/stats/B"] {
+        let (status, body) = server.get(path).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{path}");
+        assert_eq!(
+            body,
+            json!({ "error": "total_volume of B in TKN: out of the decimal range" }),
+            "{path}"
+        );
+    }
+    let (status, body) = server.get("/balance-history/B").await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(
+        body,
+        json!({ "error": "Balance of B in TKN at ts 2: out of the decimal range" })
+    );
+    // A's own stats are still in range.
+    let (status, _) = server.get("/stats/A").await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_store_errors_are_not_exposed() {
+    // Nothing listens on port 1.
+    let client = ClickhouseClient::new("http://127.0.0.1:1");
+    let server = TestServer::with_store(ClickhouseStorage::new(client)).await;
+    let (status, body) = server.get("/stats/A").await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(body, json!({ "error": "Internal server error" }));
+}
+
+#[tokio::test]
+async fn test_generated_stats_match_the_rust_engine() {
+    let transfers = DefaultTransferGenerator {
+        config: TransferGenConfig {
+            seed: Some(9),
+            now: Some(1_700_000_000),
+            ..TransferGenConfig::default()
+        },
+    }
+    .generate_batch(300)
+    .unwrap()
+    .transfers;
+    let expected = calculate_user_stats_rust(&transfers).unwrap();
+    let server = TestServer::start(transfers).await;
+    for stats in expected.iter().take(20) {
+        let (status, body) = server
+            .get(&format!("/stats/{}?token={}", stats.address, stats.token))
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!([serde_json::to_value(stats).unwrap()]));
+    }
+}
@@ -1,18 +1,37 @@
-use rust_challenge::common::ClickhouseClient;
-use rust_challenge::model::Transfer;
-use rust_challenge::stats::{calculate_user_stats_clickhouse, calculate_user_stats_rust};
+use futures::stream;
+use rust_challenge::common::{ClickhouseClient, ClickhouseConfig};
+use rust_challenge::generator::{DefaultTransferGenerator, TransferGenerator};
+use rust_challenge::migrate;
+use rust_challenge::model::{Decimal, Transfer, UserStats, DEFAULT_TOKEN};
+use rust_challenge::stats::{
+    calculate_balance_history, calculate_balance_history_of, calculate_usd_stats_rust,
+    calculate_user_stats_clickhouse, calculate_user_stats_clickhouse_with_policy,
+    calculate_user_stats_rust, calculate_user_stats_rust_with_policy, calculate_user_stats_stream,
+    compare, Mismatch, NegativeBalancePolicy, StatsAggregator, Tolerance,
+};
 use serial_test::serial;
+use std::sync::atomic::{AtomicU64, Ordering};
 
+/// Every call gets a fresh id, so equal-looking transfers stay distinct rows.
 fn make_transfer(from: &str, to: &str, amount: f64, price: f64, ts: u64) -> Transfer {
+    static NEXT_TX: AtomicU64 = AtomicU64::new(0);
     Transfer {
         ts,
         address_from: from.to_string(),
         address_to: to.to_string(),
-        amount,
-        usd_price: price,
+        token: DEFAULT_TOKEN.to_string(),
+        amount: dec(amount),
+        usd_price: dec(price),
+        block_number: ts,
+        tx_hash: format!("0x{:064x}", NEXT_TX.fetch_add(1, Ordering::Relaxed)),
+        log_index: 0,
     }
 }
 
+fn dec(value: f64) -> Decimal {
+    Decimal::from_f64(value).unwrap()
+}
+
 #[test]
 fn test_empty() {
     let stats = calculate_user_stats_rust(&[]).unwrap();
@@ -22,13 +41,13 @@ fn test_empty() {
 #[test]
 fn test_single_transfer() {
     let t = make_transfer("A", "B", 10.0, 2.0, 1);
-    let stats = calculate_user_stats_rust(&[t.clone()]).unwrap();
+    let stats = calculate_user_stats_rust(&[t]).unwrap();
     assert_eq!(stats.len(), 2);
     let a = stats.iter().find(|s| s.address == "A").unwrap();
     let b = stats.iter().find(|s| s.address == "B").unwrap();
-    assert_eq!(a.total_volume + b.total_volume, 20.0);
-    assert_eq!(a.avg_sell_price, 2.0);
-    assert_eq!(b.avg_buy_price, 2.0);
+    assert_eq!(a.total_volume + b.total_volume, dec(20.0));
+    assert_eq!(a.avg_sell_price, dec(2.0));
+    assert_eq!(b.avg_buy_price, dec(2.0));
 }
 
 #[test]
@@ -40,9 +59,9 @@ fn test_multiple_transfers() {
     let a = stats.iter().find(|s| s.address == "A").unwrap();
     let b = stats.iter().find(|s| s.address == "B").unwrap();
     let c = stats.iter().find(|s| s.address == "C").unwrap();
-    assert!(a.total_volume > 0.0);
-    assert!(b.total_volume > 0.0);
-    assert!(c.total_volume > 0.0);
+    assert!(a.total_volume.is_positive());
+    assert!(b.total_volume.is_positive());
+    assert!(c.total_volume.is_positive());
 }
 
 #[test]
@@ -50,9 +69,9 @@ fn test_same_address() {
     let t = make_transfer("A", "A", 100.0, 1.0, 1);
     let stats = calculate_user_stats_rust(&[t]).unwrap();
     let a = stats.iter().find(|s| s.address == "A").unwrap();
-    assert_eq!(a.total_volume, 100.0);
-    assert_eq!(a.avg_buy_price, 1.0);
-    assert_eq!(a.avg_sell_price, 1.0);
+    assert_eq!(a.total_volume, dec(100.0));
+    assert_eq!(a.avg_buy_price, dec(1.0));
+    assert_eq!(a.avg_sell_price, dec(1.0));
 }
 
 #[test]
@@ -60,10 +79,390 @@ fn test_negative_and_zero_amounts() {
     let t1 = make_transfer("A", "B", 0.0, 1.0, 1);
     let t2 = make_transfer("B", "A", -5.0, 2.0, 2);
     let stats = calculate_user_stats_rust(&[t1, t2]).unwrap();
-    let a = stats.iter().find(|s| s.address == "A").unwrap();
+    assert!(stats.iter().find(|s| s.address == "A").is_none());
+    assert!(stats.iter().find(|s| s.address == "B").is_none());
+}
+
+#[test]
+fn test_invalid_amounts_are_ignored() {
+    let stats = calculate_user_stats_rust(&[
+        make_transfer("A", "B", 10.0, 2.0, 1),
+        make_transfer("A", "B", 0.0, 2.0, 2),
+        make_transfer("B", "A", -5.0, 2.0, 3),
+        make_transfer("B", "C", -1.0, 2.0, 4),
+    ])
+    .unwrap();
+    assert_eq!(stats.len(), 2);
     let b = stats.iter().find(|s| s.address == "B").unwrap();
-    assert!(a.total_volume >= 0.0);
-    assert!(b.total_volume >= 0.0);
+    assert_eq!(b.total_volume, dec(10.0));
+    assert_eq!(b.avg_sell_price, dec(0.0));
+}
+
+#[test]
+fn test_self_transfer_does_not_move_balance() {
+    let stats = calculate_user_stats_rust(&[
+        make_transfer("B", "A", 50.0, 1.0, 1),
+        make_transfer("A", "A", 100.0, 2.0, 2),
+    ])
+    .unwrap();
+    let a = stats.iter().find(|s| s.address == "A").unwrap();
+    assert_eq!(a.total_volume, dec(150.0));
+    assert_eq!(a.max_balance, dec(50.0));
+    assert_eq!(a.avg_sell_price, dec(2.0));
+}
+
+#[test]
+fn test_max_balance_follows_ts_order() {
+    let stats = calculate_user_stats_rust(&[
+        make_transfer("A", "B", 10.0, 1.0, 2),
+        make_transfer("C", "A", 10.0, 1.0, 1),
+        make_transfer("C", "A", 5.0, 1.0, 3),
+    ])
+    .unwrap();
+    let a = stats.iter().find(|s| s.address == "A").unwrap();
+    assert_eq!(a.max_balance, dec(10.0));
+}
+
+#[test]
+fn test_max_balance_independent_of_input_order() {
+    let transfers = vec![
+        make_transfer("A", "B", 10.0, 1.0, 5),
+        make_transfer("B", "A", 3.0, 1.0, 5),
+        make_transfer("B", "C", 4.0, 1.0, 5),
+        make_transfer("C", "A", 1.0, 1.0, 6),
+    ];
+    let mut reversed = transfers.clone();
+    reversed.reverse();
+    let report = compare(
+        &calculate_user_stats_rust(&transfers).unwrap(),
+        &calculate_user_stats_rust(&reversed).unwrap(),
+        Tolerance::exact(),
+    );
+    assert!(report.is_ok(), "{:?}", report.mismatches);
+}
+
+#[test]
+fn test_balance_history_is_time_ordered() {
+    let history = calculate_balance_history(
+        &[
+            make_transfer("A", "B", 4.0, 1.0, 3),
+            make_transfer("C", "A", 10.0, 1.0, 1),
+            make_transfer("A", "C", 1.0, 1.0, 2),
+        ],
+        NegativeBalancePolicy::Allow,
+    )
+    .unwrap();
+    assert_eq!(
+        history["A"][DEFAULT_TOKEN],
+        vec![(1, dec(10.0)), (2, dec(9.0)), (3, dec(5.0))]
+    );
+    assert_eq!(
+        history["C"][DEFAULT_TOKEN],
+        vec![(1, dec(-10.0)), (2, dec(-9.0))]
+    );
+}
+
+#[test]
+fn test_balance_history_breaks_ties_by_row() {
+    let transfers = vec![
+        make_transfer("B", "A", 3.0, 1.0, 1),
+        make_transfer("A", "C", 5.0, 1.0, 1),
+    ];
+    let mut reversed = transfers.clone();
+    reversed.reverse();
+    let history = calculate_balance_history(&transfers, NegativeBalancePolicy::Allow).unwrap();
+    assert_eq!(
+        history["A"][DEFAULT_TOKEN],
+        vec![(1, dec(-5.0)), (1, dec(-2.0))]
+    );
+    assert_eq!(
+        history,
+        calculate_balance_history(&reversed, NegativeBalancePolicy::Allow).unwrap()
+    );
+}
+
+#[test]
+fn test_balance_history_of_some_addresses() {
+    let transfers = vec![
+        make_transfer("A", "B", 3.0, 1.0, 1),
+        make_transfer("B", "C", 2.0, 1.0, 2),
+        make_transfer("A", "A", 1.0, 1.0, 3),
+    ];
+    let all = calculate_balance_history(&transfers, NegativeBalancePolicy::PreFunded).unwrap();
+    let addresses = ["A".to_string(), "C".to_string()].into();
+    let some =
+        calculate_balance_history_of(&transfers, &addresses, NegativeBalancePolicy::PreFunded)
+            .unwrap();
+    assert_eq!(some.len(), 2);
+    assert_eq!(some["A"], all["A"]);
+    assert_eq!(some["C"], all["C"]);
+}
+
+#[test]
+fn test_balance_history_policies() {
+    let transfers = vec![
+        make_transfer("A", "B", 30.0, 1.0, 1),
+        make_transfer("C", "A", 50.0, 1.0, 2),
+        make_transfer("A", "A", 5.0, 1.0, 3),
+    ];
+
+    let allow = calculate_balance_history(&transfers, NegativeBalancePolicy::Allow).unwrap();
+    assert_eq!(
+        allow["A"][DEFAULT_TOKEN],
+        vec![(1, dec(-30.0)), (2, dec(20.0)), (3, dec(20.0))]
+    );
+
+    let clamp = calculate_balance_history(&transfers, NegativeBalancePolicy::Clamp).unwrap();
+    assert_eq!(
+        clamp["A"][DEFAULT_TOKEN],
+        vec![(1, dec(0.0)), (2, dec(50.0)), (3, dec(50.0))]
+    );
+
+    let pre_funded =
+        calculate_balance_history(&transfers, NegativeBalancePolicy::PreFunded).unwrap();
+    assert_eq!(
+        pre_funded["A"][DEFAULT_TOKEN],
+        vec![(1, dec(0.0)), (2, dec(50.0)), (3, dec(50.0))]
+    );
+    assert_eq!(pre_funded["C"][DEFAULT_TOKEN], vec![(2, dec(0.0))]);
+}
+
+#[test]
+fn test_max_balance_policies() {
+    let transfers = vec![
+        make_transfer("A", "B", 30.0, 1.0, 1),
+        make_transfer("C", "A", 20.0, 1.0, 2),
+        make_transfer("A", "D", 40.0, 1.0, 3),
+        make_transfer("E", "A", 60.0, 1.0, 4),
+    ];
+    let max_balance = |policy| {
+        calculate_user_stats_rust_with_policy(&transfers, policy)
+            .unwrap()
+            .into_iter()
+            .find(|s| s.address == "A")
+            .unwrap()
+            .max_balance
+    };
+    // Allow: -30, -10, -50, 10
+    assert_eq!(max_balance(NegativeBalancePolicy::Allow), dec(10.0));
+    // Clamp: 0, 20, 0, 60
+    assert_eq!(max_balance(NegativeBalancePolicy::Clamp), dec(60.0));
+    // PreFunded with an opening balance of 50: 20, 40, 0, 60
+    assert_eq!(max_balance(NegativeBalancePolicy::PreFunded), dec(60.0));
+}
+
+#[test]
+fn test_aggregator_policy_matches_history() {
+    let mut transfers = DefaultTransferGenerator::default().generate(300).unwrap();
+    transfers.sort_by(|a, b| a.cmp_order(b));
+    let history_max = |policy| -> Vec<(String, Decimal)> {
+        calculate_balance_history(&transfers, policy)
+            .unwrap()
+            .into_iter()
+            .map(|(address, tokens)| {
+                let max = tokens[DEFAULT_TOKEN]
+                    .iter()
+                    .map(|(_, b)| *b)
+                    .fold(Decimal::ZERO, Decimal::max);
+                (address, max)
+            })
+            .collect()
+    };
+    let allow_min: std::collections::HashMap<String, Decimal> =
+        calculate_balance_history(&transfers, NegativeBalancePolicy::Allow)
+            .unwrap()
+            .into_iter()
+            .map(|(address, tokens)| {
+                let min = tokens[DEFAULT_TOKEN]
+                    .iter()
+                    .map(|(_, b)| *b)
+                    .fold(Decimal::ZERO, Decimal::min);
+                (address, min)
+            })
+            .collect();
+
+    for policy in [NegativeBalancePolicy::Allow, NegativeBalancePolicy::Clamp] {
+        let mut aggregator = StatsAggregator::with_policy(policy);
+        aggregator.extend(&transfers);
+        for (address, expected) in history_max(policy) {
+            assert_eq!(
+                aggregator
+                    .get(&address, DEFAULT_TOKEN)
+                    .unwrap()
+                    .unwrap()
+                    .max_balance,
+                expected
+            );
+        }
+    }
+
+    // Pre-funded balances start at the opening balance, which is the deepest Allow overdraft.
+    let mut aggregator = StatsAggregator::with_policy(NegativeBalancePolicy::PreFunded);
+    aggregator.extend(&transfers);
+    for (address, max) in history_max(NegativeBalancePolicy::PreFunded) {
+        let expected = max.max(-allow_min[&address]);
+        let actual = aggregator
+            .get(&address, DEFAULT_TOKEN)
+            .unwrap()
+            .unwrap()
+            .max_balance;
+        assert_eq!(actual, expected, "{address}");
+    }
+}
+
+#[tokio::test]
+async fn test_clickhouse_rejects_clamp_policy() {
+    let client = ClickhouseClient::new("http://localhost:1");
+    let result =
+        calculate_user_stats_clickhouse_with_policy(&client, NegativeBalancePolicy::Clamp).await;
+    assert!(result.unwrap_err().to_string().contains("Clamp"));
+}
+
+fn with_token(mut t: Transfer, token: &str) -> Transfer {
+    t.token = token.to_string();
+    t
+}
+
+#[test]
+fn test_stats_are_per_token() {
+    let transfers = [
+        make_transfer("A", "B", 10.0, 2.0, 1),
+        with_token(make_transfer("A", "B", 3.0, 100.0, 2), "ETH"),
+        with_token(make_transfer("B", "A", 1.0, 120.0, 3), "ETH"),
+    ];
+    let stats = calculate_user_stats_rust(&transfers).unwrap();
+    assert_eq!(stats.len(), 4);
+    let get = |address: &str, token: &str| {
+        stats
+            .iter()
+            .find(|s| s.address == address && s.token == token)
+            .unwrap()
+    };
+    assert_eq!(get("A", DEFAULT_TOKEN).total_volume, dec(10.0));
+    assert_eq!(get("A", DEFAULT_TOKEN).avg_sell_price, dec(2.0));
+    assert_eq!(get("A", "ETH").total_volume, dec(4.0));
+    assert_eq!(get("A", "ETH").avg_sell_price, dec(100.0));
+    assert_eq!(get("A", "ETH").avg_buy_price, dec(120.0));
+    assert_eq!(get("B", "ETH").max_balance, dec(3.0));
+}
+
+#[test]
+fn test_usd_stats_sum_across_tokens() {
+    let transfers = [
+        make_transfer("A", "B", 10.0, 2.0, 1),
+        with_token(make_transfer("A", "B", 3.0, 100.0, 2), "ETH"),
+    ];
+    let mut stats = calculate_usd_stats_rust(&transfers).unwrap();
+    stats.sort_by(|a, b| a.address.cmp(&b.address));
+    assert_eq!(stats.len(), 2);
+    assert_eq!(stats[0].address, "A");
+    assert_eq!(stats[0].tokens, 2);
+    assert_eq!(stats[0].total_volume_usd, dec(320.0));
+    assert_eq!(stats[0].sell_volume_usd, dec(320.0));
+    assert_eq!(stats[0].buy_volume_usd, Decimal::ZERO);
+    assert_eq!(stats[1].buy_volume_usd, dec(320.0));
+}
+
+#[test]
+fn test_near_max_amounts_fail_instead_of_overflowing() {
+    let big = |from: &str, to: &str, amount: &str, price: f64, ts: u64| Transfer {
+        amount: amount.parse().unwrap(),
+        ..make_transfer(from, to, 1.0, price, ts)
+    };
+
+    // Each amount fits, B's volume and balance don't.
+    let transfers = [
+        big("A", "B", "100000000000000000000", 1.0, 1),
+        big("C", "B", "100000000000000000000", 1.0, 2),
+    ];
+    let error = calculate_user_stats_rust(&transfers).unwrap_err();
+    assert!(
+        error.to_string().starts_with("total_volume of B in TKN"),
+        "{error:#}"
+    );
+    let error = calculate_balance_history(&transfers, NegativeBalancePolicy::Allow).unwrap_err();
+    assert!(error.to_string().starts_with("Balance of B"), "{error:#}");
+
+    // The amount fits, its USD value doesn't.
+    let transfers = [big("A", "B", "10000000000000000000", 100.0, 1)];
+    let stats = calculate_user_stats_rust(&transfers).unwrap();
+    assert_eq!(
+        stats[0].avg_buy_price.max(stats[0].avg_sell_price),
+        dec(100.0)
+    );
+    let error = calculate_usd_stats_rust(&transfers).unwrap_err();
+    assert!(error.to_string().contains("volume_usd of"), "{error:#}");
+}
+
+#[test]
+fn test_balance_history_is_per_token() {
+    let transfers = [
+        make_transfer("A", "B", 10.0, 1.0, 1),
+        with_token(make_transfer("B", "A", 2.0, 1.0, 2), "ETH"),
+    ];
+    let history = calculate_balance_history(&transfers, NegativeBalancePolicy::Allow).unwrap();
+    assert_eq!(history["A"][DEFAULT_TOKEN], vec![(1, dec(-10.0))]);
+    assert_eq!(history["A"]["ETH"], vec![(2, dec(2.0))]);
+    assert_eq!(history["B"]["ETH"], vec![(2, dec(-2.0))]);
+}
+
+#[test]
+fn test_compare_reports_mismatches() {
+    let stat = |address: &str, volume: f64| UserStats {
+        address: address.to_string(),
+        token: DEFAULT_TOKEN.to_string(),
+        total_volume: dec(volume),
+        avg_buy_price: Decimal::ONE,
+        avg_sell_price: Decimal::ONE,
+        max_balance: Decimal::ZERO,
+    };
+    let eth = UserStats {
+        token: "ETH".to_string(),
+        ..stat("A", 5.0)
+    };
+    let left = vec![stat("A", 100.0), eth, stat("B", 10.0), stat("C", 1.0)];
+    let right = vec![stat("A", 100.0 + 1e-12), stat("B", 20.0), stat("D", 1.0)];
+
+    let report = compare(&left, &right, Tolerance::default());
+    assert_eq!(report.compared, 2);
+    assert_eq!(
+        report.mismatches,
+        vec![
+            Mismatch::MissingRight {
+                address: "A".to_string(),
+                token: "ETH".to_string(),
+            },
+            Mismatch::Field {
+                address: "B".to_string(),
+                token: DEFAULT_TOKEN.to_string(),
+                field: "total_volume",
+                left: dec(10.0),
+                right: dec(20.0),
+            },
+            Mismatch::MissingRight {
+                address: "C".to_string(),
+                token: DEFAULT_TOKEN.to_string(),
+            },
+            Mismatch::MissingLeft {
+                address: "D".to_string(),
+                token: DEFAULT_TOKEN.to_string(),
+            },
+        ]
+    );
+    assert!(!compare(&left, &right, Tolerance::default()).is_ok());
+    assert!(compare(&left, &left, Tolerance::exact()).is_ok());
+}
+
+#[test]
+fn test_tolerance() {
+    let tolerance = Tolerance {
+        absolute: dec(0.01),
+        relative: 0.001,
+    };
+    assert!(tolerance.accepts(dec(1.0), dec(1.005)));
+    assert!(tolerance.accepts(dec(1000.0), dec(1000.5)));
+    assert!(!tolerance.accepts(dec(1.0), dec(1.02)));
+    assert!(!Tolerance::exact().accepts(Decimal::ONE, Decimal::ONE + Decimal::from_raw(1)));
 }
 
 #[test]
@@ -72,16 +471,117 @@ fn test_large_values() {
     let stats = calculate_user_stats_rust(&[t]).unwrap();
     let a = stats.iter().find(|s| s.address == "A").unwrap();
     let b = stats.iter().find(|s| s.address == "B").unwrap();
-    assert!(a.total_volume > 0.0);
-    assert!(b.total_volume > 0.0);
+    assert!(a.total_volume.is_positive());
+    assert!(b.total_volume.is_positive());
+}
+
+fn sorted(mut stats: Vec<UserStats>) -> Vec<UserStats> {
+    stats.sort_by(|a, b| a.address.cmp(&b.address));
+    stats
+}
+
+fn assert_same_stats(left: Vec<UserStats>, right: Vec<UserStats>) {
+    let (left, right) = (sorted(left), sorted(right));
+    assert_eq!(left.len(), right.len());
+    for (l, r) in left.iter().zip(&right) {
+        assert_eq!(l.address, r.address);
+        assert_eq!(l.total_volume, r.total_volume, "{}", l.address);
+        assert_eq!(l.avg_buy_price, r.avg_buy_price, "{}", l.address);
+        assert_eq!(l.avg_sell_price, r.avg_sell_price, "{}", l.address);
+        assert_eq!(l.max_balance, r.max_balance, "{}", l.address);
+    }
+}
+
+#[tokio::test]
+async fn test_stream_matches_batch() {
+    let mut transfers = DefaultTransferGenerator::default().generate(500).unwrap();
+    transfers.extend([
+        make_transfer("A", "B", 10.0, 2.0, 1),
+        make_transfer("B", "A", 4.0, 3.0, 2),
+        make_transfer("A", "A", 7.0, 1.0, 3),
+        make_transfer("B", "C", -5.0, 2.0, 4),
+    ]);
+    transfers.sort_by(|a, b| a.cmp_order(b));
+
+    let batch = calculate_user_stats_rust(&transfers).unwrap();
+    let streamed = calculate_user_stats_stream(stream::iter(transfers.into_iter().map(Ok)))
+        .await
+        .unwrap();
+    assert_same_stats(batch, streamed);
+}
+
+#[tokio::test]
+async fn test_stream_propagates_errors() {
+    let transfers = vec![
+        Ok(make_transfer("A", "B", 10.0, 2.0, 1)),
+        Err(anyhow::anyhow!("connection reset")),
+    ];
+    let result = calculate_user_stats_stream(stream::iter(transfers)).await;
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_aggregator_matches_batch() {
+    let mut transfers = DefaultTransferGenerator::default().generate(500).unwrap();
+    transfers.sort_by(|a, b| a.cmp_order(b));
+    let mut aggregator = StatsAggregator::new();
+    for t in &transfers {
+        aggregator.push(t);
+    }
+    assert_same_stats(
+        calculate_user_stats_rust(&transfers).unwrap(),
+        aggregator.snapshot().unwrap(),
+    );
+}
+
+#[test]
+fn test_aggregator_live_updates() {
+    let mut aggregator = StatsAggregator::new();
+    assert!(aggregator.is_empty());
+    assert!(aggregator.get("A", DEFAULT_TOKEN).unwrap().is_none());
+
+    aggregator.push(&make_transfer("A", "B", 10.0, 2.0, 1));
+    let b = aggregator.get("B", DEFAULT_TOKEN).unwrap().unwrap();
+    assert_eq!(b.max_balance, dec(10.0));
+    assert_eq!(b.avg_buy_price, dec(2.0));
+
+    aggregator.push(&make_transfer("C", "B", 30.0, 4.0, 2));
+    let b = aggregator.get("B", DEFAULT_TOKEN).unwrap().unwrap();
+    assert_eq!(b.total_volume, dec(40.0));
+    assert_eq!(b.max_balance, dec(40.0));
+    assert_eq!(b.avg_buy_price, dec(3.5));
+    assert_eq!(aggregator.len(), 3);
+    assert_eq!(aggregator.snapshot().unwrap().len(), 3);
+}
+
+#[test]
+fn test_aggregator_extend() {
+    let transfers = vec![
+        make_transfer("A", "B", 10.0, 2.0, 1),
+        make_transfer("B", "A", 5.0, 3.0, 2),
+    ];
+    let mut aggregator = StatsAggregator::new();
+    aggregator.extend(&transfers);
+    assert_same_stats(
+        calculate_user_stats_rust(&transfers).unwrap(),
+        aggregator.snapshot().unwrap(),
+    );
 }
 
 //region stats clickhouse
 
+/// Connection settings come from `CLICKHOUSE_*` variables, e.g. `CLICKHOUSE_PASSWORD=111` for the
+/// Docker image. The schema is migrated before use.
+async fn server_client() -> ClickhouseClient {
+    let client = ClickhouseClient::from_config(&ClickhouseConfig::load().unwrap()).unwrap();
+    migrate::run(&client).await.unwrap();
+    client
+}
+
 #[tokio::test]
 #[serial]
 async fn test_empty_clickhouse() {
-    let client = ClickhouseClient::new("http://localhost:8123");
+    let client = server_client().await;
     client
         .client
         .query("TRUNCATE TABLE transfers")
@@ -95,7 +595,7 @@ async fn test_empty_clickhouse() {
 #[tokio::test]
 #[serial]
 async fn test_single_transfer_clickhouse() {
-    let client = ClickhouseClient::new("http://localhost:8123");
+    let client = server_client().await;
     client
         .client
         .query("TRUNCATE TABLE transfers")
@@ -110,15 +610,15 @@ async fn test_single_transfer_clickhouse() {
     assert_eq!(stats.len(), 2);
     let a = stats.iter().find(|s| s.address == "A").unwrap();
     let b = stats.iter().find(|s| s.address == "B").unwrap();
-    assert_eq!(a.total_volume + b.total_volume, 20.0);
-    assert_eq!(a.avg_sell_price, 2.0);
-    assert_eq!(b.avg_buy_price, 2.0);
+    assert_eq!(a.total_volume + b.total_volume, dec(20.0));
+    assert_eq!(a.avg_sell_price, dec(2.0));
+    assert_eq!(b.avg_buy_price, dec(2.0));
 }
 
 #[tokio::test]
 #[serial]
 async fn test_multiple_transfers_clickhouse() {
-    let client = ClickhouseClient::new("http://localhost:8123");
+    let client = server_client().await;
     client
         .client
         .query("TRUNCATE TABLE transfers")
@@ -136,15 +636,15 @@ async fn test_multiple_transfers_clickhouse() {
     let a = stats.iter().find(|s| s.address == "A").unwrap();
     let b = stats.iter().find(|s| s.address == "B").unwrap();
     let c = stats.iter().find(|s| s.address == "C").unwrap();
-    assert!(a.total_volume > 0.0);
-    assert!(b.total_volume > 0.0);
-    assert!(c.total_volume > 0.0);
+    assert!(a.total_volume.is_positive());
+    assert!(b.total_volume.is_positive());
+    assert!(c.total_volume.is_positive());
 }
 
 #[tokio::test]
 #[serial]
 async fn test_same_address_clickhouse() {
-    let client = ClickhouseClient::new("http://localhost:8123");
+    let client = server_client().await;
     client
         .client
         .query("TRUNCATE TABLE transfers")
@@ -157,15 +657,15 @@ async fn test_same_address_clickhouse() {
     insert.end().await.unwrap();
     let stats = calculate_user_stats_clickhouse(&client).await.unwrap();
     let a = stats.iter().find(|s| s.address == "A").unwrap();
-    assert_eq!(a.total_volume, 200.0);
-    assert_eq!(a.avg_buy_price, 1.0);
-    assert_eq!(a.avg_sell_price, 1.0);
+    assert_eq!(a.total_volume, dec(100.0));
+    assert_eq!(a.avg_buy_price, dec(1.0));
+    assert_eq!(a.avg_sell_price, dec(1.0));
 }
 
 #[tokio::test]
 #[serial]
 async fn test_negative_and_zero_amounts_clickhouse() {
-    let client = ClickhouseClient::new("http://localhost:8123");
+    let client = server_client().await;
     client
         .client
         .query("TRUNCATE TABLE transfers")
@@ -186,7 +686,7 @@ async fn test_negative_and_zero_amounts_clickhouse() {
 #[tokio::test]
 #[serial]
 async fn test_large_values_clickhouse() {
-    let client = ClickhouseClient::new("http://localhost:8123");
+    let client = server_client().await;
     client
         .client
         .query("TRUNCATE TABLE transfers")
@@ -200,6 +700,36 @@ async fn test_large_values_clickhouse() {
     let stats = calculate_user_stats_clickhouse(&client).await.unwrap();
     let a = stats.iter().find(|s| s.address == "A").unwrap();
     let b = stats.iter().find(|s| s.address == "B").unwrap();
-    assert!(a.total_volume > 0.0);
-    assert!(b.total_volume > 0.0);
+    assert!(a.total_volume.is_positive());
+    assert!(b.total_volume.is_positive());
+}
+
+#[tokio::test]
+#[serial]
+async fn test_parity_with_rust_clickhouse() {
+    let client = server_client().await;
+    client
+        .client
+        .query("TRUNCATE TABLE transfers")
+        .execute()
+        .await
+        .unwrap();
+    let mut transfers = DefaultTransferGenerator::default().generate(1000).unwrap();
+    transfers.extend([
+        make_transfer("A", "A", 100.0, 1.0, 1),
+        make_transfer("A", "B", -5.0, 2.0, 2),
+        make_transfer("B", "A", 0.0, 2.0, 3),
+        make_transfer("C", "D", 5.0, 2.0, 4),
+        make_transfer("D", "C", 5.0, 3.0, 4),
+    ]);
+    let mut insert = client.client.insert("transfers").unwrap();
+    for t in &transfers {
+        insert.write(t).await.unwrap();
+    }
+    insert.end().await.unwrap();
+
+    let rust = calculate_user_stats_rust(&transfers).unwrap();
+    let clickhouse = calculate_user_stats_clickhouse(&client).await.unwrap();
+    let report = compare(&rust, &clickhouse, Tolerance::default());
+    assert!(report.is_ok(), "{:?}", report.mismatches);
 }
@@ -1,82 +1,403 @@
-use anyhow::Result;
-use rust_challenge::model::Transfer;
-use std::sync::{Arc, Mutex};
+use clickhouse::test::{handlers, status, Mock};
+use futures::TryStreamExt;
+use rust_challenge::common::ClickhouseClient;
+use rust_challenge::model::{Decimal, Transfer, UserStats, DEFAULT_TOKEN};
+use rust_challenge::stats::calculate_user_stats_from_store;
+use rust_challenge::storage::{
+    ClickhouseStorage, InMemoryStorage, InsertConfig, SortOrder, TransferQuery, TransferStore,
+};
+use std::sync::atomic::{AtomicU64, Ordering};
 
-#[derive(Default, Clone)]
-struct MockClient {
-    pub inserted: Arc<Mutex<Vec<Transfer>>>,
+fn sample_transfer() -> Transfer {
+    Transfer {
+        ts: 1,
+        address_from: "A".to_string(),
+        address_to: "B".to_string(),
+        token: DEFAULT_TOKEN.to_string(),
+        amount: Decimal::from(100),
+        usd_price: Decimal::new(15, 1),
+        block_number: 1,
+        tx_hash: "0xaa".to_string(),
+        log_index: 0,
+    }
 }
 
-impl MockClient {
-    pub fn new() -> Self {
-        Self {
-            inserted: Arc::new(Mutex::new(vec![])),
-        }
-    }
-    pub async fn insert_transfer(&self, transfer: &Transfer) -> Result<()> {
-        self.inserted.lock().unwrap().push(transfer.clone());
-        Ok(())
-    }
-    pub async fn get_transfers(&self) -> Result<Vec<Transfer>> {
-        Ok(self.inserted.lock().unwrap().clone())
-    }
+fn dec(value: f64) -> Decimal {
+    Decimal::from_f64(value).unwrap()
 }
 
-struct TestStorage {
-    client: MockClient,
+#[tokio::test]
+async fn test_insert_and_get_transfer() {
+    let storage = InMemoryStorage::new();
+    let transfer = sample_transfer();
+    storage.insert_transfer(&transfer).await.unwrap();
+    let transfers = storage.get_transfers().await.unwrap();
+    assert_eq!(transfers.len(), 1);
+    assert_eq!(transfers[0].amount, dec(100.0));
+    assert_eq!(transfers[0].address_from, "A");
+    assert_eq!(transfers[0].address_to, "B");
+    assert_eq!(transfers[0].usd_price, dec(1.5));
 }
 
-impl TestStorage {
-    pub fn new() -> Self {
-        Self {
-            client: MockClient::new(),
-        }
-    }
-    pub async fn insert_transfer(&self, transfer: &Transfer) -> Result<()> {
-        self.client.insert_transfer(transfer).await
-    }
-    pub async fn get_transfers(&self) -> Result<Vec<Transfer>> {
-        self.client.get_transfers().await
-    }
+#[tokio::test]
+async fn test_multiple_inserts() {
+    let storage = InMemoryStorage::new();
+    let t1 = sample_transfer();
+    let mut t2 = t1.clone();
+    t2.amount = dec(200.0);
+    t2.log_index = 1;
+    storage.insert_transfer(&t1).await.unwrap();
+    storage.insert_transfer(&t2).await.unwrap();
+    let transfers = storage.get_transfers().await.unwrap();
+    assert_eq!(transfers.len(), 2);
+    assert_eq!(transfers[1].amount, dec(200.0));
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+#[tokio::test]
+async fn test_reinsert_same_id_replaces() {
+    let storage = InMemoryStorage::new();
+    let original = sample_transfer();
+    let mut corrected = original.clone();
+    corrected.amount = dec(200.0);
+    storage.insert_transfer(&original).await.unwrap();
+    storage.insert_transfer(&corrected).await.unwrap();
 
-    fn sample_transfer() -> Transfer {
-        Transfer {
-            ts: 1,
-            address_from: "A".to_string(),
-            address_to: "B".to_string(),
-            amount: 100.0,
-            usd_price: 1.5,
-        }
+    let transfers = storage.get_transfers().await.unwrap();
+    assert_eq!(transfers.len(), 1);
+    assert_eq!(transfers[0].amount, dec(200.0));
+}
+
+#[tokio::test]
+async fn test_retried_bulk_insert_does_not_double_count() {
+    let storage = InMemoryStorage::new();
+    let transfers = query_fixture().get_transfers().await.unwrap();
+    storage.insert_transfers(transfers.clone()).await.unwrap();
+    let once = calculate_user_stats_from_store(&storage).await.unwrap();
+    storage.insert_transfers(transfers.clone()).await.unwrap();
+    let twice = calculate_user_stats_from_store(&storage).await.unwrap();
+
+    assert_eq!(
+        storage.get_transfers().await.unwrap().len(),
+        transfers.len()
+    );
+    let volume = |stats: &[UserStats]| -> Decimal { stats.iter().map(|s| s.total_volume).sum() };
+    assert_eq!(volume(&once), volume(&twice));
+}
+
+#[tokio::test]
+async fn test_clones_share_data() {
+    let storage = InMemoryStorage::new();
+    let other = storage.clone();
+    storage.insert_transfer(&sample_transfer()).await.unwrap();
+    assert_eq!(other.get_transfers().await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn test_store_as_trait_object() {
+    let storage: Box<dyn TransferStore> =
+        Box::new(InMemoryStorage::with_transfers(vec![sample_transfer()]));
+    let stats = calculate_user_stats_from_store(storage.as_ref())
+        .await
+        .unwrap();
+    assert_eq!(stats.len(), 2);
+    let b = stats.iter().find(|s| s.address == "B").unwrap();
+    assert_eq!(b.max_balance, dec(100.0));
+    assert_eq!(b.avg_buy_price, dec(1.5));
+}
+
+fn numbered_transfers(count: u64) -> Vec<Transfer> {
+    (0..count)
+        .map(|i| Transfer {
+            ts: i,
+            tx_hash: format!("0x{i:064x}"),
+            ..sample_transfer()
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_insert_transfers_reports_rows() {
+    let storage = InMemoryStorage::new();
+    let written = storage
+        .insert_transfers(numbered_transfers(5))
+        .await
+        .unwrap();
+    assert_eq!(written, 5);
+    assert_eq!(storage.get_transfers().await.unwrap().len(), 5);
+}
+
+#[tokio::test]
+async fn test_clickhouse_insert_transfers_in_batches() {
+    let mock = Mock::new();
+    let storage = ClickhouseStorage::new(ClickhouseClient::new(mock.url())).with_insert_config(
+        InsertConfig {
+            batch_size: 4,
+            flush_interval: None,
+        },
+    );
+    let batches: Vec<_> = (0..3).map(|_| mock.add(handlers::record())).collect();
+
+    let written = storage
+        .insert_transfers(numbered_transfers(10))
+        .await
+        .unwrap();
+    assert_eq!(written, 10);
+
+    let mut sizes = vec![];
+    let mut ts = vec![];
+    for batch in batches {
+        let rows: Vec<Transfer> = batch.collect().await;
+        sizes.push(rows.len());
+        ts.extend(rows.iter().map(|t| t.ts));
     }
+    assert_eq!(sizes, vec![4, 4, 2]);
+    assert_eq!(ts, (0..10).collect::<Vec<_>>());
+}
+
+#[tokio::test]
+async fn test_clickhouse_insert_empty_stream() {
+    let mock = Mock::new();
+    let storage = ClickhouseStorage::new(ClickhouseClient::new(mock.url()));
+    let written = storage.insert_transfers(vec![]).await.unwrap();
+    assert_eq!(written, 0);
+}
 
-    #[tokio::test]
-    async fn test_insert_and_get_transfer() {
-        let storage = TestStorage::new();
-        let transfer = sample_transfer();
-        storage.insert_transfer(&transfer).await.unwrap();
-        let transfers = storage.get_transfers().await.unwrap();
-        assert_eq!(transfers.len(), 1);
-        assert_eq!(transfers[0].amount, 100.0);
-        assert_eq!(transfers[0].address_from, "A");
-        assert_eq!(transfers[0].address_to, "B");
-        assert_eq!(transfers[0].usd_price, 1.5);
+/// Every call gets a fresh id, so equal-looking transfers stay distinct rows.
+fn make_transfer(from: &str, to: &str, amount: f64, price: f64, ts: u64) -> Transfer {
+    static NEXT_TX: AtomicU64 = AtomicU64::new(0);
+    Transfer {
+        ts,
+        address_from: from.to_string(),
+        address_to: to.to_string(),
+        token: DEFAULT_TOKEN.to_string(),
+        amount: dec(amount),
+        usd_price: dec(price),
+        block_number: ts,
+        tx_hash: format!("0x{:064x}", NEXT_TX.fetch_add(1, Ordering::Relaxed)),
+        log_index: 0,
     }
+}
+
+fn query_fixture() -> InMemoryStorage {
+    InMemoryStorage::with_transfers(vec![
+        make_transfer("A", "B", 10.0, 1.0, 30),
+        make_transfer("B", "C", 20.0, 2.0, 10),
+        make_transfer("C", "A", 30.0, 3.0, 20),
+        make_transfer("A", "C", 40.0, 4.0, 40),
+    ])
+}
+
+#[tokio::test]
+async fn test_query_time_range_is_half_open() {
+    let storage = query_fixture();
+    let query = TransferQuery::new().from_ts(20).to_ts(40);
+    let ts: Vec<u64> = storage
+        .query_transfers(&query)
+        .await
+        .unwrap()
+        .iter()
+        .map(|t| t.ts)
+        .collect();
+    assert_eq!(ts, vec![20, 30]);
+}
+
+#[tokio::test]
+async fn test_query_by_address() {
+    let storage = query_fixture();
+
+    let sent = storage
+        .query_transfers(&TransferQuery::new().address_from("A"))
+        .await
+        .unwrap();
+    assert_eq!(sent.len(), 2);
+
+    let received = storage
+        .query_transfers(&TransferQuery::new().address_to("A"))
+        .await
+        .unwrap();
+    assert_eq!(received.len(), 1);
 
-    #[tokio::test]
-    async fn test_multiple_inserts() {
-        let storage = TestStorage::new();
-        let t1 = sample_transfer();
-        let mut t2 = t1.clone();
-        t2.amount = 200.0;
-        storage.insert_transfer(&t1).await.unwrap();
-        storage.insert_transfer(&t2).await.unwrap();
-        let transfers = storage.get_transfers().await.unwrap();
-        assert_eq!(transfers.len(), 2);
-        assert_eq!(transfers[1].amount, 200.0);
+    let any = storage
+        .query_transfers(&TransferQuery::new().address("A"))
+        .await
+        .unwrap();
+    assert_eq!(any.len(), 3);
+}
+
+#[tokio::test]
+async fn test_query_by_token() {
+    let mut eth = make_transfer("A", "B", 1.0, 100.0, 50);
+    eth.token = "ETH".to_string();
+    let storage = query_fixture();
+    storage.insert_transfer(&eth).await.unwrap();
+
+    let found = storage
+        .query_transfers(&TransferQuery::new().token("ETH"))
+        .await
+        .unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].id(), eth.id());
+    let default = storage
+        .query_transfers(&TransferQuery::new().token(DEFAULT_TOKEN))
+        .await
+        .unwrap();
+    assert_eq!(default.len(), 4);
+}
+
+#[tokio::test]
+async fn test_query_amount_and_price_bounds() {
+    let storage = query_fixture();
+    let query = TransferQuery::new()
+        .amount_between(Some(dec(20.0)), None)
+        .price_between(None, Some(dec(3.0)));
+    let amounts: Vec<Decimal> = storage
+        .query_transfers(&query)
+        .await
+        .unwrap()
+        .iter()
+        .map(|t| t.amount)
+        .collect();
+    assert_eq!(amounts, vec![dec(20.0), dec(30.0)]);
+}
+
+#[tokio::test]
+async fn test_query_keyset_pagination() {
+    let storage = query_fixture();
+    let mut query = TransferQuery::new().order(SortOrder::Desc).limit(3);
+    let mut pages = vec![];
+    loop {
+        let page = storage.query_transfers(&query).await.unwrap();
+        let Some(last) = page.last().cloned() else {
+            break;
+        };
+        pages.push(page.iter().map(|t| t.ts).collect::<Vec<_>>());
+        query = query.after(last);
     }
+    assert_eq!(pages, vec![vec![40, 30, 20], vec![10]]);
+}
+
+#[tokio::test]
+async fn test_query_pagination_keeps_same_ts_rows() {
+    let storage = InMemoryStorage::with_transfers(vec![
+        make_transfer("A", "B", 1.0, 1.0, 5),
+        make_transfer("A", "B", 2.0, 1.0, 5),
+        make_transfer("A", "C", 1.0, 1.0, 5),
+    ]);
+    let first = storage
+        .query_transfers(&TransferQuery::new().limit(1))
+        .await
+        .unwrap();
+    let rest = storage
+        .query_transfers(&TransferQuery::new().after(first[0].clone()))
+        .await
+        .unwrap();
+    assert_eq!(rest.len(), 2);
+}
+
+#[test]
+fn test_clickhouse_query_sql() {
+    let query = TransferQuery::new()
+        .from_ts(100)
+        .address("0xab'c")
+        .amount_between(Some(dec(1.5)), None)
+        .order(SortOrder::Desc)
+        .limit(50);
+    let sql = query
+        .to_clickhouse(&clickhouse::Client::default(), "transfers")
+        .sql_display()
+        .to_string();
+
+    assert!(sql.contains("ts >= 100"), "{sql}");
+    assert!(
+        sql.contains(r"(address_from = '0xab\'c' OR address_to = '0xab\'c')"),
+        "{sql}"
+    );
+    assert!(sql.contains("amount >= toDecimal128('1.5', 18)"), "{sql}");
+    assert!(sql.contains("ORDER BY ts DESC"), "{sql}");
+    assert!(sql.contains("LIMIT 50"), "{sql}");
+    assert!(sql.contains("FROM transfers FINAL"), "{sql}");
+}
+
+#[test]
+fn test_query_by_addresses() {
+    let query = TransferQuery::new().addresses(["A", "0xb'"]).token("ETH");
+    let sql = query
+        .to_clickhouse(&clickhouse::Client::default(), "transfers")
+        .sql_display()
+        .to_string();
+    assert!(
+        sql.contains(r"(has(['A','0xb\''], address_from) OR has(['A','0xb\''], address_to))"),
+        "{sql}"
+    );
+
+    let mut transfer = sample_transfer();
+    transfer.token = "ETH".to_string();
+    transfer.address_from = "B".to_string();
+    transfer.address_to = "A".to_string();
+    assert!(query.matches(&transfer));
+    transfer.address_to = "C".to_string();
+    assert!(!query.matches(&transfer));
+    assert!(TransferQuery::new()
+        .addresses(Vec::<String>::new())
+        .matches(&transfer));
+}
+
+#[test]
+fn test_clickhouse_cursor_includes_id() {
+    let sql = TransferQuery::new()
+        .after(sample_transfer())
+        .to_clickhouse(&clickhouse::Client::default(), "transfers")
+        .sql_display()
+        .to_string();
+    assert!(sql.contains("usd_price, tx_hash, log_index) > ("), "{sql}");
+    assert!(sql.contains("'0xaa', 0)"), "{sql}");
+    assert!(sql.ends_with("tx_hash ASC, log_index ASC"), "{sql}");
+}
+
+#[tokio::test]
+async fn test_stream_transfers_applies_query() {
+    let storage = query_fixture();
+    let streamed: Vec<Transfer> = storage
+        .stream_transfers(&TransferQuery::new().address("A"))
+        .try_collect()
+        .await
+        .unwrap();
+    let queried = storage
+        .query_transfers(&TransferQuery::new().address("A"))
+        .await
+        .unwrap();
+    assert_eq!(
+        streamed.iter().map(|t| t.ts).collect::<Vec<_>>(),
+        queried.iter().map(|t| t.ts).collect::<Vec<_>>()
+    );
+}
+
+#[tokio::test]
+async fn test_clickhouse_stream_transfers() {
+    let mock = Mock::new();
+    let storage = ClickhouseStorage::new(ClickhouseClient::new(mock.url()));
+    mock.add(handlers::provide(numbered_transfers(3)));
+
+    let streamed: Vec<Transfer> = storage
+        .stream_transfers(&TransferQuery::new())
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(
+        streamed.iter().map(|t| t.ts).collect::<Vec<_>>(),
+        vec![0, 1, 2]
+    );
+}
+
+#[tokio::test]
+async fn test_clickhouse_stream_transfers_error() {
+    let mock = Mock::new();
+    let storage = ClickhouseStorage::new(ClickhouseClient::new(mock.url()));
+    mock.add(handlers::failure(status::INTERNAL_SERVER_ERROR));
+
+    let result: anyhow::Result<Vec<Transfer>> = storage
+        .stream_transfers(&TransferQuery::new())
+        .try_collect()
+        .await;
+    assert!(result.is_err());
 }